@@ -0,0 +1,96 @@
+public class Arrays {
+    static int sum(int n) {
+        int[] a = new int[n];
+        for (int i = 0; i < n; i++) {
+            a[i] = i;
+        }
+        int s = 0;
+        for (int i = 0; i < a.length; i++) {
+            s += a[i];
+        }
+        return s;
+    }
+
+    static int narrow() {
+        byte[] b = new byte[1];
+        char[] c = new char[1];
+        short[] s = new short[1];
+        b[0] = (byte) 200;
+        c[0] = (char) -1;
+        s[0] = (short) 40000;
+        return b[0] + c[0] + s[0];
+    }
+
+    static boolean flag() {
+        boolean[] f = new boolean[2];
+        f[1] = true;
+        return !f[0] && f[1];
+    }
+
+    static double wide() {
+        long[] l = { 1L << 40 };
+        float[] f = { 0.5f };
+        double[] d = { 0.25 };
+        return l[0] + f[0] + d[0];
+    }
+
+    static int grid(int w, int h) {
+        int[][] g = new int[w][h];
+        g[w - 1][h - 1] = 7;
+        return g.length * 100 + g[0].length * 10 + g[w - 1][h - 1];
+    }
+
+    static boolean partial() {
+        int[][] g = new int[3][];
+        return g[2] == null;
+    }
+
+    static int load(int i) {
+        int[] a = new int[3];
+        return a[i];
+    }
+
+    static int[] alloc(int n) {
+        return new int[n];
+    }
+
+    static int[][][] alloc3(int a, int b, int c) {
+        return new int[a][b][c];
+    }
+
+    static void store(Object[] a, Object v) {
+        a[0] = v;
+    }
+
+    static Object[] strings() {
+        return new String[1];
+    }
+
+    static Object nodes() {
+        return new Node[2][3];
+    }
+
+    static Object[][] castGrid(Object o) {
+        return (Object[][]) o;
+    }
+
+    static boolean isInts(Object o) {
+        return o instanceof int[];
+    }
+
+    static boolean isObjects(Object o) {
+        return o instanceof Object[];
+    }
+
+    static boolean isCloneable(Object o) {
+        return o instanceof Cloneable;
+    }
+
+    static Class<?> intsClass() {
+        return int[].class;
+    }
+
+    static Class<?> classOf(Object o) {
+        return o.getClass();
+    }
+}
@@ -0,0 +1,53 @@
+public class Branch {
+    public static int sum(int n) {
+        int s = 0;
+        for (int i = 0; i < n; i++) {
+            s += i;
+        }
+        return s;
+    }
+
+    public static int table(int x) {
+        switch (x) {
+            case 1:
+                return 10;
+            case 2:
+                return 20;
+            case 3:
+                return 30;
+            case 4:
+                return 40;
+            default:
+                return -1;
+        }
+    }
+
+    public static int lookup(int x) {
+        switch (x) {
+            case -1000:
+                return 1;
+            case 0:
+                return 2;
+            case 1000000:
+                return 3;
+            default:
+                return 0;
+        }
+    }
+
+    public static int compare(long a, long b) {
+        return a < b ? -1 : (a == b ? 0 : 1);
+    }
+
+    public static boolean less(double a, double b) {
+        return a < b;
+    }
+
+    public static boolean isNull(Object o) {
+        return o == null;
+    }
+
+    public static boolean same(Object a, Object b) {
+        return a == b;
+    }
+}
@@ -0,0 +1,79 @@
+interface Shape {
+    int area();
+
+    default int sides() {
+        return 0;
+    }
+
+    default int describe() {
+        return sides();
+    }
+}
+
+abstract class Base implements Shape {
+    public int sides() {
+        return 4;
+    }
+
+    public abstract int size();
+}
+
+class Square extends Base {
+    private int secret() {
+        return 7;
+    }
+
+    public int area() {
+        return size();
+    }
+
+    public int size() {
+        return 3;
+    }
+
+    int callSecret() {
+        return secret();
+    }
+}
+
+class Cube extends Square {
+    public int size() {
+        return 2;
+    }
+
+    public int superSize() {
+        return super.size();
+    }
+}
+
+class Tri implements Shape {
+    public int area() {
+        return 6;
+    }
+}
+
+public class Dispatch {
+    public static int area(Shape s) {
+        return s.area();
+    }
+
+    public static int sides(Shape s) {
+        return s.sides();
+    }
+
+    public static int size(Base b) {
+        return b.size();
+    }
+
+    public static int describe(Shape s) {
+        return s.describe();
+    }
+
+    public static int superSize(Cube c) {
+        return c.superSize();
+    }
+
+    public static int secret(Square s) {
+        return s.callSecret();
+    }
+}
@@ -0,0 +1,51 @@
+public class Exc {
+    int value() {
+        return 1;
+    }
+
+    public static int divide(int a, int b) {
+        return a / b;
+    }
+
+    public static int safeDivide(int a, int b) {
+        try {
+            return divide(a, b);
+        } catch (ArithmeticException e) {
+            return -1;
+        }
+    }
+
+    public static int finallyCount(int x) {
+        int n = 0;
+        try {
+            n = divide(10, x);
+        } catch (ArithmeticException e) {
+            n = -1;
+        } finally {
+            n += 100;
+        }
+        return n;
+    }
+
+    public static int callNull(Exc e) {
+        try {
+            return e.value();
+        } catch (NullPointerException x) {
+            return -2;
+        }
+    }
+
+    public static int catchIt(RuntimeException e) {
+        try {
+            throw e;
+        } catch (IllegalStateException x) {
+            return 1;
+        } catch (RuntimeException x) {
+            return 2;
+        }
+    }
+
+    public static void rethrow(Throwable t) throws Throwable {
+        throw t;
+    }
+}
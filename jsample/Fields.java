@@ -0,0 +1,77 @@
+interface FieldsIface {
+    int COUNT = Fields.twice(21);
+}
+
+class FieldsBase {
+    static int trace;
+    static int zero;
+    int hidden = 1;
+
+    static {
+        trace = trace * 10 + 1;
+    }
+}
+
+class FieldsBad {
+    static int v = 1 / FieldsBase.zero;
+}
+
+public class Fields extends FieldsBase implements FieldsIface {
+    static final int K = 42;
+    static long big = 1L << 40;
+    static byte b;
+    static char c;
+    static boolean flag;
+    int x = 3;
+    long y;
+    double z;
+    String s;
+    int hidden = 2;
+
+    static {
+        trace = trace * 10 + 2;
+    }
+
+    static int twice(int v) {
+        return v * 2;
+    }
+
+    static int trace() {
+        return trace;
+    }
+
+    static long big() {
+        return big + K;
+    }
+
+    static int count() {
+        return COUNT;
+    }
+
+    static int bad() {
+        return FieldsBad.v;
+    }
+
+    static int nullRead(Fields f) {
+        return f.x;
+    }
+
+    int sum() {
+        return x + hidden * 10 + super.hidden * 100 + (int) y;
+    }
+
+    void set(long y, double z, String s) {
+        this.y = y;
+        this.z = z;
+        this.s = s;
+        b = (byte) y;
+    }
+
+    double z() {
+        return z;
+    }
+
+    String s() {
+        return s;
+    }
+}
@@ -0,0 +1,40 @@
+class Plain {
+    int x = 1;
+
+    Object copy() throws CloneNotSupportedException {
+        return clone();
+    }
+}
+
+public class Header implements Cloneable {
+    int x;
+    int[] data = { 1, 2, 3 };
+
+    static int hash(Object o) {
+        return o.hashCode();
+    }
+
+    static int identity(Object o) {
+        return System.identityHashCode(o);
+    }
+
+    static boolean copy() throws CloneNotSupportedException {
+        Header h = new Header();
+        h.x = 5;
+        Header c = (Header) h.clone();
+        return c != h && c.x == 5 && c.data == h.data;
+    }
+
+    static int[] copyArray(int[] a) {
+        return a.clone();
+    }
+
+    static boolean notCloneable() {
+        try {
+            new Plain().copy();
+            return false;
+        } catch (CloneNotSupportedException e) {
+            return true;
+        }
+    }
+}
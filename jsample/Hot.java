@@ -0,0 +1,22 @@
+public class Hot {
+    public int value() {
+        return 1;
+    }
+
+    public static int callValue(Hot h) {
+        return h.value();
+    }
+
+    public static int version() {
+        return 1;
+    }
+
+    // yields, so that the class can be redefined while the frame runs
+    public static int running() {
+        Thread.yield();
+        return 10 + version();
+    }
+}
+
+class HotSub extends Hot {
+}
@@ -0,0 +1,34 @@
+public class Invoke {
+    public Invoke() {
+    }
+
+    public static int second(int a, int b) {
+        return b;
+    }
+
+    public static long pick(int a, long b, double c) {
+        return b;
+    }
+
+    public static double pickDouble(int a, long b, double c) {
+        return c;
+    }
+
+    public static int swap(int a, int b) {
+        return second(b, a);
+    }
+
+    public static native int twice(int x);
+
+    public static int callNative(int x) {
+        return twice(x);
+    }
+
+    public Object self() {
+        return this;
+    }
+
+    public int echo(int x) {
+        return x;
+    }
+}
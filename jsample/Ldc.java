@@ -0,0 +1,36 @@
+public class Ldc {
+    static final String GREETING = "hello, ldc";
+
+    static int bigInt() {
+        return 1000000;
+    }
+
+    static float half() {
+        return 2.5f;
+    }
+
+    static long bigLong() {
+        return 1L << 40;
+    }
+
+    static double e() {
+        return Math.E;
+    }
+
+    static String greeting() {
+        return "hello, ldc";
+    }
+
+    static String greeting2() {
+        return GREETING;
+    }
+
+    // an unpaired surrogate
+    static String surrogate() {
+        return "a\uD800b";
+    }
+
+    static Class<?> self() {
+        return Ldc.class;
+    }
+}
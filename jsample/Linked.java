@@ -0,0 +1,37 @@
+class Node {
+    static int created;
+    int value;
+    Node next;
+
+    Node(int value, Node next) {
+        this.value = value;
+        this.next = next;
+        created++;
+    }
+}
+
+public class Linked {
+    static Node build(int n) {
+        Node head = null;
+        for (int i = 1; i <= n; i++) {
+            head = new Node(i, head);
+        }
+        return head;
+    }
+
+    static int sum(Node head) {
+        int s = 0;
+        for (Node p = head; p != null; p = p.next) {
+            s += p.value;
+        }
+        return s;
+    }
+
+    static int created() {
+        return Node.created;
+    }
+
+    static Object make() {
+        return new Object();
+    }
+}
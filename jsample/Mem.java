@@ -0,0 +1,58 @@
+public class Mem {
+    static Object keep;
+
+    static long churn(int n) {
+        Runtime rt = Runtime.getRuntime();
+        for (int i = 0; i < n; i++) {
+            keep = new Object();
+        }
+        keep = null;
+        long before = rt.freeMemory();
+        System.gc();
+        return rt.freeMemory() - before;
+    }
+
+    static void garbage(int n) {
+        for (int i = 0; i < n; i++) {
+            keep = new Object();
+        }
+    }
+
+    static long total() {
+        return Runtime.getRuntime().totalMemory();
+    }
+
+    static long max() {
+        return Runtime.getRuntime().maxMemory();
+    }
+
+    static int exhaust() {
+        Object[] head = null;
+        int n = 0;
+        try {
+            while (true) {
+                Object[] next = new Object[256];
+                next[0] = head;
+                head = next;
+                n++;
+            }
+        } catch (OutOfMemoryError e) {
+            head = null;
+        }
+        return n;
+    }
+
+    static int huge(int n) {
+        try {
+            return new long[n].length;
+        } catch (OutOfMemoryError e) {
+            return -1;
+        }
+    }
+
+    static boolean sameRuntime() {
+        Runtime rt = Runtime.getRuntime();
+        rt.gc();
+        return rt == Runtime.getRuntime();
+    }
+}
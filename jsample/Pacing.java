@@ -0,0 +1,27 @@
+import java.util.Date;
+
+public class Pacing {
+    static long elapsed;
+    static long nanos;
+    static long dateElapsed;
+
+    static long now() {
+        return System.currentTimeMillis();
+    }
+
+    static long date() {
+        return new Date().getTime();
+    }
+
+    static void frames(int n) throws InterruptedException {
+        long start = System.currentTimeMillis();
+        long startNanos = System.nanoTime();
+        Date startDate = new Date();
+        for (int i = 0; i < n; i++) {
+            Thread.sleep(70);
+        }
+        elapsed = System.currentTimeMillis() - start;
+        nanos = System.nanoTime() - startNanos;
+        dateElapsed = new Date().getTime() - startDate.getTime();
+    }
+}
@@ -0,0 +1,46 @@
+public class Strs {
+    static int length(String s) {
+        return s.length();
+    }
+
+    static char charAt(String s, int i) {
+        return s.charAt(i);
+    }
+
+    static int hash(String s) {
+        return s.hashCode();
+    }
+
+    static int dispatch(String s) {
+        switch (s) {
+            case "apple":
+                return 1;
+            case "banana":
+                return 2;
+            // "Aa" and "BB" have the same hash
+            case "Aa":
+                return 3;
+            case "BB":
+                return 4;
+            default:
+                return 0;
+        }
+    }
+
+    static boolean sameLiteral() {
+        return Ldc.greeting() == "hello, ldc";
+    }
+
+    static boolean interned() {
+        String s = new String(new char[] { 'h', 'i' });
+        return s != "hi" && s.equals("hi") && s.intern() == "hi";
+    }
+
+    static String slice(int offset, int count) {
+        return new String(new char[] { 'a', 'b', 'c', 'd' }, offset, count);
+    }
+
+    static String surrogates() {
+        return "\uD83D\uDE00!";
+    }
+}
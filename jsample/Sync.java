@@ -0,0 +1,180 @@
+public class Sync implements Runnable {
+    static final Object lock = new Object();
+    static int count;
+    static int waiting;
+    static int[] log = new int[4];
+    static int next;
+    static boolean ok;
+
+    int value;
+    final int id;
+    final int mode;
+
+    Sync(int id, int mode) {
+        this.id = id;
+        this.mode = mode;
+    }
+
+    // the yields give the other thread a chance to break in
+    synchronized void add() {
+        int v = value;
+        Thread.yield();
+        value = v + 1;
+    }
+
+    static void addLocked() {
+        synchronized (lock) {
+            int v = count;
+            Thread.yield();
+            count = v + 1;
+        }
+    }
+
+    static synchronized void addStatic() {
+        int v = count;
+        Thread.yield();
+        count = v + 1;
+    }
+
+    public void run() {
+        switch (mode) {
+            case 0:
+                for (int i = 0; i < 5; i++) {
+                    add();
+                    addLocked();
+                }
+                break;
+            case 1:
+                waitForNotify();
+                break;
+            case 2:
+                addStatic();
+                break;
+        }
+    }
+
+    void waitForNotify() {
+        synchronized (lock) {
+            waiting++;
+            try {
+                lock.wait();
+            } catch (InterruptedException e) {
+                return;
+            }
+            log[next++] = id;
+        }
+    }
+
+    static void exclusion() throws InterruptedException {
+        Sync shared = new Sync(0, 0);
+        Thread a = new Thread(shared);
+        Thread b = new Thread(shared);
+        a.start();
+        b.start();
+        a.join();
+        b.join();
+        ok = shared.value == 10 && count == 10;
+    }
+
+    synchronized int reentrant(int depth) {
+        if (depth == 0) {
+            synchronized (this) {
+                synchronized (lock) {
+                    return 1;
+                }
+            }
+        }
+        return reentrant(depth - 1) + 1;
+    }
+
+    static boolean reentrancy() {
+        return new Sync(0, 0).reentrant(3) == 4;
+    }
+
+    static int illegalState() throws InterruptedException {
+        int caught = 0;
+        try {
+            lock.notify();
+        } catch (IllegalMonitorStateException e) {
+            caught++;
+        }
+        try {
+            lock.notifyAll();
+        } catch (IllegalMonitorStateException e) {
+            caught++;
+        }
+        try {
+            lock.wait();
+        } catch (IllegalMonitorStateException e) {
+            caught++;
+        }
+        return caught;
+    }
+
+    /// notify wakes the waiters in the order they waited
+    static void notifyOrder() {
+        for (int i = 1; i <= 3; i++) {
+            new Thread(new Sync(i, 1)).start();
+        }
+        while (waiting < 3) {
+            Thread.yield();
+        }
+        for (int i = 1; i <= 3; i++) {
+            synchronized (lock) {
+                lock.notify();
+            }
+            while (next < i) {
+                Thread.yield();
+            }
+        }
+    }
+
+    static void notifyAllWaiters() throws InterruptedException {
+        Thread[] threads = new Thread[3];
+        for (int i = 0; i < 3; i++) {
+            threads[i] = new Thread(new Sync(i + 1, 1));
+            threads[i].start();
+        }
+        while (waiting < 3) {
+            Thread.yield();
+        }
+        synchronized (lock) {
+            lock.notifyAll();
+            // the waiters need the monitor to return from wait
+            Thread.yield();
+            ok = next == 0;
+        }
+        for (Thread t : threads) {
+            t.join();
+        }
+    }
+
+    static void timedWait() throws InterruptedException {
+        synchronized (lock) {
+            lock.wait(20);
+            ok = true;
+        }
+    }
+
+    static synchronized void fail() {
+        throw new IllegalStateException();
+    }
+
+    /// the monitor of a synchronized method is released by an exception
+    static void releaseOnThrow() throws InterruptedException {
+        try {
+            fail();
+        } catch (IllegalStateException e) {
+        }
+        Thread t = new Thread(new Sync(0, 2));
+        t.start();
+        t.join();
+        ok = count == 1;
+    }
+
+    static void lonelyWait() throws InterruptedException {
+        synchronized (lock) {
+            lock.wait();
+        }
+    }
+}
@@ -0,0 +1,113 @@
+public class Threads implements Runnable {
+    static int[] log = new int[8];
+    static int next;
+    static int total;
+    static boolean ok;
+    static int[] spins = new int[2];
+    static int[] checks = new int[6];
+
+    final int id;
+    final int count;
+
+    Threads(int id, int count) {
+        this.id = id;
+        this.count = count;
+    }
+
+    public void run() {
+        for (int i = 0; i < count; i++) {
+            log[next++] = id;
+            total += id;
+            Thread.yield();
+        }
+    }
+
+    static void interleave() throws InterruptedException {
+        Thread a = new Thread(new Threads(1, 3));
+        Thread b = new Thread(new Threads(2, 3));
+        a.start();
+        b.start();
+        a.join();
+        b.join();
+        ok = !a.isAlive() && !b.isAlive() && total == 9;
+    }
+
+    static void subclass() throws InterruptedException {
+        Sleeper s = new Sleeper(0);
+        s.start();
+        s.join(1000);
+        ok = Sleeper.woke && !s.isAlive();
+    }
+
+    static void sleepy() {
+        new Sleeper(30).start();
+    }
+
+    static void spin() {
+        Thread fast = new Thread(new Spinner(0));
+        Thread slow = new Thread(new Spinner(1));
+        fast.setPriority(Thread.MAX_PRIORITY);
+        slow.setPriority(Thread.MIN_PRIORITY);
+        fast.start();
+        slow.start();
+    }
+
+    static void states() throws InterruptedException {
+        Thread main = Thread.currentThread();
+        checks[0] = main.getName().equals("main") && main.isAlive() ? 1 : 0;
+        Thread t = new Thread(new Threads(3, 1));
+        checks[1] = t.getName().equals("Thread-0") && !t.isAlive() ? 1 : 0;
+        checks[2] = t.getPriority() == Thread.NORM_PRIORITY ? 1 : 0;
+        t.start();
+        checks[3] = t.isAlive() ? 1 : 0;
+        try {
+            t.start();
+        } catch (IllegalThreadStateException e) {
+            checks[4] = 1;
+        }
+        try {
+            t.setPriority(11);
+        } catch (IllegalArgumentException e) {
+            checks[5] = 1;
+        }
+        t.join();
+    }
+
+    static void selfJoin() throws InterruptedException {
+        Thread.currentThread().join();
+    }
+}
+
+class Sleeper extends Thread {
+    static boolean woke;
+
+    final long millis;
+
+    Sleeper(long millis) {
+        super("sleeper");
+        this.millis = millis;
+    }
+
+    public void run() {
+        try {
+            Thread.sleep(millis);
+        } catch (InterruptedException e) {
+            return;
+        }
+        woke = currentThread() == this && getName().equals("sleeper");
+    }
+}
+
+class Spinner implements Runnable {
+    final int id;
+
+    Spinner(int id) {
+        this.id = id;
+    }
+
+    public void run() {
+        while (true) {
+            Threads.spins[id]++;
+        }
+    }
+}
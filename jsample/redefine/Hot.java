@@ -0,0 +1,21 @@
+public class Hot {
+    public int value() {
+        return 2;
+    }
+
+    public static int callValue(Hot h) {
+        return h.value();
+    }
+
+    public static int version() {
+        return 2;
+    }
+
+    // the long constant and the call before yielding move the constant pool
+    // entries, so that #18 (Hot.version in the old pool) is Thread.yield
+    public static int running() {
+        long v = version() + 10_000_000_000L;
+        Thread.yield();
+        return 20 + (int) (v - 10_000_000_000L);
+    }
+}
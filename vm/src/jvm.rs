@@ -1,53 +1,208 @@
+// macros for Java exceptions are used in the following modules
+#[macro_use]
+mod exception;
+
+mod arith;
+mod array;
+mod clock;
 mod desc;
+mod dispatch;
+mod gc;
+mod heap;
+mod hprof;
+mod image;
+mod invoke;
 mod jvm_impl;
+mod link;
+mod monitor;
 mod op;
 mod parse;
+mod redefine;
+mod resolve;
+mod stack;
 mod stdlib;
+mod string;
+mod thread;
+
+#[cfg(test)]
+mod test_util;
 
-use std::{collections::HashMap, rc::Rc};
+use std::{
+    collections::{HashMap, VecDeque},
+    rc::Rc,
+};
 
-pub use op::next_op;
+pub use clock::{Clock, VirtualClock, WallClock};
+pub use heap::JRef;
+pub use invoke::InvokeResult;
 pub use stdlib::load_core as stdlib_load_core;
+pub use thread::SchedulerState;
 
+#[allow(clippy::upper_case_acronyms)]
 pub struct JVM {
     classes: HashMap<String, Rc<JClass>>,
     class_rt: HashMap<String, JClassRuntimeInfo>,
+    /// Resolutions of the constant pool of each class definition.
+    /// (key = [Rc::as_ptr] of the class)
+    cp_caches: HashMap<*const JClass, CpCache>,
+    heap: heap::Heap,
+    /// Implementations of native methods declared in class files.
+    /// (key = "class.name_desc")
+    natives: HashMap<String, Rc<NativeMathod>>,
+    /// VM-side state of Throwable instances. (message and stack trace)
+    exceptions: HashMap<JRef, exception::ExceptionInfo>,
+    /// Interned java/lang/String instances by the UTF-16 contents.
+    /// (string literals and intern())
+    interned: HashMap<Box<[u16]>, JRef>,
+    /// java/lang/Class instances by class name.
+    class_objects: HashMap<String, JRef>,
+    gc: gc::GcState,
+    /// References held by the host or native code, which are GC roots.
+    /// (reference counts)
+    global_refs: HashMap<JRef, u32>,
+    /// Limit of the current [JVM::run_for] slice. (unlimited otherwise)
+    budget: Budget,
+    /// End of the current slice in [clock::Clock::monotonic] time.
+    /// (from [Budget::time])
+    deadline: Option<std::time::Duration>,
+    /// Instructions executed in the current slice.
+    executed: u64,
+    /// The turn of the running thread ends when `executed` reaches this.
+    /// (u64::MAX unless scheduled by [JVM::run_for])
+    turn_end: u64,
+    /// Threads scheduled by [JVM::run_for], except the running one.
+    threads: VecDeque<thread::JThread>,
+    /// ID of the next started thread.
+    next_thread_id: monitor::ThreadId,
+    /// Time of sleep, timeouts and the time queries of Java.
+    clock: Box<dyn clock::Clock>,
+    config: JVMConfig,
+}
+
+/// Limits of a [JVM], fixed when it is created. ([JVM::with_config])
+#[derive(Debug, Clone, Copy)]
+pub struct JVMConfig {
+    /// Bytes of all objects in the heap. (Runtime.maxMemory)
+    pub max_heap: usize,
+    /// Bytes of a single object or array.
+    pub max_object_size: usize,
 }
 
 #[derive(Debug)]
 pub struct JThreadContext {
+    id: monitor::ThreadId,
+    /// Thread name. (for messages)
+    name: String,
+    /// The java/lang/Thread instance. (created on first use for the main
+    /// thread)
+    object: Option<JRef>,
     stack: Vec<u32>,
+    /// Whether each slot of the stack holds a reference. (for the GC)
+    refs: Vec<bool>,
     frames: Vec<JStackFrame>,
+    /// Set by a native method to suspend the thread after it returns.
+    /// (Yielded or Blocked)
+    suspend: Option<ThreadState>,
+    /// Entry count of the monitor released by Object.wait, restored when
+    /// the thread reenters it.
+    wait_count: u32,
 }
 
 impl JThreadContext {
     pub const DEFAULT_STACK: u32 = 1024;
     pub const MAX_STACK: u32 = 1024;
     pub const DEFAULT_FRAME: u32 = Self::DEFAULT_STACK / 8;
-}
+    /// ID of the main thread, which the host runs.
+    pub const MAIN_ID: monitor::ThreadId = 1;
 
-impl Default for JThreadContext {
-    fn default() -> Self {
+    pub fn new(id: monitor::ThreadId, name: &str) -> Self {
         Self {
+            id,
+            name: name.to_string(),
+            object: None,
             stack: Vec::with_capacity(Self::DEFAULT_STACK as usize),
+            refs: Vec::with_capacity(Self::DEFAULT_STACK as usize),
             frames: Vec::with_capacity(Self::DEFAULT_FRAME as usize),
+            suspend: None,
+            wait_count: 0,
         }
     }
 }
 
+impl Default for JThreadContext {
+    /// The main thread.
+    fn default() -> Self {
+        Self::new(Self::MAIN_ID, "main")
+    }
+}
+
+/// State of a thread when [JVM::run] returns.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ThreadState {
+    /// The bottom frame returned. (None if void)
+    Finished(Option<JValue>),
+    /// The thread can continue, but gave the chance to others.
+    Yielded,
+    /// The thread cannot continue until the reason is resolved.
+    Blocked(BlockReason),
+    /// The bottom frame completed abruptly by the exception.
+    Uncaught(JRef),
+}
+
+/// Why a thread is blocked.
+#[derive(Debug, Clone, PartialEq)]
+pub enum BlockReason {
+    /// Thread.sleep until the time. (milliseconds)
+    Sleep(u64),
+    /// Waiting to enter the monitor of the object.
+    Monitor(JRef),
+    /// Object.wait on the object, until the time if given.
+    /// (milliseconds)
+    Wait(JRef, Option<u64>),
+    /// Thread.join on the thread object, until the time if given.
+    /// (milliseconds)
+    Join(JRef, Option<u64>),
+}
+
+/// How long [JVM::run_for] may run before it returns control to the host.
+/// The slice ends at whichever limit is reached first.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Budget {
+    /// Maximum number of instructions to execute.
+    pub instructions: Option<u64>,
+    /// Time to run for, in the time of the [clock::Clock] of the VM.
+    /// The deadline is checked every [Budget::DEADLINE_CHECK_INTERVAL]
+    /// instructions, so the slice may overrun it slightly.
+    /// A [clock::VirtualClock] stands still while threads run, so only idle
+    /// time counts then.
+    pub time: Option<std::time::Duration>,
+    /// Time to spend on incremental GC at the start of the slice.
+    /// No collection runs automatically if None.
+    pub gc_time: Option<std::time::Duration>,
+}
+
 #[derive(Debug)]
 struct JStackFrame {
     /// Range in [JThreadContext] stack. (size = max_locals + max_stack)
     range: std::ops::Range<u32>,
     /// Operand stack top. (initial = max_locals)
     sp: u32,
-    /// Program counter.
+    /// Number of local variable slots. (operand stack bottom)
+    max_locals: u32,
+    /// Program counter. (the next op while executing an op)
     pc: u32,
+    /// Address of the op being executed. (the base of branch offsets)
+    op_pc: u32,
     class: Rc<JClass>,
     method: Rc<MethodInfo>,
+    /// The monitor of a synchronized method. (`this` or the Class instance)
+    monitor: Option<JRef>,
+    /// Whether the monitor is entered. It is entered before the first op,
+    /// and exited when the frame is popped.
+    locked: bool,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum JValue {
     Invalid,
     Null,
@@ -55,16 +210,34 @@ pub enum JValue {
     Long(i64),
     Float(f32),
     Double(f64),
-    //Ref(Rc<JValue>),
+    Ref(JRef),
+}
+
+impl JValue {
+    pub fn from_ref(r: Option<JRef>) -> Self {
+        match r {
+            Some(r) => Self::Ref(r),
+            None => Self::Null,
+        }
+    }
+
+    /// The reference, or None if null or not a reference.
+    pub fn as_ref(&self) -> Option<JRef> {
+        match *self {
+            Self::Ref(r) => Some(r),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug, PartialEq, Eq)]
-struct JType {
+pub struct JType {
     array_dim: usize,
     ctype: JComponentType,
 }
 
 impl JType {
+    #[cfg(test)]
     fn scalar_of(ctype: JComponentType) -> Self {
         Self {
             array_dim: 0,
@@ -82,10 +255,65 @@ impl JType {
             self.ctype.to_default_value()
         }
     }
+
+    /// Object or array.
+    pub fn is_reference(&self) -> bool {
+        self.array_dim > 0 || matches!(self.ctype, JComponentType::Object(_))
+    }
+
+    /// Narrow an int to boolean, byte, char or short for a store.
+    /// (the value is kept as int)
+    pub fn narrow(&self, v: JValue) -> JValue {
+        let JValue::Int(x) = v else {
+            return v;
+        };
+        if self.array_dim > 0 {
+            return v;
+        }
+        match self.ctype {
+            JComponentType::Boolean => JValue::Int(x & 1),
+            JComponentType::Byte => JValue::Int(x as i8 as i32),
+            JComponentType::Char => JValue::Int(x as u16 as i32),
+            JComponentType::Short => JValue::Int(x as i16 as i32),
+            _ => v,
+        }
+    }
+
+    /// Number of u32 slots occupied by a value of this type.
+    /// long and double are category 2 types and occupy two slots.
+    pub fn slot_size(&self) -> u32 {
+        if self.array_dim == 0
+            && matches!(self.ctype, JComponentType::Long | JComponentType::Double)
+        {
+            2
+        } else {
+            1
+        }
+    }
+
+    /// The class name of a reference type, as in CONSTANT_Class.
+    /// (`java/lang/String`, `[I`, `[[Ljava/lang/String;`)
+    pub fn class_name(&self) -> String {
+        let component = match &self.ctype {
+            JComponentType::Object(name) if self.array_dim == 0 => return name.clone(),
+            JComponentType::Object(name) => {
+                return format!("{}L{name};", "[".repeat(self.array_dim));
+            }
+            JComponentType::Boolean => 'Z',
+            JComponentType::Byte => 'B',
+            JComponentType::Char => 'C',
+            JComponentType::Short => 'S',
+            JComponentType::Int => 'I',
+            JComponentType::Long => 'J',
+            JComponentType::Float => 'F',
+            JComponentType::Double => 'D',
+        };
+        format!("{}{component}", "[".repeat(self.array_dim))
+    }
 }
 
 #[derive(Debug, PartialEq, Eq)]
-enum JComponentType {
+pub enum JComponentType {
     Boolean,
     Byte,
     Char,
@@ -109,7 +337,6 @@ impl JComponentType {
     }
 }
 
-#[allow(dead_code)]
 pub mod acc_class {
     /// Declared public; may be accessed from outside its package.
     pub const PUBLIC: u16 = 0x0001;
@@ -129,7 +356,6 @@ pub mod acc_class {
     pub const ENUM: u16 = 0x4000;
 }
 
-#[allow(dead_code)]
 pub mod acc_field {
     /// Declared public; may be accessed from outside its package.
     pub const PUBLIC: u16 = 0x0001;
@@ -151,7 +377,6 @@ pub mod acc_field {
     pub const ENUM: u16 = 0x4000;
 }
 
-#[allow(dead_code)]
 pub mod acc_method {
     /// Declared public; may be accessed from outside its package.
     pub const PUBLIC: u16 = 0x0001;
@@ -179,7 +404,6 @@ pub mod acc_method {
     pub const SYNTHETIC: u16 = 0x1000;
 }
 
-#[allow(dead_code)]
 #[derive(Debug)]
 pub struct JClass {
     constant_pool: ConstantPool,
@@ -194,8 +418,98 @@ pub struct JClass {
 
 #[derive(Default)]
 struct JClassRuntimeInfo {
-    pub initialized: bool,
+    pub init_state: InitState,
+    /// Values of the static fields declared in the class. (key = name_desc)
     pub static_fields: HashMap<String, JValue>,
+    /// Instance field layout. (computed at link time)
+    pub layout: Option<Rc<ClassLayout>>,
+    /// Virtual method table. (built at the first use)
+    pub vtable: Option<Rc<VTable>>,
+}
+
+/// Caches of one class definition, keyed by the constant pool index.
+///
+/// A redefined class has another constant pool, so the new definition gets
+/// its own caches while the frames of the old one keep using the old caches.
+#[derive(Default)]
+struct CpCache {
+    /// The definition. The [Weak] keeps its address from being reused while
+    /// the cache exists.
+    class: std::rc::Weak<JClass>,
+    /// Resolved Fieldref for getfield/putfield.
+    fields: HashMap<u16, ResolvedField>,
+    /// Resolved Methodref/InterfaceMethodref.
+    methods: HashMap<u16, ResolvedMethod>,
+    /// Interned String instances of CONSTANT_String.
+    strings: HashMap<u16, JRef>,
+}
+
+/// 5.5. Initialization state of a class.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum InitState {
+    #[default]
+    Uninitialized,
+    /// <clinit> is running. A recursive request from the same thread
+    /// completes immediately.
+    Initializing,
+    Initialized,
+    /// <clinit> threw an exception. The class cannot be used any more.
+    Erroneous,
+}
+
+/// Instance field layout of a class.
+///
+/// An instance is a flat array of u32 slots.
+/// The slots of the superclass come first and the fields declared in this
+/// class follow, so a slot index resolved against a class is also valid
+/// for every instance of its subclasses.
+#[derive(Debug, Default)]
+pub struct ClassLayout {
+    /// Total number of slots of an instance, including inherited fields.
+    slot_count: u32,
+    /// Slot index of each instance field declared in this class.
+    /// (key = name_desc)
+    ///
+    /// A field with the same name as a superclass field hides it and gets
+    /// its own slot.
+    slots: HashMap<String, u32>,
+    /// Slots which hold references, including inherited fields. (for the GC)
+    ref_slots: Vec<u32>,
+}
+
+/// getfield/putfield target resolved to a direct slot.
+#[derive(Debug, Clone)]
+pub struct ResolvedField {
+    /// The class which declares the field.
+    class: Rc<String>,
+    field: Rc<FieldInfo>,
+    /// Slot index in the instance.
+    slot: u32,
+}
+
+/// Virtual method table of a class.
+///
+/// The slots of the superclass come first and an overriding method takes
+/// the slot of the overridden one.
+#[derive(Debug, Default, Clone)]
+pub struct VTable {
+    /// Selected method of each slot and the class which declares it.
+    entries: Vec<(Rc<JClass>, Rc<MethodInfo>)>,
+    /// Slot index by name_desc.
+    ///
+    /// Package-private methods of different packages may have the same
+    /// name_desc in different slots, and the latest one is indexed.
+    index: HashMap<String, usize>,
+}
+
+/// invoke* target resolved from a Methodref or InterfaceMethodref.
+#[derive(Debug, Clone)]
+pub struct ResolvedMethod {
+    /// The class referenced by the Methodref.
+    class: Rc<JClass>,
+    /// The class which declares the method.
+    decl: Rc<JClass>,
+    method: Rc<MethodInfo>,
 }
 
 #[derive(Debug)]
@@ -224,8 +538,9 @@ enum ConstInfo {
         name: Rc<String>,
         descriptor: Rc<String>,
     },
+    /// The UTF-16 code units, which may not be valid Unicode.
     String {
-        string: Rc<String>,
+        string: Rc<[u16]>,
     },
     Integer {
         bytes: i32,
@@ -255,33 +570,13 @@ pub struct FieldInfo {
     descriptor: Rc<String>,
     name_desc: String,
     // attributes
-    constant_value: Option<JValue>,
+    /// ConstantValue: Integer, Float, Long, Double or String.
+    constant_value: Option<ConstInfo>,
 
     // parsed
     pub jtype: JType,
 }
 
-type NativeFunc = Box<dyn FnMut(/* TODO */)>;
-
-enum MethodCode {
-    /// No code attribute
-    None,
-    /// JVM instructions
-    JOp(Code),
-    /// Native function call
-    Native(NativeFunc),
-}
-
-impl std::fmt::Debug for MethodCode {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
-        match self {
-            Self::None => f.write_str("None"),
-            Self::JOp(code) => f.debug_tuple("JOp").field(code).finish(),
-            Self::Native(_) => f.write_str("Native"),
-        }
-    }
-}
-
 #[derive(Debug)]
 pub struct MethodInfo {
     access_flags: u16,
@@ -298,16 +593,20 @@ pub struct MethodInfo {
 
 #[derive(Debug)]
 enum Attribute {
-    ConstantValue(JValue),
+    ConstantValue(ConstInfo),
     Code(Code),
 }
 
-type NativeMathod = Box<dyn FnMut()>;
+/// Native method implementation.
+/// args includes `this` at first for an instance method.
+/// Returns the return value (None if void).
+type NativeMathod =
+    dyn Fn(&mut JVM, &mut JThreadContext, &[JValue]) -> anyhow::Result<Option<JValue>>;
 
 pub enum MethodBody {
     None,
     Java(Code),
-    Native(NativeMathod),
+    Native(Rc<NativeMathod>),
 }
 
 impl std::fmt::Debug for MethodBody {
@@ -325,7 +624,8 @@ impl std::fmt::Debug for MethodBody {
 pub struct Code {
     pub max_stack: u16,
     pub max_locals: u16,
-    pub code: Vec<u8>,
+    /// The ops, decoded and checked at load.
+    pub ops: op::DecodedCode,
     pub exception_table: Vec<ExceptionTableEntry>,
     // attributes
 }
@@ -335,7 +635,7 @@ impl std::fmt::Debug for Code {
         f.debug_struct("Code")
             .field("max_stack", &self.max_stack)
             .field("max_locals", &self.max_locals)
-            .field("code_len", &self.code.len())
+            .field("code_len", &self.ops.code_length())
             .field("exception_table", &self.exception_table)
             .finish()
     }
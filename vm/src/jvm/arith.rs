@@ -0,0 +1,131 @@
+//! Arithmetic, conversion and comparison instructions.
+//!
+//! Integer operations wrap in two's complement and shift counts are masked
+//! (5 bits for int, 6 bits for long), as `wrapping_*` does.
+//! Floating-point operations are IEEE 754 round-to-nearest, and `as`
+//! conversions from float to integer saturate and map NaN to 0, both exactly
+//! as Java does. `%` on floats is fmod (truncated), which is Java frem/drem.
+
+use super::*;
+
+use op::Op;
+use stack::SlotValue;
+use std::cmp::Ordering;
+
+impl JVM {
+    /// Execute an arithmetic, conversion or comparison op.
+    pub(super) fn exec_arith(&mut self, th: &mut JThreadContext, op: Op) -> anyhow::Result<()> {
+        match op {
+            Op::Iadd => binary(th, i32::wrapping_add),
+            Op::Ladd => binary(th, i64::wrapping_add),
+            Op::Fadd => binary(th, |a: f32, b| a + b),
+            Op::Dadd => binary(th, |a: f64, b| a + b),
+            Op::Isub => binary(th, i32::wrapping_sub),
+            Op::Lsub => binary(th, i64::wrapping_sub),
+            Op::Fsub => binary(th, |a: f32, b| a - b),
+            Op::Dsub => binary(th, |a: f64, b| a - b),
+            Op::Imul => binary(th, i32::wrapping_mul),
+            Op::Lmul => binary(th, i64::wrapping_mul),
+            Op::Fmul => binary(th, |a: f32, b| a * b),
+            Op::Dmul => binary(th, |a: f64, b| a * b),
+            // INT_MIN / -1 = INT_MIN, INT_MIN % -1 = 0
+            Op::Idiv => int_division(th, i32::wrapping_div)?,
+            Op::Ldiv => int_division(th, i64::wrapping_div)?,
+            Op::Fdiv => binary(th, |a: f32, b| a / b),
+            Op::Ddiv => binary(th, |a: f64, b| a / b),
+            Op::Irem => int_division(th, i32::wrapping_rem)?,
+            Op::Lrem => int_division(th, i64::wrapping_rem)?,
+            Op::Frem => binary(th, |a: f32, b| a % b),
+            Op::Drem => binary(th, |a: f64, b| a % b),
+            Op::Ineg => unary(th, i32::wrapping_neg),
+            Op::Lneg => unary(th, i64::wrapping_neg),
+            Op::Fneg => unary(th, |a: f32| -a),
+            Op::Dneg => unary(th, |a: f64| -a),
+            // the shift count is an int for both int and long
+            Op::Ishl => binary(th, |a: i32, b| a.wrapping_shl(b as u32)),
+            Op::Ishr => binary(th, |a: i32, b| a.wrapping_shr(b as u32)),
+            Op::Iushr => binary(th, |a: i32, b| (a as u32).wrapping_shr(b as u32) as i32),
+            Op::Lshl => shift(th, |a, b| a.wrapping_shl(b as u32)),
+            Op::Lshr => shift(th, |a, b| a.wrapping_shr(b as u32)),
+            Op::Lushr => shift(th, |a, b| (a as u64).wrapping_shr(b as u32) as i64),
+            Op::Iand => binary(th, |a: i32, b| a & b),
+            Op::Land => binary(th, |a: i64, b| a & b),
+            Op::Ior => binary(th, |a: i32, b| a | b),
+            Op::Lor => binary(th, |a: i64, b| a | b),
+            Op::Ixor => binary(th, |a: i32, b| a ^ b),
+            Op::Lxor => binary(th, |a: i64, b| a ^ b),
+            Op::I2L => unary(th, |a: i32| a as i64),
+            Op::I2F => unary(th, |a: i32| a as f32),
+            Op::I2D => unary(th, |a: i32| a as f64),
+            Op::L2I => unary(th, |a: i64| a as i32),
+            Op::L2F => unary(th, |a: i64| a as f32),
+            Op::L2D => unary(th, |a: i64| a as f64),
+            Op::F2I => unary(th, |a: f32| a as i32),
+            Op::F2L => unary(th, |a: f32| a as i64),
+            Op::F2D => unary(th, |a: f32| a as f64),
+            Op::D2I => unary(th, |a: f64| a as i32),
+            Op::D2L => unary(th, |a: f64| a as i64),
+            Op::D2F => unary(th, |a: f64| a as f32),
+            Op::I2B => unary(th, |a: i32| a as i8 as i32),
+            Op::I2C => unary(th, |a: i32| a as u16 as i32),
+            Op::I2S => unary(th, |a: i32| a as i16 as i32),
+            Op::Lcmp => compare(th, |a: i64, b| Some(a.cmp(&b)), 0),
+            // NaN: fcmpl/dcmpl push -1, fcmpg/dcmpg push 1
+            Op::Fcmpl => compare(th, |a: f32, b| a.partial_cmp(&b), -1),
+            Op::Fcmpg => compare(th, |a: f32, b| a.partial_cmp(&b), 1),
+            Op::Dcmpl => compare(th, |a: f64, b| a.partial_cmp(&b), -1),
+            Op::Dcmpg => compare(th, |a: f64, b| a.partial_cmp(&b), 1),
+            _ => anyhow::bail!("not an arithmetic op: {op:?}"),
+        }
+
+        Ok(())
+    }
+}
+
+fn unary<T: SlotValue, U: SlotValue>(th: &mut JThreadContext, f: impl FnOnce(T) -> U) {
+    let a: T = th.pop();
+    th.push(f(a));
+}
+
+fn binary<T: SlotValue>(th: &mut JThreadContext, f: impl FnOnce(T, T) -> T) {
+    let b: T = th.pop();
+    let a: T = th.pop();
+    th.push(f(a, b));
+}
+
+/// lshl, lshr, lushr: long value and int shift count.
+fn shift(th: &mut JThreadContext, f: impl FnOnce(i64, i32) -> i64) {
+    let b: i32 = th.pop();
+    let a: i64 = th.pop();
+    th.push(f(a, b));
+}
+
+/// idiv, ldiv, irem, lrem: ArithmeticException if the divisor is 0.
+fn int_division<T: SlotValue + Default + PartialEq>(
+    th: &mut JThreadContext,
+    f: impl FnOnce(T, T) -> T,
+) -> anyhow::Result<()> {
+    let b: T = th.pop();
+    let a: T = th.pop();
+    throw_unless!(b != T::default(), ArithmeticException, "/ by zero");
+    th.push(f(a, b));
+
+    Ok(())
+}
+
+/// Push -1, 0 or 1, or `nan` if unordered.
+fn compare<T: SlotValue>(
+    th: &mut JThreadContext,
+    f: impl FnOnce(T, T) -> Option<Ordering>,
+    nan: i32,
+) {
+    let b: T = th.pop();
+    let a: T = th.pop();
+    let v = match f(a, b) {
+        Some(Ordering::Less) => -1,
+        Some(Ordering::Equal) => 0,
+        Some(Ordering::Greater) => 1,
+        None => nan,
+    };
+    th.push(v);
+}
@@ -0,0 +1,545 @@
+//! Arrays and the array instructions.
+//!
+//! An array is a heap object of an array class (`[I`, `[[Ljava/lang/String;`)
+//! whose elements are stored compactly by the component type, e.g. byte[] as
+//! one byte and char[] as one u16 per element.
+//! Array classes are created on first use as final subclasses of Object
+//! implementing Cloneable and Serializable.
+
+use super::*;
+
+use heap::JObject;
+use op::Op;
+
+#[derive(Debug, Clone)]
+pub enum JArray {
+    Boolean(Box<[bool]>),
+    Byte(Box<[i8]>),
+    Char(Box<[u16]>),
+    Short(Box<[i16]>),
+    Int(Box<[i32]>),
+    Long(Box<[i64]>),
+    Float(Box<[f32]>),
+    Double(Box<[f64]>),
+    /// Arrays of classes, interfaces and arrays.
+    Ref(Box<[Option<JRef>]>),
+}
+
+impl JArray {
+    /// An array of the array class with all elements zero
+    /// (0, 0.0, false or null).
+    fn new(clsname: &str, len: usize) -> Self {
+        fn zeros<T: Clone + Default>(len: usize) -> Box<[T]> {
+            vec![T::default(); len].into_boxed_slice()
+        }
+        match clsname.as_bytes()[1] {
+            b'Z' => Self::Boolean(zeros(len)),
+            b'B' => Self::Byte(zeros(len)),
+            b'C' => Self::Char(zeros(len)),
+            b'S' => Self::Short(zeros(len)),
+            b'I' => Self::Int(zeros(len)),
+            b'J' => Self::Long(zeros(len)),
+            b'F' => Self::Float(zeros(len)),
+            b'D' => Self::Double(zeros(len)),
+            _ => Self::Ref(zeros(len)),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            Self::Boolean(a) => a.len(),
+            Self::Byte(a) => a.len(),
+            Self::Char(a) => a.len(),
+            Self::Short(a) => a.len(),
+            Self::Int(a) => a.len(),
+            Self::Long(a) => a.len(),
+            Self::Float(a) => a.len(),
+            Self::Double(a) => a.len(),
+            Self::Ref(a) => a.len(),
+        }
+    }
+
+    /// Bytes of the elements.
+    pub fn byte_size(&self) -> usize {
+        let elem = match self {
+            Self::Boolean(_) | Self::Byte(_) => 1,
+            Self::Char(_) | Self::Short(_) => 2,
+            Self::Int(_) | Self::Float(_) | Self::Ref(_) => 4,
+            Self::Long(_) | Self::Double(_) => 8,
+        };
+        self.len() * elem
+    }
+
+    /// Bytes of an element of the array class.
+    fn element_size(clsname: &str) -> usize {
+        match clsname.as_bytes()[1] {
+            b'Z' | b'B' => 1,
+            b'C' | b'S' => 2,
+            b'J' | b'D' => 8,
+            _ => 4,
+        }
+    }
+
+    /// Read the element. boolean, byte, char and short are widened to int.
+    /// The index must be in bounds.
+    pub fn get(&self, i: usize) -> JValue {
+        match self {
+            Self::Boolean(a) => JValue::Int(a[i] as i32),
+            Self::Byte(a) => JValue::Int(a[i] as i32),
+            Self::Char(a) => JValue::Int(a[i] as i32),
+            Self::Short(a) => JValue::Int(a[i] as i32),
+            Self::Int(a) => JValue::Int(a[i]),
+            Self::Long(a) => JValue::Long(a[i]),
+            Self::Float(a) => JValue::Float(a[i]),
+            Self::Double(a) => JValue::Double(a[i]),
+            Self::Ref(a) => JValue::from_ref(a[i]),
+        }
+    }
+
+    /// Write the element. An int is narrowed to the component type.
+    /// The index must be in bounds.
+    /// A value of another type is an error, which unverified code can cause.
+    pub fn set(&mut self, i: usize, v: JValue) -> anyhow::Result<()> {
+        match (self, v) {
+            (Self::Boolean(a), JValue::Int(x)) => a[i] = x & 1 != 0,
+            (Self::Byte(a), JValue::Int(x)) => a[i] = x as i8,
+            (Self::Char(a), JValue::Int(x)) => a[i] = x as u16,
+            (Self::Short(a), JValue::Int(x)) => a[i] = x as i16,
+            (Self::Int(a), JValue::Int(x)) => a[i] = x,
+            (Self::Long(a), JValue::Long(x)) => a[i] = x,
+            (Self::Float(a), JValue::Float(x)) => a[i] = x,
+            (Self::Double(a), JValue::Double(x)) => a[i] = x,
+            (Self::Ref(a), JValue::Null) => a[i] = None,
+            (Self::Ref(a), JValue::Ref(r)) => a[i] = Some(r),
+            (_, v) => anyhow::bail!("{v:?} does not match the component type"),
+        }
+        Ok(())
+    }
+
+    /// Elements of a reference array. (empty for the other arrays)
+    pub fn ref_elems(&self) -> &[Option<JRef>] {
+        match self {
+            Self::Ref(a) => a,
+            _ => &[],
+        }
+    }
+}
+
+/// The class name of the components of the array class.
+/// (`[I` -> `I`, `[Ljava/lang/String;` -> `java/lang/String`, `[[I` -> `[I`)
+pub(super) fn component_class(clsname: &str) -> &str {
+    let comp = &clsname[1..];
+    comp.strip_prefix('L')
+        .and_then(|c| c.strip_suffix(';'))
+        .unwrap_or(comp)
+}
+
+impl JVM {
+    /// The array class of the name, created on first use.
+    /// NoClassDefFoundError if the element class is not loaded.
+    pub fn array_class(&mut self, clsname: &str) -> anyhow::Result<Rc<JClass>> {
+        if let Some(cls) = self.classes.get(clsname) {
+            return Ok(Rc::clone(cls));
+        }
+        let jtype = desc::parse_field_desc(clsname)
+            .ok()
+            .filter(|t| t.array_dim > 0)
+            .ok_or_else(|| exception!(NoClassDefFoundError, "{clsname}"))?;
+        let comp = component_class(clsname);
+        if comp.starts_with('[') {
+            self.array_class(comp)?;
+        } else if let JComponentType::Object(name) = &jtype.ctype {
+            self.get_class(name)?;
+        }
+
+        let mut cls = parse::define_native_class(
+            clsname,
+            Some("java/lang/Object"),
+            HashMap::new(),
+            HashMap::new(),
+        );
+        cls.access_flags = acc_class::PUBLIC | acc_class::FINAL | acc_class::ABSTRACT;
+        cls.interfaces = vec![
+            Rc::new("java/lang/Cloneable".to_string()),
+            Rc::new("java/io/Serializable".to_string()),
+        ];
+        self.load_native_class(cls);
+
+        self.get_class(clsname)
+    }
+
+    /// Allocate an array of the array class with all elements zero.
+    /// NegativeArraySizeException if the length is negative, and
+    /// OutOfMemoryError if it is larger than a single object can be, or as
+    /// [JVM::alloc_object].
+    pub fn alloc_array(&mut self, clsname: &str, len: i32) -> anyhow::Result<JRef> {
+        throw_unless!(len >= 0, NegativeArraySizeException, "{len}");
+        let class = self.array_class(clsname)?;
+        self.link_class(clsname)?;
+        // before the elements are allocated on the host
+        let size = JObject::HEADER_SIZE + len as usize * JArray::element_size(clsname);
+        throw_unless!(
+            size <= self.config.max_object_size,
+            OutOfMemoryError,
+            "Requested array size exceeds VM limit"
+        );
+        self.check_heap_limit(size)?;
+
+        let array = JArray::new(clsname, len as usize);
+
+        Ok(self
+            .heap
+            .alloc(JObject::new(class, Box::new([]), Some(array))))
+    }
+
+    /// Allocate a multi-dimensional array. (multianewarray)
+    /// `counts[0]` is the length of the outermost array. Components of the
+    /// dimensions beyond `counts` are left null.
+    fn alloc_multi_array(&mut self, clsname: &str, counts: &[i32]) -> anyhow::Result<JRef> {
+        for &count in counts {
+            throw_unless!(count >= 0, NegativeArraySizeException, "{count}");
+        }
+        let r = self.alloc_array(clsname, counts[0])?;
+        if counts.len() > 1 {
+            for i in 0..counts[0] as usize {
+                let sub = self.alloc_multi_array(&clsname[1..], &counts[1..])?;
+                self.heap
+                    .get_mut(r)
+                    .elements_mut()
+                    .set(i, JValue::Ref(sub))?;
+            }
+        }
+
+        Ok(r)
+    }
+
+    /// Check the array reference and the index of an element access.
+    fn array_element(
+        &self,
+        r: Option<JRef>,
+        index: i32,
+        access: &str,
+        component: &str,
+    ) -> anyhow::Result<(JRef, usize)> {
+        let r = r.ok_or_else(|| {
+            exception!(
+                NullPointerException,
+                "Cannot {access} {component} array because the reference is null"
+            )
+        })?;
+        let len = self.heap.get(r).elements().len();
+        throw_unless!(
+            0 <= index && (index as usize) < len,
+            ArrayIndexOutOfBoundsException,
+            "Index {index} out of bounds for length {len}"
+        );
+
+        Ok((r, index as usize))
+    }
+
+    /// Execute an array creation, length, load or store op.
+    pub(super) fn exec_array(
+        &mut self,
+        th: &mut JThreadContext,
+        cls: &JClass,
+        op: Op,
+    ) -> anyhow::Result<()> {
+        match op {
+            Op::Newarray { atype } => {
+                let clsname = match atype {
+                    4 => "[Z",
+                    5 => "[C",
+                    6 => "[F",
+                    7 => "[D",
+                    8 => "[B",
+                    9 => "[S",
+                    10 => "[I",
+                    11 => "[J",
+                    _ => anyhow::bail!("invalid atype: {atype}"),
+                };
+                let len: i32 = th.pop();
+                let r = self.alloc_or_collect(th, |jvm| jvm.alloc_array(clsname, len))?;
+                th.push(Some(r));
+            }
+            Op::Anewarray { index } => {
+                let comp = cls.constant_pool.get_class(index)?;
+                let clsname = if comp.starts_with('[') {
+                    format!("[{comp}")
+                } else {
+                    format!("[L{comp};")
+                };
+                let len: i32 = th.pop();
+                let r = self.alloc_or_collect(th, |jvm| jvm.alloc_array(&clsname, len))?;
+                th.push(Some(r));
+            }
+            Op::Multianewarray { index, dimensions } => {
+                let clsname = cls.constant_pool.get_class(index)?;
+                let mut counts = vec![0; dimensions as usize];
+                for count in counts.iter_mut().rev() {
+                    *count = th.pop();
+                }
+                let r =
+                    self.alloc_or_collect(th, |jvm| jvm.alloc_multi_array(&clsname, &counts))?;
+                th.push(Some(r));
+            }
+            Op::Arraylength => {
+                let r: Option<JRef> = th.pop();
+                let r = r.ok_or_else(|| {
+                    exception!(
+                        NullPointerException,
+                        "Cannot read the array length because the reference is null"
+                    )
+                })?;
+                th.push(self.heap.get(r).elements().len() as i32);
+            }
+            Op::Iaload
+            | Op::Laload
+            | Op::Faload
+            | Op::Daload
+            | Op::Aaload
+            | Op::Baload
+            | Op::Caload
+            | Op::Saload => {
+                let index: i32 = th.pop();
+                let r: Option<JRef> = th.pop();
+                let (r, i) = self.array_element(r, index, "load from", component_name(&op))?;
+                th.push_value(self.heap.get(r).elements().get(i));
+            }
+            Op::Iastore
+            | Op::Lastore
+            | Op::Fastore
+            | Op::Dastore
+            | Op::Aastore
+            | Op::Bastore
+            | Op::Castore
+            | Op::Sastore => {
+                let v = match op {
+                    Op::Lastore => JValue::Long(th.pop()),
+                    Op::Fastore => JValue::Float(th.pop()),
+                    Op::Dastore => JValue::Double(th.pop()),
+                    Op::Aastore => JValue::from_ref(th.pop()),
+                    _ => JValue::Int(th.pop()),
+                };
+                let index: i32 = th.pop();
+                let r: Option<JRef> = th.pop();
+                let (r, i) = self.array_element(r, index, "store to", component_name(&op))?;
+                if matches!(op, Op::Aastore) {
+                    self.check_array_store(r, &v)?;
+                    let old = self.heap.get(r).elements().get(i).as_ref();
+                    self.write_barrier(old);
+                }
+                self.heap
+                    .get_mut(r)
+                    .elements_mut()
+                    .set(i, v)
+                    .map_err(|e| exception!(VerifyError, "{e:#}"))?;
+            }
+            _ => anyhow::bail!("not an array op: {op:?}"),
+        }
+
+        Ok(())
+    }
+
+    /// aastore: ArrayStoreException unless the value is null or an instance
+    /// of the component type. (arrays are covariant)
+    fn check_array_store(&self, array: JRef, v: &JValue) -> anyhow::Result<()> {
+        let Some(r) = v.as_ref() else {
+            return Ok(());
+        };
+        let array_class = Rc::clone(&self.heap.get(array).class);
+        let vcls = Rc::clone(&self.heap.get(r).class);
+        throw_unless!(
+            self.instance_of(&vcls, component_class(&array_class.this_class))?,
+            ArrayStoreException,
+            "{}",
+            vcls.this_class.replace('/', ".")
+        );
+
+        Ok(())
+    }
+}
+
+/// The component type in the NullPointerException message of the op.
+fn component_name(op: &Op) -> &'static str {
+    match op {
+        Op::Iaload | Op::Iastore => "int",
+        Op::Laload | Op::Lastore => "long",
+        Op::Faload | Op::Fastore => "float",
+        Op::Daload | Op::Dastore => "double",
+        Op::Aaload | Op::Aastore => "object",
+        Op::Baload | Op::Bastore => "byte/boolean",
+        Op::Caload | Op::Castore => "char",
+        _ => "short",
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use test_util::setup;
+
+    /// The return value, or the class name of the thrown exception.
+    fn call(jvm: &mut JVM, name: &str, args: &[JValue]) -> Result<Option<JValue>, String> {
+        match test_util::invoke(jvm, "Arrays", name, args) {
+            invoke::InvokeResult::Return(v) => Ok(v),
+            invoke::InvokeResult::Exception(ex) => {
+                Err(jvm.heap.get(ex).class.this_class.to_string())
+            }
+        }
+    }
+
+    fn call_ref(jvm: &mut JVM, name: &str, args: &[JValue]) -> JValue {
+        match call(jvm, name, args) {
+            Ok(Some(v @ JValue::Ref(_))) => v,
+            res => panic!("{res:?}"),
+        }
+    }
+
+    #[test]
+    fn test_elements() {
+        let mut jvm = setup();
+        let int = |v| Ok(Some(JValue::Int(v)));
+
+        assert_eq!(call(&mut jvm, "sum", &[JValue::Int(1000)]), int(499500));
+        // -56 + 65535 + -25536
+        assert_eq!(call(&mut jvm, "narrow", &[]), int(39943));
+        assert_eq!(call(&mut jvm, "flag", &[]), int(1));
+        // long + float is a float, which loses the 0.5
+        assert_eq!(
+            call(&mut jvm, "wide", &[]),
+            Ok(Some(JValue::Double((1u64 << 40) as f64 + 0.25)))
+        );
+        let args = [JValue::Int(3), JValue::Int(4)];
+        assert_eq!(call(&mut jvm, "grid", &args), int(347));
+        assert_eq!(call(&mut jvm, "partial", &[]), int(1));
+
+        // compact storage
+        let JValue::Ref(a) = call_ref(&mut jvm, "alloc", &[JValue::Int(1000)]) else {
+            unreachable!();
+        };
+        let obj = jvm.heap.get(a);
+        assert_eq!(obj.class.this_class.as_str(), "[I");
+        assert!(matches!(obj.elements(), JArray::Int(e) if e.len() == 1000));
+        let b = jvm.alloc_array("[B", 1000).unwrap();
+        assert!(jvm.heap.get(b).size() < 1100);
+    }
+
+    #[test]
+    fn test_exceptions() {
+        let mut jvm = setup();
+        let aioobe = "java/lang/ArrayIndexOutOfBoundsException".to_string();
+        let nase = "java/lang/NegativeArraySizeException".to_string();
+
+        assert_eq!(
+            call(&mut jvm, "load", &[JValue::Int(2)]),
+            Ok(Some(JValue::Int(0)))
+        );
+        assert_eq!(
+            call(&mut jvm, "load", &[JValue::Int(3)]),
+            Err(aioobe.clone())
+        );
+        assert_eq!(call(&mut jvm, "load", &[JValue::Int(-1)]), Err(aioobe));
+        assert_eq!(
+            call(&mut jvm, "alloc", &[JValue::Int(-1)]),
+            Err(nase.clone())
+        );
+        let dims = |a, b, c| [JValue::Int(a), JValue::Int(b), JValue::Int(c)];
+        assert_eq!(call(&mut jvm, "alloc3", &dims(2, -1, 3)), Err(nase));
+        let JValue::Ref(a) = call_ref(&mut jvm, "alloc3", &dims(2, 0, 3)) else {
+            unreachable!();
+        };
+        let JValue::Ref(inner) = jvm.heap.get(a).elements().get(1) else {
+            panic!();
+        };
+        assert_eq!(jvm.heap.get(inner).elements().len(), 0);
+        assert_eq!(jvm.heap.get(inner).class.this_class.as_str(), "[[I");
+
+        // covariant stores
+        let strings = call_ref(&mut jvm, "strings", &[]);
+        let s = JValue::Ref(jvm.new_string(&JThreadContext::default(), "s").unwrap());
+        let obj = JValue::Ref(jvm.alloc_object("java/lang/Object").unwrap());
+        assert_eq!(
+            call(&mut jvm, "store", &[strings.clone(), s.clone()]),
+            Ok(None)
+        );
+        assert_eq!(
+            call(&mut jvm, "store", &[strings.clone(), obj.clone()]),
+            Err("java/lang/ArrayStoreException".to_string())
+        );
+        assert_eq!(
+            call(&mut jvm, "store", &[strings.clone(), JValue::Null]),
+            Ok(None)
+        );
+        assert_eq!(
+            call(&mut jvm, "store", &[JValue::Null, s]),
+            Err("java/lang/NullPointerException".to_string())
+        );
+    }
+
+    #[test]
+    fn test_array_classes() {
+        let mut jvm = setup();
+        let yes = Ok(Some(JValue::Int(1)));
+        let no = Ok(Some(JValue::Int(0)));
+
+        // single arguments
+        let ints = [call_ref(&mut jvm, "alloc", &[JValue::Int(1)])];
+        let strings = [call_ref(&mut jvm, "strings", &[])];
+        let nodes = [call_ref(&mut jvm, "nodes", &[])];
+        let dims = [JValue::Int(1), JValue::Int(1), JValue::Int(1)];
+        let ints3 = [call_ref(&mut jvm, "alloc3", &dims)];
+
+        assert_eq!(call(&mut jvm, "isInts", &ints), yes);
+        assert_eq!(call(&mut jvm, "isInts", &strings), no);
+        assert_eq!(call(&mut jvm, "isObjects", &strings), yes);
+        assert_eq!(call(&mut jvm, "isObjects", &ints), no);
+        assert_eq!(call(&mut jvm, "isCloneable", &ints), yes);
+
+        let JValue::Ref(n) = nodes[0] else {
+            unreachable!();
+        };
+        assert_eq!(jvm.heap.get(n).class.this_class.as_str(), "[[LNode;");
+        let [node_grid] = nodes.clone();
+        assert_eq!(call(&mut jvm, "castGrid", &nodes), Ok(Some(node_grid)));
+        // int[][][] is an Object[][] since int[] is an Object
+        let [int_grid] = ints3.clone();
+        assert_eq!(call(&mut jvm, "castGrid", &ints3), Ok(Some(int_grid)));
+        assert_eq!(
+            call(&mut jvm, "castGrid", &strings),
+            Err("java/lang/ClassCastException".to_string())
+        );
+
+        let class = call_ref(&mut jvm, "intsClass", &[]);
+        assert_eq!(call(&mut jvm, "classOf", &ints), Ok(Some(class)));
+
+        // unknown element class
+        assert!(jvm.alloc_array("[LMissing;", 1).is_err());
+    }
+
+    #[test]
+    fn test_gc() {
+        let mut jvm = setup();
+        let th = JThreadContext::default();
+
+        let JValue::Ref(a) = call_ref(&mut jvm, "strings", &[]) else {
+            unreachable!();
+        };
+        let s = jvm.new_string(&th, "kept").unwrap();
+        jvm.heap
+            .get_mut(a)
+            .elements_mut()
+            .set(0, JValue::Ref(s))
+            .unwrap();
+        jvm.new_global_ref(a);
+        jvm.gc(&th);
+        assert_eq!(jvm.get_string(s).as_deref(), Some("kept"));
+
+        jvm.heap
+            .get_mut(a)
+            .elements_mut()
+            .set(0, JValue::Null)
+            .unwrap();
+        jvm.gc(&th);
+        assert!(!jvm.heap.contains(s));
+    }
+}
@@ -0,0 +1,170 @@
+//! Time of the VM.
+//!
+//! Thread.sleep, timeouts of wait and join, System.currentTimeMillis,
+//! System.nanoTime and java/util/Date all read the [Clock] of the [JVM].
+//! [WallClock] follows the host. [VirtualClock] stands still while threads
+//! run and jumps to the next timeout when every thread is blocked, so a
+//! program pacing itself with sleep runs as fast as it can and always sees
+//! the same times.
+//!
+//! A host without std::time (wasm) can supply its own clock.
+
+use super::*;
+
+use std::cell::OnceCell;
+use std::time::{Duration, Instant, SystemTime};
+
+pub trait Clock {
+    /// Time since the Unix epoch. (currentTimeMillis and Date)
+    fn now(&self) -> Duration;
+    /// Time since an arbitrary origin, which never goes back.
+    /// (nanoTime and timeouts)
+    fn monotonic(&self) -> Duration;
+    /// Called when every thread is blocked until `duration` later.
+    /// Returns true if the clock has advanced by it, or false if the host
+    /// should wait.
+    fn idle(&mut self, duration: Duration) -> bool;
+}
+
+/// The clock of the host. It is not read until the first query, so a VM
+/// created on a host without std::time can still be given its own clock.
+#[derive(Default)]
+pub struct WallClock {
+    /// The time of the first [Clock::monotonic].
+    origin: OnceCell<Instant>,
+}
+
+impl Clock for WallClock {
+    fn now(&self) -> Duration {
+        SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+    }
+
+    fn monotonic(&self) -> Duration {
+        self.origin.get_or_init(Instant::now).elapsed()
+    }
+
+    fn idle(&mut self, _duration: Duration) -> bool {
+        false
+    }
+}
+
+/// A clock which advances only when every thread is blocked.
+#[derive(Debug, Clone, Default)]
+pub struct VirtualClock {
+    /// The time since the Unix epoch when the clock is created.
+    start: Duration,
+    elapsed: Duration,
+}
+
+impl VirtualClock {
+    /// A clock starting at the time since the Unix epoch.
+    pub fn new(start: Duration) -> Self {
+        Self {
+            start,
+            elapsed: Duration::ZERO,
+        }
+    }
+}
+
+impl Clock for VirtualClock {
+    fn now(&self) -> Duration {
+        self.start + self.elapsed
+    }
+
+    fn monotonic(&self) -> Duration {
+        self.elapsed
+    }
+
+    fn idle(&mut self, duration: Duration) -> bool {
+        self.elapsed += duration;
+        true
+    }
+}
+
+impl JVM {
+    /// Replace the clock. It should be set before any thread runs, since
+    /// the timeouts of blocked threads are times of the old clock.
+    pub fn set_clock(&mut self, clock: impl Clock + 'static) {
+        self.clock = Box::new(clock);
+    }
+
+    /// The time for timeouts. (milliseconds)
+    pub(super) fn millis(&self) -> u64 {
+        self.clock.monotonic().as_millis() as u64
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use test_util::setup;
+
+    fn call(jvm: &mut JVM, name: &str, args: &[JValue]) -> JValue {
+        test_util::call(jvm, "Pacing", name, args)
+    }
+
+    fn get_static(jvm: &mut JVM, name: &str) -> JValue {
+        let mut th = JThreadContext::default();
+        jvm.get_static(&mut th, "Pacing", name, "J").unwrap()
+    }
+
+    #[test]
+    fn test_wall_clock() {
+        let mut jvm = setup();
+        let before = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as i64;
+        let JValue::Long(now) = call(&mut jvm, "now", &[]) else {
+            panic!();
+        };
+        assert!((before..before + 1000).contains(&now));
+        let JValue::Long(date) = call(&mut jvm, "date", &[]) else {
+            panic!();
+        };
+        assert!(date >= now);
+
+        // read lazily
+        let clock = WallClock::default();
+        assert!(clock.origin.get().is_none());
+        assert!(clock.monotonic() < Duration::from_secs(1));
+        assert!(clock.origin.get().is_some());
+    }
+
+    #[test]
+    fn test_virtual_clock() {
+        let mut jvm = setup();
+        let start = Duration::from_secs(1_000_000_000);
+        jvm.set_clock(VirtualClock::new(start));
+        assert_eq!(call(&mut jvm, "now", &[]), JValue::Long(1_000_000_000_000));
+        let date = call(&mut jvm, "date", &[]);
+        assert_eq!(date, JValue::Long(1_000_000_000_000));
+
+        // a minute of sleep(70) passes at once, and the threads never wait
+        let mut th = JThreadContext::default();
+        let cls = jvm.get_class("Pacing").unwrap();
+        let method = cls.get_method("frames(I)V").unwrap();
+        jvm.call_method(&mut th, cls, method, &[JValue::Int(60_000 / 70)])
+            .unwrap();
+        jvm.spawn(th);
+        let res = jvm.run_for(Budget::default()).unwrap();
+        assert_eq!(res, SchedulerState::Terminated);
+
+        let frames = 60_000 / 70;
+        assert_eq!(get_static(&mut jvm, "elapsed"), JValue::Long(frames * 70));
+        assert_eq!(
+            get_static(&mut jvm, "nanos"),
+            JValue::Long(frames * 70_000_000)
+        );
+        assert_eq!(
+            get_static(&mut jvm, "dateElapsed"),
+            JValue::Long(frames * 70)
+        );
+        assert_eq!(
+            call(&mut jvm, "now", &[]),
+            JValue::Long(1_000_000_000_000 + frames * 70)
+        );
+    }
+}
@@ -0,0 +1,483 @@
+// https://docs.oracle.com/javase/specs/jvms/se21/html/jvms-6.html#jvms-6.5.invokevirtual
+
+use super::*;
+
+use anyhow::Context;
+
+impl JVM {
+    /// Virtual method table of the class. (built at the first use)
+    ///
+    /// The table of the superclass is built first (recursively) and copied,
+    /// so a slot index resolved against a class is also valid for every
+    /// subclass, like the instance field layout.
+    pub fn vtable(&mut self, name: &str) -> anyhow::Result<Rc<VTable>> {
+        let rtinfo = self
+            .class_rt
+            .get(name)
+            .with_context(|| format!("class rtinfo not found: {name}"))?;
+        if let Some(vtable) = &rtinfo.vtable {
+            return Ok(Rc::clone(vtable));
+        }
+
+        let cls = self.get_class(name)?;
+        anyhow::ensure!(!cls.is_interface(), "{name} is an interface");
+        let mut vtable = match &cls.super_class {
+            Some(super_class) => VTable::clone(&*self.vtable(super_class)?),
+            None => VTable::default(),
+        };
+
+        // methods declared in this class override or get new slots
+        let mut methods: Vec<_> = cls.methods.values().filter(|m| is_virtual(m)).collect();
+        methods.sort_by(|a, b| a.name_desc.cmp(&b.name_desc));
+        for m in methods {
+            let mut slot = None;
+            for (i, (decl, sup)) in vtable.entries.iter_mut().enumerate() {
+                if sup.name_desc == m.name_desc && overrides(&cls, decl, sup) {
+                    *decl = Rc::clone(&cls);
+                    *sup = Rc::clone(m);
+                    slot = Some(i);
+                }
+            }
+            let slot = slot.unwrap_or_else(|| {
+                vtable.entries.push((Rc::clone(&cls), Rc::clone(m)));
+                vtable.entries.len() - 1
+            });
+            vtable.index.insert(m.name_desc.clone(), slot);
+        }
+
+        // Slots not implemented by classes take the maximally-specific
+        // superinterface method. (default or abstract)
+        // A slot inherited from an interface is selected again, since this
+        // class may implement a more specific interface.
+        let mut iface_methods = Vec::new();
+        for iface in self.superinterfaces(&cls)? {
+            let mut names: Vec<_> = iface
+                .methods
+                .values()
+                .filter(|m| m.access_flags & (acc_method::PRIVATE | acc_method::STATIC) == 0)
+                .map(|m| m.name_desc.clone())
+                .collect();
+            names.sort();
+            iface_methods.extend(names);
+        }
+        for name_desc in iface_methods {
+            let slot = vtable.index.get(&name_desc).copied();
+            if let Some(slot) = slot
+                && !vtable.entries[slot].0.is_interface()
+            {
+                continue;
+            }
+            let Some(selected) = self.lookup_superinterface_method(&cls, &name_desc)? else {
+                continue;
+            };
+            match slot {
+                Some(slot) => vtable.entries[slot] = selected,
+                None => {
+                    vtable.entries.push(selected);
+                    vtable.index.insert(name_desc, vtable.entries.len() - 1);
+                }
+            }
+        }
+
+        let vtable = Rc::new(vtable);
+        let rtinfo = self
+            .class_rt
+            .get_mut(name)
+            .with_context(|| format!("class rtinfo not found: {name}"))?;
+        rtinfo.vtable = Some(Rc::clone(&vtable));
+
+        Ok(vtable)
+    }
+
+    /// Resolve a Methodref or InterfaceMethodref for an invoke instruction.
+    ///
+    /// The result is cached per constant pool index of the referencing class
+    /// definition.
+    pub fn resolve_method_ref(
+        &mut self,
+        cls: &Rc<JClass>,
+        index: u16,
+    ) -> anyhow::Result<ResolvedMethod> {
+        let cache = self
+            .cp_cache(cls)
+            .and_then(|cache| cache.methods.get(&index));
+        if let Some(resolved) = cache {
+            return Ok(resolved.clone());
+        }
+
+        let mref = cls.constant_pool.get_method(index)?;
+        // methods of Object invoked on an array, such as clone
+        if mref.class.starts_with('[') {
+            self.array_class(&mref.class)?;
+        }
+        let (decl, method) = if mref.is_interface {
+            self.resolve_interface_method(&mref.class, &mref.name, &mref.descriptor)?
+        } else {
+            self.resolve_method(&mref.class, &mref.name, &mref.descriptor)?
+        };
+        let resolved = ResolvedMethod {
+            class: self.get_class(&mref.class)?,
+            decl,
+            method,
+        };
+
+        self.cp_cache_mut(cls)
+            .methods
+            .insert(index, resolved.clone());
+
+        Ok(resolved)
+    }
+
+    /// invokevirtual: select the method by the class of the receiver.
+    ///
+    /// A private method is selected as resolved.
+    /// Otherwise the slot of the resolved method in the referenced class is
+    /// looked up in the vtable of the receiver class.
+    pub fn select_virtual(
+        &mut self,
+        resolved: &ResolvedMethod,
+        receiver: &JClass,
+    ) -> anyhow::Result<(Rc<JClass>, Rc<MethodInfo>)> {
+        let ResolvedMethod {
+            class,
+            decl,
+            method,
+        } = resolved;
+        throw_unless!(
+            method.access_flags & acc_method::STATIC == 0,
+            IncompatibleClassChangeError,
+            "{}.{} is static",
+            decl.this_class,
+            method.name_desc
+        );
+        if method.access_flags & acc_method::PRIVATE != 0 {
+            return Ok((Rc::clone(decl), Rc::clone(method)));
+        }
+
+        let slot = *self
+            .vtable(&class.this_class)?
+            .index
+            .get(&method.name_desc)
+            .with_context(|| {
+                format!(
+                    "vtable slot not found: {}.{}",
+                    class.this_class, method.name_desc
+                )
+            })?;
+        let (cls, m) = &self.vtable(&receiver.this_class)?.entries[slot];
+
+        Ok((Rc::clone(cls), Rc::clone(m)))
+    }
+
+    /// invokeinterface: select the method by the class of the receiver.
+    ///
+    /// The name_desc index of the vtable serves as the itable, since all
+    /// interface methods selected here are public and the latest public
+    /// method for a name_desc overrides all others.
+    pub fn select_interface(
+        &mut self,
+        resolved: &ResolvedMethod,
+        receiver: &JClass,
+    ) -> anyhow::Result<(Rc<JClass>, Rc<MethodInfo>)> {
+        let ResolvedMethod {
+            class,
+            decl,
+            method,
+        } = resolved;
+        throw_unless!(
+            method.access_flags & acc_method::STATIC == 0,
+            IncompatibleClassChangeError,
+            "{}.{} is static",
+            decl.this_class,
+            method.name_desc
+        );
+        throw_unless!(
+            self.instance_of(receiver, &class.this_class)?,
+            IncompatibleClassChangeError,
+            "{} does not implement {}",
+            receiver.this_class,
+            class.this_class
+        );
+        if method.access_flags & acc_method::PRIVATE != 0 {
+            return Ok((Rc::clone(decl), Rc::clone(method)));
+        }
+
+        let vtable = self.vtable(&receiver.this_class)?;
+        let (cls, m) = vtable
+            .index
+            .get(&method.name_desc)
+            .map(|&slot| &vtable.entries[slot])
+            .ok_or_else(|| {
+                exception!(
+                    AbstractMethodError,
+                    "{}.{}",
+                    receiver.this_class,
+                    method.name_desc
+                )
+            })?;
+        throw_unless!(
+            m.access_flags & acc_method::PUBLIC != 0,
+            IllegalAccessError,
+            "{}.{} is not public",
+            cls.this_class,
+            m.name_desc
+        );
+
+        Ok((Rc::clone(cls), Rc::clone(m)))
+    }
+
+    /// invokespecial: the ACC_SUPER procedure. (see technote.md)
+    ///
+    /// The resolved method is selected unless all of the following are true:
+    /// * The ACC_SUPER flag is set for the current class.
+    ///   (every compiler since Java 1.1 sets it, and HotSpot since Java 7
+    ///   Update 13 treats it as always set, but this follows the spec)
+    /// * The class of the resolved method is a superclass of the current class.
+    /// * The resolved method is not an instance initialization method.
+    ///
+    /// Otherwise the method with the same name and descriptor is looked up
+    /// from the direct superclass of the current class upward.
+    /// If nothing is found or the selected method is abstract,
+    /// AbstractMethodError.
+    pub fn select_special(
+        &self,
+        resolved: &ResolvedMethod,
+        current: &JClass,
+    ) -> anyhow::Result<(Rc<JClass>, Rc<MethodInfo>)> {
+        let ResolvedMethod { decl, method, .. } = resolved;
+        throw_unless!(
+            method.access_flags & acc_method::STATIC == 0,
+            IncompatibleClassChangeError,
+            "{}.{} is static",
+            decl.this_class,
+            method.name_desc
+        );
+
+        let dynamic = current.access_flags & acc_class::SUPER != 0
+            && method.name.as_str() != "<init>"
+            && !decl.is_interface()
+            && self.is_subclass_of(&current.this_class, &decl.this_class)?;
+        let selected = if dynamic {
+            self.lookup_super_method(current, &method.name_desc)?
+        } else {
+            Some((Rc::clone(decl), Rc::clone(method)))
+        };
+
+        match selected {
+            Some((cls, m)) if m.access_flags & acc_method::ABSTRACT == 0 => Ok((cls, m)),
+            _ => throw!(
+                AbstractMethodError,
+                "{}.{}",
+                current.this_class,
+                method.name_desc
+            ),
+        }
+    }
+
+    /// Look up an instance method from the direct superclass upward.
+    fn lookup_super_method(
+        &self,
+        current: &JClass,
+        name_desc: &str,
+    ) -> anyhow::Result<Option<(Rc<JClass>, Rc<MethodInfo>)>> {
+        let mut cur = current.super_class.clone();
+        while let Some(name) = cur {
+            let c = self.get_class(&name)?;
+            if let Some(m) = c.methods.get(name_desc)
+                && m.access_flags & acc_method::STATIC == 0
+            {
+                let m = Rc::clone(m);
+                return Ok(Some((c, m)));
+            }
+            cur = c.super_class.clone();
+        }
+
+        Ok(None)
+    }
+}
+
+/// Whether the method can be selected by invokevirtual, i.e. can override
+/// or be overridden.
+fn is_virtual(m: &MethodInfo) -> bool {
+    m.access_flags & (acc_method::STATIC | acc_method::PRIVATE) == 0 && !m.name.starts_with('<')
+}
+
+/// 5.4.5. Overriding
+///
+/// Whether a method declared in `cls` overrides `sup` declared in `decl`.
+/// (the name and descriptor are already checked)
+/// A package-private method is overridden only in the same run-time package.
+fn overrides(cls: &JClass, decl: &JClass, sup: &MethodInfo) -> bool {
+    if sup.access_flags & (acc_method::PUBLIC | acc_method::PROTECTED) != 0 {
+        return true;
+    }
+    if sup.access_flags & acc_method::PRIVATE != 0 {
+        return false;
+    }
+
+    package_of(&cls.this_class) == package_of(&decl.this_class)
+}
+
+fn package_of(clsname: &str) -> &str {
+    clsname.rsplit_once('/').map_or("", |(pkg, _)| pkg)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::jvm::invoke::InvokeResult;
+    use test_util::define_class;
+
+    fn resolved(jvm: &JVM, clsname: &str, name: &str) -> ResolvedMethod {
+        let (decl, method) = jvm.resolve_method(clsname, name, "()V").unwrap();
+        ResolvedMethod {
+            class: jvm.get_class(clsname).unwrap(),
+            decl,
+            method,
+        }
+    }
+
+    #[test]
+    fn test_vtable() {
+        const IFACE: u16 = acc_class::INTERFACE | acc_class::ABSTRACT;
+        const PUBLIC: u16 = acc_method::PUBLIC;
+        const ABSTRACT: u16 = acc_method::PUBLIC | acc_method::ABSTRACT;
+
+        let mut jvm = JVM::new();
+        let classes = [
+            define_class(0, "java/lang/Object", None, &[], &[], &[]),
+            define_class(
+                IFACE,
+                "I",
+                None,
+                &[],
+                &[],
+                &[(ABSTRACT, "i", "()V"), (PUBLIC, "d", "()V")],
+            ),
+            // package-private a is not overridden from another package
+            define_class(
+                acc_class::ABSTRACT,
+                "p/A",
+                Some("java/lang/Object"),
+                &["I"],
+                &[],
+                &[
+                    (PUBLIC, "m", "()V"),
+                    (0, "a", "()V"),
+                    (acc_method::PRIVATE, "p", "()V"),
+                    (ABSTRACT, "n", "()V"),
+                ],
+            ),
+            define_class(
+                0,
+                "q/B",
+                Some("p/A"),
+                &[],
+                &[],
+                &[(PUBLIC, "m", "()V"), (0, "a", "()V")],
+            ),
+            define_class(
+                acc_class::SUPER,
+                "q/C",
+                Some("q/B"),
+                &[],
+                &[],
+                &[(0, "a", "()V"), (PUBLIC, "i", "()V")],
+            ),
+            // compiled without ACC_SUPER
+            define_class(0, "q/D", Some("q/B"), &[], &[], &[]),
+        ];
+        for cls in classes {
+            jvm.load_native_class(cls);
+        }
+
+        let a = jvm.vtable("p/A").unwrap();
+        let b = jvm.vtable("q/B").unwrap();
+        let c = jvm.vtable("q/C").unwrap();
+        assert!(!a.index.contains_key("p()V"));
+        // m: overridden in the same slot
+        let m = a.index["m()V"];
+        assert_eq!(b.index["m()V"], m);
+        assert_eq!(b.entries[m].0.this_class.as_str(), "q/B");
+        assert_eq!(c.entries[m].0.this_class.as_str(), "q/B");
+        // a: a new slot in B, and C overrides B.a only
+        assert_ne!(b.index["a()V"], a.index["a()V"]);
+        assert_eq!(c.index["a()V"], b.index["a()V"]);
+        assert_eq!(c.entries[a.index["a()V"]].0.this_class.as_str(), "p/A");
+        // interface methods: default, abstract and implemented
+        assert_eq!(a.entries[a.index["d()V"]].0.this_class.as_str(), "I");
+        assert_eq!(a.entries[a.index["i()V"]].0.this_class.as_str(), "I");
+        assert_eq!(c.index["i()V"], a.index["i()V"]);
+        assert_eq!(c.entries[c.index["i()V"]].0.this_class.as_str(), "q/C");
+
+        // AbstractMethodError on an abstract selected method
+        let b_cls = jvm.get_class("q/B").unwrap();
+        let (cls, m) = jvm
+            .select_virtual(&resolved(&jvm, "p/A", "i"), &b_cls)
+            .unwrap();
+        let mut th = JThreadContext::default();
+        let err = jvm.call_method(&mut th, cls, m, &[]).err().unwrap();
+        assert!(err.to_string().starts_with("java.lang.AbstractMethodError"));
+
+        // invokespecial: super.m() from C selects B.m dynamically
+        let c_cls = jvm.get_class("q/C").unwrap();
+        let (cls, _) = jvm
+            .select_special(&resolved(&jvm, "p/A", "m"), &c_cls)
+            .unwrap();
+        assert_eq!(cls.this_class.as_str(), "q/B");
+        // without ACC_SUPER, the resolved A.m is selected as is
+        let d_cls = jvm.get_class("q/D").unwrap();
+        let (cls, _) = jvm
+            .select_special(&resolved(&jvm, "p/A", "m"), &d_cls)
+            .unwrap();
+        assert_eq!(cls.this_class.as_str(), "p/A");
+        // super.n() selects the abstract A.n
+        let err = jvm
+            .select_special(&resolved(&jvm, "p/A", "n"), &c_cls)
+            .err()
+            .unwrap();
+        assert!(err.to_string().starts_with("java.lang.AbstractMethodError"));
+    }
+
+    #[test]
+    fn test_invoke() {
+        let mut jvm = test_util::setup();
+
+        let mut th = JThreadContext::default();
+        let mut new = |jvm: &mut JVM, clsname| match jvm
+            .new_instance(&mut th, clsname, "()V", &[])
+            .unwrap()
+        {
+            InvokeResult::Return(Some(v)) => v,
+            res => panic!("{res:?}"),
+        };
+        let square = new(&mut jvm, "Square");
+        let cube = new(&mut jvm, "Cube");
+        let tri = new(&mut jvm, "Tri");
+
+        let mut call = |name, obj: &JValue| match test_util::call(
+            &mut jvm,
+            "Dispatch",
+            name,
+            std::slice::from_ref(obj),
+        ) {
+            JValue::Int(v) => v,
+            res => panic!("{res:?}"),
+        };
+        // invokeinterface
+        assert_eq!(call("area", &square), 3);
+        assert_eq!(call("area", &cube), 2);
+        assert_eq!(call("area", &tri), 6);
+        // default method and the override in a class
+        assert_eq!(call("sides", &tri), 0);
+        assert_eq!(call("sides", &cube), 4);
+        assert_eq!(call("describe", &tri), 0);
+        assert_eq!(call("describe", &square), 4);
+        // invokevirtual
+        assert_eq!(call("size", &square), 3);
+        assert_eq!(call("size", &cube), 2);
+        // invokespecial: super.size() and a private method
+        assert_eq!(call("superSize", &cube), 3);
+        assert_eq!(call("secret", &cube), 7);
+    }
+}
@@ -0,0 +1,276 @@
+//! Java exceptions.
+//!
+//! A Java exception raised by the VM or a native method travels as a
+//! [Throw] in `anyhow::Error` until it reaches the interpreter loop, where it
+//! becomes a Java object and the frames are unwound to a handler.
+//! Any other error is a VM failure and aborts the run.
+
+use super::*;
+
+use anyhow::Context;
+
+/// A Java exception in `anyhow::Error`: `java/lang/<class>` with a message.
+macro_rules! exception {
+    ($class:ident, $($arg:tt)*) => {
+        anyhow::Error::new($crate::jvm::exception::Throw::New {
+            class: concat!("java/lang/", stringify!($class)).to_string(),
+            message: Some(format!($($arg)*)),
+        })
+    };
+}
+
+/// Return a Java exception from the function.
+macro_rules! throw {
+    ($class:ident, $($arg:tt)*) => {
+        return Err(exception!($class, $($arg)*))
+    };
+}
+
+/// Like `anyhow::ensure!`, but returns a Java exception.
+macro_rules! throw_unless {
+    ($cond:expr, $class:ident, $($arg:tt)*) => {
+        if !$cond {
+            throw!($class, $($arg)*);
+        }
+    };
+}
+
+/// A Java exception to be thrown into the thread.
+#[derive(Debug)]
+pub enum Throw {
+    /// A new instance of the class is created with the stack trace of the
+    /// thread.
+    New {
+        class: String,
+        message: Option<String>,
+    },
+    /// athrow, or an exception object created by a native method.
+    Object(JRef),
+}
+
+impl std::fmt::Display for Throw {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::New { class, message } => {
+                write!(f, "{}", class.replace('/', "."))?;
+                if let Some(message) = message {
+                    write!(f, ": {message}")?;
+                }
+                Ok(())
+            }
+            Self::Object(r) => write!(f, "exception object {r:?}"),
+        }
+    }
+}
+
+impl std::error::Error for Throw {}
+
+/// VM-side state of a Throwable instance.
+#[derive(Debug, Default)]
+pub struct ExceptionInfo {
+    /// The message of an exception raised by the VM.
+    pub message: Option<String>,
+    /// "Class.method(pc N)" from the innermost frame.
+    pub stack_trace: Vec<String>,
+}
+
+impl JVM {
+    /// Convert a Java exception in the error to an exception object.
+    /// Any other error is returned as is.
+    pub fn exception_object(
+        &mut self,
+        th: &JThreadContext,
+        err: anyhow::Error,
+    ) -> anyhow::Result<JRef> {
+        match err.downcast::<Throw>() {
+            Ok(Throw::Object(ex)) => Ok(ex),
+            Ok(Throw::New { class, message }) => {
+                let obj = self
+                    .new_object(&class)
+                    .with_context(|| format!("cannot create {class}"))?;
+                // even over the heap limit, to throw OutOfMemoryError
+                let ex = self.heap.alloc(obj);
+                self.fill_in_stack_trace(th, ex)?;
+                self.exceptions.entry(ex).or_default().message = message;
+                Ok(ex)
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Record the stack trace of the thread to the exception.
+    ///
+    /// The frames of the constructors of the exception are omitted, as
+    /// HotSpot does.
+    pub fn fill_in_stack_trace(&mut self, th: &JThreadContext, ex: JRef) -> anyhow::Result<()> {
+        let mut frames = th.frames.iter().rev().peekable();
+        while let Some(frame) = frames.peek() {
+            if frame.method.name.as_str() != "<init>"
+                || !self.instance_of(&self.heap.get(ex).class, &frame.class.this_class)?
+            {
+                break;
+            }
+            frames.next();
+        }
+        let stack_trace = frames
+            .map(|frame| {
+                format!(
+                    "{}.{}(pc {})",
+                    frame.class.this_class.replace('/', "."),
+                    frame.method.name,
+                    frame.op_pc
+                )
+            })
+            .collect();
+        self.exceptions.entry(ex).or_default().stack_trace = stack_trace;
+
+        Ok(())
+    }
+
+    /// Unwind the frames above `depth` to the first handler of the exception.
+    ///
+    /// If a handler is found, the operand stack of the frame is cleared,
+    /// the exception is pushed, and the execution continues at the handler.
+    /// Returns false if no frame above `depth` handles it.
+    pub fn unwind(
+        &mut self,
+        th: &mut JThreadContext,
+        ex: JRef,
+        depth: usize,
+    ) -> anyhow::Result<bool> {
+        let ex_class = Rc::clone(&self.heap.get(ex).class);
+        while th.frames.len() > depth {
+            let frame = th.current_frame();
+            if let Some(handler_pc) = self.find_handler(frame, &ex_class)? {
+                frame.sp = frame.max_locals;
+                frame.pc = handler_pc as u32;
+                th.push(Some(ex));
+                return Ok(true);
+            }
+            self.exit_frame_monitor(th)?;
+            th.pop_frame();
+        }
+
+        Ok(false)
+    }
+
+    /// Search the exception table of the frame for the op being executed.
+    /// The first matching entry is the handler.
+    fn find_handler(&self, frame: &JStackFrame, ex_class: &JClass) -> anyhow::Result<Option<u16>> {
+        let MethodBody::Java(code) = &frame.method.method_body else {
+            return Ok(None);
+        };
+        for e in code.exception_table.iter() {
+            if !(e.start_pc as u32..e.end_pc as u32).contains(&frame.op_pc) {
+                continue;
+            }
+            // 0 catches all (finally)
+            if e.catch_type == 0 {
+                return Ok(Some(e.handler_pc));
+            }
+            let catch_class = frame.class.constant_pool.get_class(e.catch_type)?;
+            if self.instance_of(ex_class, &catch_class)? {
+                return Ok(Some(e.handler_pc));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Print the exception and its stack trace to stderr.
+    /// (Throwable.printStackTrace)
+    pub fn print_stack_trace(&self, ex: JRef) {
+        let class = self.heap.get(ex).class.this_class.replace('/', ".");
+        let info = self.exceptions.get(&ex);
+        match info.and_then(|info| info.message.as_ref()) {
+            Some(message) => eprintln!("{class}: {message}"),
+            None => eprintln!("{class}"),
+        }
+        for elem in info.iter().flat_map(|info| info.stack_trace.iter()) {
+            eprintln!("\tat {elem}");
+        }
+    }
+
+    /// Print an exception which terminated the thread.
+    pub fn print_uncaught(&self, th: &JThreadContext, ex: JRef) {
+        eprint!("Exception in thread \"{}\" ", th.name);
+        self.print_stack_trace(ex);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use invoke::InvokeResult;
+    use test_util::{exception_class, setup};
+
+    fn new_exception(jvm: &mut JVM, clsname: &str) -> JRef {
+        let mut th = JThreadContext::default();
+        match jvm.new_instance(&mut th, clsname, "()V", &[]).unwrap() {
+            InvokeResult::Return(Some(JValue::Ref(ex))) => ex,
+            res => panic!("{res:?}"),
+        }
+    }
+
+    fn call(jvm: &mut JVM, name: &str, args: &[JValue]) -> InvokeResult {
+        let mut th = JThreadContext::default();
+        let res = jvm.call_method_by_name(&mut th, "Exc", name, args).unwrap();
+        assert!(th.frames.is_empty());
+        assert!(th.stack.is_empty());
+        res
+    }
+
+    #[test]
+    fn test_catch() {
+        let mut jvm = setup();
+        let int = |v| InvokeResult::Return(Some(JValue::Int(v)));
+
+        // VM-raised exceptions
+        let res = call(&mut jvm, "safeDivide", &[JValue::Int(6), JValue::Int(3)]);
+        assert_eq!(res, int(2));
+        let res = call(&mut jvm, "safeDivide", &[JValue::Int(6), JValue::Int(0)]);
+        assert_eq!(res, int(-1));
+        let res = call(&mut jvm, "finallyCount", &[JValue::Int(5)]);
+        assert_eq!(res, int(102));
+        let res = call(&mut jvm, "finallyCount", &[JValue::Int(0)]);
+        assert_eq!(res, int(99));
+        let res = call(&mut jvm, "callNull", &[JValue::Null]);
+        assert_eq!(res, int(-2));
+
+        // athrow and catch_type
+        let ise = new_exception(&mut jvm, "java/lang/IllegalStateException");
+        let iae = new_exception(&mut jvm, "java/lang/IllegalArgumentException");
+        assert_eq!(call(&mut jvm, "catchIt", &[JValue::Ref(ise)]), int(1));
+        assert_eq!(call(&mut jvm, "catchIt", &[JValue::Ref(iae)]), int(2));
+        let res = call(&mut jvm, "rethrow", &[JValue::Ref(ise)]);
+        assert_eq!(res, InvokeResult::Exception(ise));
+        let res = call(&mut jvm, "rethrow", &[JValue::Null]);
+        assert_eq!(exception_class(&jvm, res), "java/lang/NullPointerException");
+    }
+
+    #[test]
+    fn test_uncaught() {
+        let mut jvm = setup();
+
+        let res = call(&mut jvm, "divide", &[JValue::Int(1), JValue::Int(0)]);
+        let InvokeResult::Exception(ex) = res else {
+            panic!("{res:?}");
+        };
+        assert_eq!(exception_class(&jvm, res), "java/lang/ArithmeticException");
+        let info = &jvm.exceptions[&ex];
+        assert_eq!(info.message.as_deref(), Some("/ by zero"));
+        assert_eq!(info.stack_trace, ["Exc.divide(pc 2)"]);
+
+        // only the thread ends
+        let cls = jvm.get_class("Exc").unwrap();
+        let method = cls.get_method("divide(II)I").unwrap();
+        let mut th = JThreadContext::default();
+        jvm.call_method(&mut th, cls, method, &[JValue::Int(1), JValue::Int(0)])
+            .unwrap();
+        let res = jvm.run(&mut th).unwrap();
+        assert!(matches!(res, ThreadState::Uncaught(_)));
+        assert!(th.frames.is_empty());
+        let res = call(&mut jvm, "safeDivide", &[JValue::Int(6), JValue::Int(3)]);
+        assert_eq!(res, InvokeResult::Return(Some(JValue::Int(2))));
+    }
+}
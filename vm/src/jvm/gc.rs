@@ -0,0 +1,472 @@
+//! Mark-sweep garbage collector.
+//!
+//! The roots are precise:
+//! - the locals and operand stacks of the running thread and the threads
+//!   owned by the VM (slots tagged as references), and their Thread objects
+//! - the monitors of synchronized methods on the stacks
+//! - static fields
+//! - interned Strings and Class instances
+//! - global references held by the host or native code
+//!
+//! A reference held only in a Rust variable is not a root, so the host must
+//! make it a global reference to keep it across a collection.
+//!
+//! A collection can also run incrementally in slices of [JVM::run_for]
+//! (snapshot-at-the-beginning): the roots are taken when the cycle starts,
+//! objects allocated during the cycle are marked, and the write barrier
+//! marks a reference before it is overwritten in a field, a static field
+//! or an array. So every object reachable at the start, or reached later
+//! through an overwritten reference, survives the cycle.
+
+use super::*;
+
+use std::time::Duration;
+
+/// State of the collector.
+pub(super) struct GcState {
+    phase: Option<GcPhase>,
+    /// An incremental cycle starts when the used bytes exceed this.
+    trigger: usize,
+    /// Number of completed cycles.
+    cycles: u64,
+}
+
+impl Default for GcState {
+    fn default() -> Self {
+        Self {
+            phase: None,
+            trigger: heap::Heap::INITIAL_SIZE / 2,
+            cycles: 0,
+        }
+    }
+}
+
+enum GcPhase {
+    Mark {
+        /// Marked objects whose fields are not scanned yet.
+        gray: Vec<JRef>,
+        /// A reference array being scanned, and the index of its next
+        /// element.
+        array: Option<(JRef, usize)>,
+    },
+    /// Handles below the cursor are swept.
+    Sweep { cursor: usize },
+}
+
+impl GcState {
+    /// Objects scanned or handles swept between deadline checks.
+    const CHECK_INTERVAL: u64 = 64;
+    /// Elements of a reference array scanned as the work of an object, so
+    /// that a large array does not overrun the deadline.
+    const ARRAY_CHUNK: usize = 256;
+}
+
+impl JVM {
+    /// Collect unreachable objects. `th` is the running thread, and the
+    /// threads owned by the VM are also scanned.
+    /// An incremental cycle in progress is restarted and completed at once.
+    /// Returns the number of freed bytes.
+    pub fn gc(&mut self, th: &JThreadContext) -> usize {
+        let used = self.heap.used();
+        self.start_cycle(Some(th));
+        self.gc_step(None);
+
+        used - self.heap.used()
+    }
+
+    /// Do incremental GC work for the time of the clock. A cycle is started
+    /// when the heap grows beyond the trigger. `th` is the running thread if
+    /// any. A [clock::VirtualClock] stands still, so the cycle completes.
+    pub(super) fn gc_slice(&mut self, th: Option<&JThreadContext>, time: Duration) {
+        let deadline = self.clock.monotonic() + time;
+        if self.gc.phase.is_none() {
+            if self.heap.used() < self.gc.trigger {
+                return;
+            }
+            self.start_cycle(th);
+        }
+        self.gc_step(Some(deadline));
+    }
+
+    /// Keep the object alive until [JVM::delete_global_ref].
+    /// Calls are counted.
+    pub fn new_global_ref(&mut self, r: JRef) {
+        *self.global_refs.entry(r).or_default() += 1;
+    }
+
+    pub fn delete_global_ref(&mut self, r: JRef) {
+        if let Some(count) = self.global_refs.get_mut(&r) {
+            *count -= 1;
+            if *count == 0 {
+                self.global_refs.remove(&r);
+            }
+        }
+    }
+
+    /// Called before a reference in the heap or a static field is
+    /// overwritten. The old value is marked while marking is in progress.
+    pub(super) fn write_barrier(&mut self, old: Option<JRef>) {
+        if let (Some(old), Some(GcPhase::Mark { gray, .. })) = (old, &mut self.gc.phase) {
+            self.heap.shade(old, gray);
+        }
+    }
+
+    fn start_cycle(&mut self, th: Option<&JThreadContext>) {
+        let roots = self.roots(th);
+        let mut gray = Vec::with_capacity(roots.len());
+        self.heap.start_marking();
+        for r in roots {
+            self.heap.shade(r, &mut gray);
+        }
+        self.gc.phase = Some(GcPhase::Mark { gray, array: None });
+    }
+
+    fn roots(&self, th: Option<&JThreadContext>) -> Vec<JRef> {
+        let mut roots = Vec::new();
+        for th in th.into_iter().chain(self.threads.iter().map(|t| &t.ctx)) {
+            roots.extend(th.stack_refs());
+            // `this` may be overwritten in the locals
+            roots.extend(th.frames.iter().filter_map(|frame| frame.monitor));
+            roots.extend(th.object);
+            if let Some(ThreadState::Blocked(reason)) = &th.suspend {
+                roots.extend(reason.object());
+            }
+        }
+        for reason in self.threads.iter().filter_map(|t| t.blocked.as_ref()) {
+            roots.extend(reason.object());
+        }
+        for rtinfo in self.class_rt.values() {
+            roots.extend(rtinfo.static_fields.values().filter_map(JValue::as_ref));
+        }
+        roots.extend(self.interned.values());
+        roots.extend(self.class_objects.values());
+        roots.extend(self.global_refs.keys());
+
+        roots
+    }
+
+    /// Advance the cycle until it completes or the deadline passes.
+    /// Returns true if completed.
+    fn gc_step(&mut self, deadline: Option<Duration>) -> bool {
+        let Some(mut phase) = self.gc.phase.take() else {
+            return true;
+        };
+        let mut work = 0;
+        loop {
+            if work % GcState::CHECK_INTERVAL == 0
+                && deadline.is_some_and(|deadline| self.clock.monotonic() >= deadline)
+            {
+                self.gc.phase = Some(phase);
+                return false;
+            }
+            work += 1;
+
+            match &mut phase {
+                GcPhase::Mark { gray, array } => {
+                    if let Some((r, start)) = array.take() {
+                        *array = self.scan_array(r, start, gray);
+                    } else if let Some(r) = gray.pop() {
+                        self.scan(r, gray);
+                        *array = self.scan_array(r, 0, gray);
+                    } else {
+                        phase = GcPhase::Sweep { cursor: 1 };
+                    }
+                }
+                GcPhase::Sweep { cursor } => {
+                    if *cursor >= self.heap.capacity() {
+                        break;
+                    }
+                    if let Some(r) = self.heap.sweep(*cursor) {
+                        self.exceptions.remove(&r);
+                    }
+                    *cursor += 1;
+                }
+            }
+        }
+
+        self.heap.finish_marking();
+        self.gc.trigger = (self.heap.used() * 2).max(heap::Heap::INITIAL_SIZE / 2);
+        self.gc.cycles += 1;
+        true
+    }
+
+    /// Mark the objects referenced by the fields of the object.
+    fn scan(&mut self, r: JRef, gray: &mut Vec<JRef>) {
+        let obj = self.heap.get(r);
+        let layout = self.class_rt[obj.class.this_class.as_str()]
+            .layout
+            .as_ref()
+            .expect("instance of an unlinked class");
+        let refs: Vec<JRef> = layout
+            .ref_slots
+            .iter()
+            .filter_map(|&slot| JRef::from_slot(obj.fields[slot as usize]))
+            .collect();
+        for r in refs {
+            self.heap.shade(r, gray);
+        }
+    }
+
+    /// Mark the objects referenced by up to [GcState::ARRAY_CHUNK] elements
+    /// of the array from `start`. Returns where to continue if elements
+    /// remain. (None for an object which is not a reference array)
+    fn scan_array(&mut self, r: JRef, start: usize, gray: &mut Vec<JRef>) -> Option<(JRef, usize)> {
+        let elems = self.heap.get(r).array.as_ref()?.ref_elems();
+        let end = elems.len().min(start + GcState::ARRAY_CHUNK);
+        let refs: Vec<JRef> = elems[start..end].iter().flatten().copied().collect();
+        let len = elems.len();
+        for r in refs {
+            self.heap.shade(r, gray);
+        }
+        (end < len).then_some((r, end))
+    }
+}
+
+impl BlockReason {
+    /// The object the thread is blocked on.
+    fn object(&self) -> Option<JRef> {
+        match *self {
+            Self::Sleep(_) => None,
+            Self::Monitor(r) | Self::Wait(r, _) | Self::Join(r, _) => Some(r),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use test_util::{call, setup};
+
+    /// A thread with a frame of 2 locals.
+    fn thread_with_frame() -> JThreadContext {
+        let cls = Rc::new(parse::define_native_class(
+            "T",
+            Some("java/lang/Object"),
+            HashMap::new(),
+            HashMap::new(),
+        ));
+        let mut method = stdlib::define_method(acc_method::STATIC, "t", "()V", |_, _, _| Ok(None));
+        method.method_body = MethodBody::Java(Code {
+            max_stack: 2,
+            max_locals: 2,
+            ops: Default::default(),
+            exception_table: vec![],
+        });
+        let mut th = JThreadContext::default();
+        th.new_frame(cls, Rc::new(method)).unwrap();
+        th
+    }
+
+    #[test]
+    fn test_reachability() {
+        let mut jvm = setup();
+        let idle = JThreadContext::default();
+
+        let JValue::Ref(head) = call(&mut jvm, "Linked", "build", &[JValue::Int(100)]) else {
+            panic!();
+        };
+        // kept by a global reference
+        jvm.new_global_ref(head);
+        jvm.gc(&idle);
+        assert!(jvm.heap.contains(head));
+        let sum = call(&mut jvm, "Linked", "sum", &[JValue::Ref(head)]);
+        assert_eq!(sum, JValue::Int(5050));
+
+        jvm.delete_global_ref(head);
+        let used = jvm.heap.used();
+        let freed = jvm.gc(&idle);
+        assert!(!jvm.heap.contains(head));
+        assert_eq!(used - freed, jvm.heap.used());
+        assert!(freed >= 100 * 24);
+
+        // a freed handle is reused
+        let obj = jvm.alloc_object("java/lang/Object").unwrap();
+        assert!(obj.index() <= head.index());
+    }
+
+    #[test]
+    fn test_stack_roots() {
+        let mut jvm = setup();
+
+        let a = jvm.alloc_object("java/lang/Object").unwrap();
+        let b = jvm.alloc_object("java/lang/Object").unwrap();
+        let c = jvm.alloc_object("java/lang/Object").unwrap();
+        let mut th = thread_with_frame();
+        th.push(Some(a));
+        // an int or a returnAddress with the same value is not a reference
+        th.push(JRef::to_slot(Some(b)) as i32);
+        th.store(0, JRef::to_slot(Some(c)));
+        let slot = th.pop_slot();
+        th.store_slot(1, slot);
+        th.push_slot(th.load_slot(1));
+
+        jvm.gc(&th);
+        assert!(jvm.heap.contains(a));
+        assert!(!jvm.heap.contains(b));
+        assert!(!jvm.heap.contains(c));
+
+        // popped slots are dead
+        th.pop::<u32>();
+        th.pop::<u32>();
+        jvm.gc(&th);
+        assert!(!jvm.heap.contains(a));
+
+        // the monitor of a synchronized method, not in the locals
+        let b = jvm.alloc_object("java/lang/Object").unwrap();
+        th.current_frame().monitor = Some(b);
+        jvm.gc(&th);
+        assert!(jvm.heap.contains(b));
+    }
+
+    #[test]
+    fn test_vm_roots() {
+        let mut jvm = setup();
+        let idle = JThreadContext::default();
+
+        let interned = jvm.intern_string(&idle, "interned").unwrap();
+        let garbage = jvm.new_string(&idle, "garbage").unwrap();
+        let class = jvm.class_object(&idle, "Linked").unwrap();
+        let kept = jvm.alloc_object("java/lang/Object").unwrap();
+        let node = jvm.alloc_object("Node").unwrap();
+        let mut th = JThreadContext::default();
+        let kept_value = JValue::Ref(kept);
+        jvm.put_static(&mut th, "Mem", "keep", "Ljava/lang/Object;", kept_value)
+            .unwrap();
+        let node_value = JValue::Int(JRef::to_slot(Some(node)) as i32);
+        jvm.put_static(&mut th, "Node", "created", "I", node_value)
+            .unwrap();
+
+        jvm.gc(&idle);
+        assert!(jvm.heap.contains(interned));
+        assert!(jvm.heap.contains(class));
+        assert!(jvm.heap.contains(kept));
+        assert!(!jvm.heap.contains(garbage));
+        // an int static is not a reference
+        assert!(!jvm.heap.contains(node));
+    }
+
+    #[test]
+    fn test_system_gc() {
+        let mut jvm = setup();
+
+        let JValue::Long(freed) = call(&mut jvm, "Mem", "churn", &[JValue::Int(1000)]) else {
+            panic!();
+        };
+        assert!(freed >= 1000 * 16);
+        let JValue::Long(total) = call(&mut jvm, "Mem", "total", &[]) else {
+            panic!();
+        };
+        assert_eq!(total as usize, jvm.heap.total());
+        // the runtime object is kept alive
+        assert_eq!(call(&mut jvm, "Mem", "sameRuntime", &[]), JValue::Int(1));
+    }
+
+    #[test]
+    fn test_write_barrier() {
+        let mut jvm = setup();
+        let mut th = thread_with_frame();
+
+        // a -> b, and a is the only root
+        let JValue::Ref(a) = call(&mut jvm, "Linked", "build", &[JValue::Int(2)]) else {
+            panic!();
+        };
+        let JValue::Ref(b) = jvm.get_field(a, "next", "LNode;").unwrap() else {
+            panic!();
+        };
+        th.push(Some(a));
+        jvm.start_cycle(Some(&th));
+
+        // b moves from the heap to the stack during marking
+        th.push(Some(b));
+        jvm.put_field(a, "next", "LNode;", JValue::Null).unwrap();
+        let c = jvm.alloc_object("java/lang/Object").unwrap();
+        assert!(jvm.gc_step(None));
+        assert_eq!(jvm.gc.cycles, 1);
+        assert!(jvm.heap.contains(a));
+        assert!(jvm.heap.contains(b));
+        // allocated during the cycle
+        assert!(jvm.heap.contains(c));
+
+        jvm.gc(&th);
+        assert!(jvm.heap.contains(b));
+        assert!(!jvm.heap.contains(c));
+    }
+
+    /// A clock which advances by a millisecond each time it is read.
+    #[derive(Default)]
+    struct TickClock(std::cell::Cell<Duration>);
+
+    impl Clock for TickClock {
+        fn now(&self) -> Duration {
+            self.monotonic()
+        }
+
+        fn monotonic(&self) -> Duration {
+            let t = self.0.get();
+            self.0.set(t + Duration::from_millis(1));
+            t
+        }
+
+        fn idle(&mut self, _duration: Duration) -> bool {
+            false
+        }
+    }
+
+    #[test]
+    fn test_gc_slice_array() {
+        let mut jvm = setup();
+        let len = 1 << 20;
+        let array = jvm.alloc_array("[Ljava/lang/Object;", len).unwrap();
+        jvm.new_global_ref(array);
+        jvm.set_clock(TickClock::default());
+        jvm.gc.trigger = 0;
+
+        // the deadline passes at the second check, after CHECK_INTERVAL
+        // units of work, in the middle of the array
+        let time = Duration::from_micros(1500);
+        jvm.gc_slice(None, time);
+        let Some(GcPhase::Mark {
+            array: Some((r, next)),
+            ..
+        }) = jvm.gc.phase
+        else {
+            panic!("the array is not being scanned");
+        };
+        assert_eq!(r, array);
+        assert!(next < len as usize);
+        assert_eq!(jvm.gc.cycles, 0);
+
+        while jvm.gc.cycles == 0 {
+            jvm.gc_slice(None, time);
+        }
+        assert!(jvm.heap.contains(array));
+    }
+
+    #[test]
+    fn test_gc_slice() {
+        let mut jvm = setup();
+        jvm.gc.trigger = 4096;
+
+        let cls = jvm.get_class("Mem").unwrap();
+        let method = cls.get_method("garbage(I)V").unwrap();
+        let mut th = JThreadContext::default();
+        jvm.call_method(&mut th, cls, method, &[JValue::Int(2000)])
+            .unwrap();
+        jvm.spawn(th);
+        let budget = Budget {
+            instructions: Some(500),
+            gc_time: Some(std::time::Duration::from_micros(200)),
+            ..Default::default()
+        };
+        let mut peak = 0;
+        loop {
+            match jvm.run_for(budget).unwrap() {
+                SchedulerState::Yielded => peak = peak.max(jvm.heap.used()),
+                res => break assert_eq!(res, SchedulerState::Terminated),
+            }
+        }
+        assert!(jvm.gc.cycles > 0);
+        // 2000 objects of 16 bytes without collection
+        assert!(peak < 2000 * 16);
+    }
+}
@@ -0,0 +1,576 @@
+//! Object heap.
+
+use super::*;
+
+use array::JArray;
+use monitor::Monitor;
+use stack::SlotValue;
+use std::num::NonZeroU32;
+
+/// Reference to an object in [Heap]. (non-null)
+///
+/// A reference is stored in a u32 slot as its handle, and null is 0.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct JRef(NonZeroU32);
+
+impl JRef {
+    pub fn from_slot(v: u32) -> Option<Self> {
+        NonZeroU32::new(v).map(Self)
+    }
+
+    pub fn to_slot(r: Option<Self>) -> u32 {
+        r.map_or(0, |r| r.0.get())
+    }
+
+    pub(super) fn index(self) -> usize {
+        self.0.get() as usize
+    }
+}
+
+/// A reference or null.
+impl stack::SlotValue for Option<JRef> {
+    const SLOTS: u32 = 1;
+    const IS_REF: bool = true;
+
+    fn to_slots(self, dst: &mut [u32]) {
+        dst[0] = JRef::to_slot(self);
+    }
+    fn from_slots(src: &[u32]) -> Self {
+        JRef::from_slot(src[0])
+    }
+}
+
+#[derive(Debug)]
+pub struct JObject {
+    pub class: Rc<JClass>,
+    /// Instance fields. (layout is [ClassLayout])
+    pub fields: Box<[u32]>,
+    /// Elements if the object is an array.
+    pub array: Option<JArray>,
+    /// Identity hash, kept in the header once assigned so that it does not
+    /// depend on where the object is. (0 if not assigned yet)
+    hash: i32,
+    /// Inflated on the first synchronization on the object.
+    pub monitor: Option<Box<Monitor>>,
+}
+
+impl JObject {
+    pub fn new(class: Rc<JClass>, fields: Box<[u32]>, array: Option<JArray>) -> Self {
+        Self {
+            class,
+            fields,
+            array,
+            hash: 0,
+            monitor: None,
+        }
+    }
+
+    /// Read the instance field at the slot. (getfield)
+    pub fn get_field(&self, slot: u32, jtype: &JType) -> JValue {
+        let src = &self.fields[slot as usize..];
+        if jtype.is_reference() {
+            return JValue::from_ref(SlotValue::from_slots(src));
+        }
+        match jtype.ctype {
+            JComponentType::Long => JValue::Long(SlotValue::from_slots(src)),
+            JComponentType::Float => JValue::Float(SlotValue::from_slots(src)),
+            JComponentType::Double => JValue::Double(SlotValue::from_slots(src)),
+            _ => JValue::Int(SlotValue::from_slots(src)),
+        }
+    }
+
+    /// Approximate size in bytes for the heap statistics.
+    pub fn size(&self) -> usize {
+        let elements = self.array.as_ref().map_or(0, JArray::byte_size);
+        Self::HEADER_SIZE + self.fields.len() * 4 + elements
+    }
+
+    /// Write the instance field at the slot. (putfield)
+    pub fn set_field(&mut self, slot: u32, v: JValue) {
+        let dst = &mut self.fields[slot as usize..];
+        match v {
+            JValue::Int(x) => x.to_slots(dst),
+            JValue::Long(x) => x.to_slots(dst),
+            JValue::Float(x) => x.to_slots(dst),
+            JValue::Double(x) => x.to_slots(dst),
+            JValue::Null => None::<JRef>.to_slots(dst),
+            JValue::Ref(r) => Some(r).to_slots(dst),
+            JValue::Invalid => panic!("invalid value"),
+        }
+    }
+
+    /// The elements of an array. Panics if it is not an array.
+    pub fn elements(&self) -> &JArray {
+        self.array.as_ref().expect("not an array")
+    }
+
+    pub fn elements_mut(&mut self) -> &mut JArray {
+        self.array.as_mut().expect("not an array")
+    }
+}
+
+impl JObject {
+    /// Class pointer and identity, as a typical JVM header.
+    pub(super) const HEADER_SIZE: usize = 16;
+}
+
+/// Objects never move, and a handle freed by the GC is reused by a later
+/// allocation.
+pub struct Heap {
+    /// Indexed by handle. (objects[0] is null and always None)
+    objects: Vec<Option<JObject>>,
+    /// Freed handles to reuse.
+    free: Vec<u32>,
+    /// Bytes of the allocated objects. (live or not yet collected)
+    used: usize,
+    /// Bytes reserved for objects. It grows when `used` exceeds it, up to
+    /// `max`.
+    total: usize,
+    /// The heap limit. (Runtime.maxMemory)
+    max: usize,
+    /// Mark bits while the GC is marking or sweeping. (indexed by handle)
+    /// An object allocated meanwhile is marked.
+    marks: Option<Vec<bool>>,
+    /// xorshift state for identity hashes.
+    hash_seed: u32,
+}
+
+impl Heap {
+    pub const INITIAL_SIZE: usize = 1 << 20;
+
+    pub fn new(max: usize) -> Self {
+        Self {
+            objects: vec![None],
+            free: Vec::new(),
+            used: 0,
+            total: Self::INITIAL_SIZE.min(max),
+            max,
+            marks: None,
+            hash_seed: 0x2545_f491,
+        }
+    }
+
+    /// Add the object regardless of the limit. (see [JVM::alloc_object])
+    pub fn alloc(&mut self, obj: JObject) -> JRef {
+        self.used += obj.size();
+        if self.used > self.total {
+            // doubled, but not beyond the limit unless already over it
+            self.total = self.used.next_power_of_two().min(self.max.max(self.used));
+        }
+        let idx = match self.free.pop() {
+            Some(idx) => {
+                self.objects[idx as usize] = Some(obj);
+                idx
+            }
+            None => {
+                self.objects.push(Some(obj));
+                (self.objects.len() - 1) as u32
+            }
+        };
+        if let Some(marks) = &mut self.marks {
+            marks.resize(self.objects.len(), false);
+            marks[idx as usize] = true;
+        }
+        JRef::from_slot(idx).expect("handle must not be 0")
+    }
+
+    /// Free the object. The handle must not be used any more.
+    pub fn free(&mut self, r: JRef) {
+        let obj = self.objects[r.index()].take().expect("double free");
+        self.used -= obj.size();
+        self.free.push(r.index() as u32);
+    }
+
+    /// Number of handles, including freed ones and null.
+    pub fn capacity(&self) -> usize {
+        self.objects.len()
+    }
+
+    /// Whether the handle refers to an object which is not freed.
+    #[cfg(test)]
+    pub fn contains(&self, r: JRef) -> bool {
+        self.objects.get(r.index()).is_some_and(Option::is_some)
+    }
+
+    /// Clear the mark bits to start a GC cycle.
+    pub fn start_marking(&mut self) {
+        self.marks = Some(vec![false; self.objects.len()]);
+    }
+
+    pub fn finish_marking(&mut self) {
+        self.marks = None;
+    }
+
+    /// Mark the object and add it to `gray` if it is not marked yet.
+    pub fn shade(&mut self, r: JRef, gray: &mut Vec<JRef>) {
+        let marks = self.marks.as_mut().expect("not marking");
+        if !marks[r.index()] {
+            marks[r.index()] = true;
+            gray.push(r);
+        }
+    }
+
+    /// Free the object at the index if it is not marked.
+    /// Returns the handle if freed.
+    pub fn sweep(&mut self, idx: usize) -> Option<JRef> {
+        let marks = self.marks.as_ref().expect("not marking");
+        if marks[idx] || self.objects[idx].is_none() {
+            return None;
+        }
+        let r = JRef::from_slot(idx as u32)?;
+        self.free(r);
+        Some(r)
+    }
+
+    /// Bytes of the allocated objects. (Runtime.totalMemory - freeMemory)
+    pub fn used(&self) -> usize {
+        self.used
+    }
+
+    /// Bytes reserved for objects. (Runtime.totalMemory)
+    pub fn total(&self) -> usize {
+        self.total
+    }
+
+    /// The heap limit. (Runtime.maxMemory)
+    pub fn max(&self) -> usize {
+        self.max
+    }
+
+    /// The identity hash of the object, assigned on first use.
+    /// (Object.hashCode, System.identityHashCode)
+    ///
+    /// A random positive value, as HotSpot does, so hash tables do not
+    /// depend on the allocation order.
+    pub fn identity_hash(&mut self, r: JRef) -> i32 {
+        if self.get(r).hash == 0 {
+            let mut hash = 0;
+            while hash == 0 {
+                let mut x = self.hash_seed;
+                x ^= x << 13;
+                x ^= x >> 17;
+                x ^= x << 5;
+                self.hash_seed = x;
+                hash = (x & 0x7fff_ffff) as i32;
+            }
+            self.get_mut(r).hash = hash;
+        }
+        self.get(r).hash
+    }
+
+    /// All objects which are not freed.
+    pub fn iter(&self) -> impl Iterator<Item = (JRef, &JObject)> {
+        self.objects
+            .iter()
+            .enumerate()
+            .filter_map(|(i, obj)| Some((JRef::from_slot(i as u32)?, obj.as_ref()?)))
+    }
+
+    pub fn get(&self, r: JRef) -> &JObject {
+        self.objects[r.index()]
+            .as_ref()
+            .expect("dangling reference")
+    }
+
+    pub fn get_mut(&mut self, r: JRef) -> &mut JObject {
+        self.objects[r.index()]
+            .as_mut()
+            .expect("dangling reference")
+    }
+}
+
+impl JVM {
+    /// Allocate an instance of the class with all fields zero
+    /// (0, 0.0, false or null), without running any constructor.
+    ///
+    /// OutOfMemoryError if the heap is full. No GC runs here, since the
+    /// caller may hold references the GC cannot see.
+    /// (see [JVM::alloc_or_collect])
+    pub fn alloc_object(&mut self, clsname: &str) -> anyhow::Result<JRef> {
+        let obj = self.new_object(clsname)?;
+        self.check_heap_limit(obj.size())?;
+
+        Ok(self.heap.alloc(obj))
+    }
+
+    /// An instance of the class with all fields zero, not in the heap yet.
+    pub(super) fn new_object(&mut self, clsname: &str) -> anyhow::Result<JObject> {
+        let class = self.get_class(clsname)?;
+        throw_unless!(
+            class.access_flags & (acc_class::INTERFACE | acc_class::ABSTRACT) == 0,
+            InstantiationError,
+            "{clsname}"
+        );
+        let layout = self.link_class(clsname)?;
+        let fields = vec![0; layout.slot_count as usize].into_boxed_slice();
+
+        Ok(JObject::new(class, fields, None))
+    }
+
+    /// A shallow copy of the object with a new identity. (Object.clone)
+    /// CloneNotSupportedException unless it is an array or Cloneable.
+    /// Garbage is collected if the heap is full, as [JVM::alloc_or_collect].
+    pub fn clone_object(&mut self, th: &JThreadContext, r: JRef) -> anyhow::Result<JRef> {
+        let class = Rc::clone(&self.heap.get(r).class);
+        throw_unless!(
+            self.instance_of(&class, "java/lang/Cloneable")?,
+            CloneNotSupportedException,
+            "{}",
+            class.this_class.replace('/', ".")
+        );
+        let size = self.heap.get(r).size();
+        // the original may be only in the arguments of a native method
+        self.with_roots(&[r], |jvm| {
+            jvm.alloc_or_collect(th, |jvm| jvm.check_heap_limit(size))
+        })?;
+        let obj = self.heap.get(r);
+        let copy = JObject::new(class, obj.fields.clone(), obj.array.clone());
+
+        Ok(self.heap.alloc(copy))
+    }
+
+    /// OutOfMemoryError unless an object of the size fits in the limits.
+    pub(super) fn check_heap_limit(&self, size: usize) -> anyhow::Result<()> {
+        throw_unless!(
+            size <= self.config.max_object_size && self.heap.used() + size <= self.config.max_heap,
+            OutOfMemoryError,
+            "{}",
+            Self::HEAP_SPACE
+        );
+
+        Ok(())
+    }
+
+    const HEAP_SPACE: &str = "Java heap space";
+
+    /// Run the allocation, and if the heap is full, collect garbage and run
+    /// it again. A failed allocation must leave nothing behind but garbage,
+    /// and all references live in the caller must be on the stack of `th`.
+    pub(super) fn alloc_or_collect<T>(
+        &mut self,
+        th: &JThreadContext,
+        mut alloc: impl FnMut(&mut Self) -> anyhow::Result<T>,
+    ) -> anyhow::Result<T> {
+        match alloc(self) {
+            Err(err) if Self::is_heap_full(&err) => {
+                self.gc(th);
+                alloc(self)
+            }
+            res => res,
+        }
+    }
+
+    /// Run `f` keeping the objects alive as global references, for
+    /// references a collection in it cannot find on the stack of the thread.
+    /// (such as the popped arguments of a native method)
+    pub(super) fn with_roots<T>(
+        &mut self,
+        roots: &[JRef],
+        f: impl FnOnce(&mut Self) -> anyhow::Result<T>,
+    ) -> anyhow::Result<T> {
+        for &r in roots {
+            self.new_global_ref(r);
+        }
+        let res = f(self);
+        for &r in roots {
+            self.delete_global_ref(r);
+        }
+        res
+    }
+
+    fn is_heap_full(err: &anyhow::Error) -> bool {
+        matches!(
+            err.downcast_ref::<exception::Throw>(),
+            Some(exception::Throw::New { class, message: Some(message) })
+                if class == "java/lang/OutOfMemoryError" && message == Self::HEAP_SPACE
+        )
+    }
+
+    /// Read an instance field of the object from the host.
+    /// The field is resolved from the class of the object.
+    pub fn get_field(&mut self, obj: JRef, name: &str, descriptor: &str) -> anyhow::Result<JValue> {
+        let (field, slot) = self.field_slot(obj, name, descriptor)?;
+        Ok(self.heap.get(obj).get_field(slot, &field.jtype))
+    }
+
+    /// Write an instance field of the object from the host.
+    /// boolean, byte, char and short values are narrowed to the field type.
+    pub fn put_field(
+        &mut self,
+        obj: JRef,
+        name: &str,
+        descriptor: &str,
+        v: JValue,
+    ) -> anyhow::Result<()> {
+        let (field, slot) = self.field_slot(obj, name, descriptor)?;
+        if field.jtype.is_reference() {
+            let old = JRef::from_slot(self.heap.get(obj).fields[slot as usize]);
+            self.write_barrier(old);
+        }
+        self.heap
+            .get_mut(obj)
+            .set_field(slot, field.jtype.narrow(v));
+        Ok(())
+    }
+
+    fn field_slot(
+        &mut self,
+        obj: JRef,
+        name: &str,
+        descriptor: &str,
+    ) -> anyhow::Result<(Rc<FieldInfo>, u32)> {
+        let clsname = Rc::clone(&self.heap.get(obj).class.this_class);
+        let (decl, field) = self.resolve_field(&clsname, name, descriptor)?;
+        throw_unless!(
+            field.access_flags & acc_field::STATIC == 0,
+            IncompatibleClassChangeError,
+            "{}.{name} is static",
+            decl.this_class
+        );
+        let slot = self.link_class(&decl.this_class)?.slots[&field.name_desc];
+
+        Ok((field, slot))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_new() {
+        let mut jvm = test_util::setup();
+        let call =
+            |jvm: &mut JVM, name, args: &[JValue]| test_util::call(jvm, "Linked", name, args);
+
+        let JValue::Ref(head) = call(&mut jvm, "build", &[JValue::Int(4)]) else {
+            panic!();
+        };
+        assert_eq!(jvm.heap.get(head).class.this_class.as_str(), "Node");
+        assert_eq!(call(&mut jvm, "sum", &[JValue::Ref(head)]), JValue::Int(10));
+        assert_eq!(call(&mut jvm, "sum", &[JValue::Null]), JValue::Int(0));
+        assert_eq!(call(&mut jvm, "created", &[]), JValue::Int(4));
+
+        // fields from the host
+        assert_eq!(jvm.get_field(head, "value", "I").unwrap(), JValue::Int(4));
+        let JValue::Ref(next) = jvm.get_field(head, "next", "LNode;").unwrap() else {
+            panic!();
+        };
+        jvm.put_field(next, "next", "LNode;", JValue::Null).unwrap();
+        jvm.put_field(next, "value", "I", JValue::Int(10)).unwrap();
+        assert_eq!(call(&mut jvm, "sum", &[JValue::Ref(head)]), JValue::Int(14));
+
+        let JValue::Ref(a) = call(&mut jvm, "make", &[]) else {
+            panic!();
+        };
+        let JValue::Ref(b) = call(&mut jvm, "make", &[]) else {
+            panic!();
+        };
+        assert_ne!(a, b);
+        assert_eq!(
+            jvm.heap.get(a).class.this_class.as_str(),
+            "java/lang/Object"
+        );
+    }
+
+    #[test]
+    fn test_heap_limit() {
+        let mut jvm = test_util::setup_with_config(JVMConfig {
+            max_heap: 256 << 10,
+            max_object_size: 64 << 10,
+        });
+        let call = |jvm: &mut JVM, name, args: &[JValue]| match test_util::invoke(
+            jvm, "Mem", name, args,
+        ) {
+            InvokeResult::Return(v) => v,
+            res => panic!("{res:?}"),
+        };
+
+        assert_eq!(call(&mut jvm, "max", &[]), Some(JValue::Long(256 << 10)));
+        // garbage is collected when the heap is full
+        call(&mut jvm, "garbage", &[JValue::Int(100_000)]);
+        assert!(jvm.heap.total() <= 256 << 10);
+
+        // OutOfMemoryError is thrown into Java, and the heap recovers after
+        // the references are dropped
+        let Some(JValue::Int(n)) = call(&mut jvm, "exhaust", &[]) else {
+            panic!();
+        };
+        assert!(n > 100);
+        assert_eq!(call(&mut jvm, "exhaust", &[]), Some(JValue::Int(n)));
+
+        // the elements are never allocated on the host
+        let res = call(&mut jvm, "huge", &[JValue::Int(i32::MAX)]);
+        assert_eq!(res, Some(JValue::Int(-1)));
+        let res = call(&mut jvm, "huge", &[JValue::Int(1000)]);
+        assert_eq!(res, Some(JValue::Int(1000)));
+
+        // from the host, without GC
+        let err = jvm.alloc_array("[J", 1 << 20).unwrap_err();
+        assert!(err.to_string().starts_with("java.lang.OutOfMemoryError"));
+        assert!(err.to_string().contains("Requested array size"), "{err}");
+        let err = jvm.check_heap_limit((64 << 10) + 1).unwrap_err();
+        assert!(err.to_string().contains("Java heap space"), "{err}");
+
+        // clone collects garbage when the heap is full
+        let mut th = JThreadContext::default();
+        jvm.gc(&th);
+        let array = jvm.alloc_array("[I", 12 << 10).unwrap();
+        while jvm.alloc_array("[I", 12 << 10).is_ok() {}
+        let res = jvm.call_method_by_name(&mut th, "Header", "copyArray", &[JValue::Ref(array)]);
+        assert!(
+            matches!(res, Ok(InvokeResult::Return(Some(JValue::Ref(_))))),
+            "{res:?}"
+        );
+    }
+
+    #[test]
+    fn test_object_header() {
+        let mut jvm = test_util::setup();
+        let call =
+            |jvm: &mut JVM, name, args: &[JValue]| test_util::call(jvm, "Header", name, args);
+
+        // identity hash
+        let a = jvm.alloc_object("java/lang/Object").unwrap();
+        let b = jvm.alloc_object("java/lang/Object").unwrap();
+        let this = [JValue::Ref(a)];
+        let JValue::Int(hash) = call(&mut jvm, "hash", &this) else {
+            panic!();
+        };
+        assert!(hash > 0);
+        assert_eq!(call(&mut jvm, "identity", &this), JValue::Int(hash));
+        let b_hash = jvm.heap.identity_hash(b);
+        assert_ne!(b_hash, hash);
+        jvm.new_global_ref(a);
+        jvm.gc(&JThreadContext::default());
+        assert_eq!(call(&mut jvm, "hash", &this), JValue::Int(hash));
+        assert_eq!(call(&mut jvm, "identity", &[JValue::Null]), JValue::Int(0));
+        // b is freed, and a new object reusing its handle gets a new hash
+        assert!(!jvm.heap.contains(b));
+        let c = std::iter::repeat_with(|| jvm.alloc_object("java/lang/Object").unwrap())
+            .find(|&c| c == b)
+            .unwrap();
+        assert_ne!(jvm.heap.identity_hash(c), b_hash);
+
+        // clone
+        assert_eq!(call(&mut jvm, "copy", &[]), JValue::Int(1));
+        assert_eq!(call(&mut jvm, "notCloneable", &[]), JValue::Int(1));
+        let array = jvm.alloc_array("[I", 3).unwrap();
+        jvm.heap
+            .get_mut(array)
+            .elements_mut()
+            .set(2, JValue::Int(7))
+            .unwrap();
+        let JValue::Ref(copy) = call(&mut jvm, "copyArray", &[JValue::Ref(array)]) else {
+            panic!();
+        };
+        assert_ne!(copy, array);
+        assert_eq!(jvm.heap.get(copy).elements().get(2), JValue::Int(7));
+
+        // monitors are inflated on first use
+        let obj = jvm.alloc_object("java/lang/Object").unwrap();
+        assert!(jvm.heap.get(obj).monitor.is_none());
+        jvm.monitor(obj).count = 1;
+        assert_eq!(jvm.heap.get(obj).monitor.as_ref().unwrap().count, 1);
+    }
+}
@@ -0,0 +1,857 @@
+//! Heap statistics and HPROF heap dumps.
+//!
+//! [JVM::dump_hprof] writes the HPROF binary format ("JAVA PROFILE 1.0.2")
+//! in the way HotSpot does, so the dump opens in heap analyzers such as
+//! Eclipse MAT and VisualVM.
+//!
+//! Identifiers are 8 bytes. An object is identified by its handle, and a
+//! class by its java/lang/Class instance if it has one, or by a number above
+//! all handles otherwise. Strings of the UTF8 records have their own range.
+//!
+//! ```text
+//! "JAVA PROFILE 1.0.2\0"
+//! u4 identifier size
+//! u8 timestamp (milliseconds)
+//! record {
+//!     u1 tag;
+//!     u4 time (microseconds since the timestamp)
+//!     u4 length;
+//!     u1 body[length];
+//! }*
+//! ```
+
+use super::*;
+
+use anyhow::Context;
+use array::JArray;
+use bytes::BufMut;
+use std::collections::HashSet;
+use std::path::Path;
+
+/// Instances and bytes of a class in the heap. ([JVM::heap_histogram])
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HistogramEntry {
+    pub class: Rc<String>,
+    pub instances: usize,
+    pub bytes: usize,
+}
+
+mod tag {
+    pub const UTF8: u8 = 0x01;
+    pub const LOAD_CLASS: u8 = 0x02;
+    pub const FRAME: u8 = 0x04;
+    pub const TRACE: u8 = 0x05;
+    pub const HEAP_DUMP_SEGMENT: u8 = 0x1c;
+    pub const HEAP_DUMP_END: u8 = 0x2c;
+}
+
+/// Sub-records of a heap dump segment.
+mod sub {
+    pub const ROOT_UNKNOWN: u8 = 0xff;
+    pub const ROOT_JNI_GLOBAL: u8 = 0x01;
+    pub const ROOT_JAVA_FRAME: u8 = 0x03;
+    pub const ROOT_MONITOR_USED: u8 = 0x07;
+    pub const ROOT_STICKY_CLASS: u8 = 0x05;
+    pub const ROOT_THREAD_OBJECT: u8 = 0x08;
+    pub const CLASS_DUMP: u8 = 0x20;
+    pub const INSTANCE_DUMP: u8 = 0x21;
+    pub const OBJECT_ARRAY_DUMP: u8 = 0x22;
+    pub const PRIMITIVE_ARRAY_DUMP: u8 = 0x23;
+}
+
+/// Types of field values and array elements.
+mod basic {
+    pub const OBJECT: u8 = 2;
+    pub const BOOLEAN: u8 = 4;
+    pub const CHAR: u8 = 5;
+    pub const FLOAT: u8 = 6;
+    pub const DOUBLE: u8 = 7;
+    pub const BYTE: u8 = 8;
+    pub const SHORT: u8 = 9;
+    pub const INT: u8 = 10;
+    pub const LONG: u8 = 11;
+}
+
+const ID_SIZE: u32 = 8;
+const CLASS_ID_BASE: u64 = 1 << 32;
+const STRING_ID_BASE: u64 = 2 << 32;
+/// The empty stack trace of objects and classes.
+const NO_TRACE: u32 = 1;
+/// Stack trace of a thread is this plus the thread serial. Threads are
+/// numbered from 1 in the order of [JVM::dump_threads], as the IDs of a host
+/// context and a thread owned by the VM may be the same.
+const THREAD_TRACE_BASE: u32 = NO_TRACE;
+/// A segment is closed when it grows beyond this. (the length is a u4)
+const SEGMENT_SIZE: usize = 1 << 30;
+
+#[derive(Default)]
+struct HprofWriter {
+    out: Vec<u8>,
+    /// IDs of the strings written as UTF8 records.
+    strings: HashMap<String, u64>,
+    /// Sub-records of the current heap dump segment.
+    segment: Vec<u8>,
+}
+
+impl HprofWriter {
+    fn record(&mut self, tag: u8, body: &[u8]) {
+        self.out.put_u8(tag);
+        self.out.put_u32(0);
+        self.out.put_u32(body.len() as u32);
+        self.out.put_slice(body);
+    }
+
+    /// The ID of the string, written as a UTF8 record on first use.
+    fn string(&mut self, s: &str) -> u64 {
+        if let Some(&id) = self.strings.get(s) {
+            return id;
+        }
+        let id = STRING_ID_BASE + self.strings.len() as u64 + 1;
+        let mut body = Vec::with_capacity(8 + s.len());
+        body.put_u64(id);
+        body.put_slice(s.as_bytes());
+        self.record(tag::UTF8, &body);
+        self.strings.insert(s.to_string(), id);
+        id
+    }
+
+    /// Called after each sub-record.
+    fn end_sub_record(&mut self) {
+        if self.segment.len() >= SEGMENT_SIZE {
+            self.flush_segment();
+        }
+    }
+
+    fn flush_segment(&mut self) {
+        if !self.segment.is_empty() {
+            let segment = std::mem::take(&mut self.segment);
+            self.record(tag::HEAP_DUMP_SEGMENT, &segment);
+        }
+    }
+}
+
+/// A class in the dump.
+struct DumpClass {
+    id: u64,
+    class: Rc<JClass>,
+    layout: Rc<ClassLayout>,
+    /// Instance fields declared in the class, in slot order.
+    fields: Vec<(Rc<FieldInfo>, u32)>,
+}
+
+fn basic_type(jtype: &JType) -> u8 {
+    if jtype.is_reference() {
+        return basic::OBJECT;
+    }
+    match jtype.ctype {
+        JComponentType::Boolean => basic::BOOLEAN,
+        JComponentType::Byte => basic::BYTE,
+        JComponentType::Char => basic::CHAR,
+        JComponentType::Short => basic::SHORT,
+        JComponentType::Int => basic::INT,
+        JComponentType::Long => basic::LONG,
+        JComponentType::Float => basic::FLOAT,
+        JComponentType::Double => basic::DOUBLE,
+        JComponentType::Object(_) => basic::OBJECT,
+    }
+}
+
+fn object_id(r: Option<JRef>) -> u64 {
+    r.map_or(0, |r| r.index() as u64)
+}
+
+fn put_value(buf: &mut Vec<u8>, ty: u8, v: &JValue) {
+    match *v {
+        JValue::Int(x) => match ty {
+            basic::BOOLEAN | basic::BYTE => buf.put_u8(x as u8),
+            basic::CHAR | basic::SHORT => buf.put_u16(x as u16),
+            _ => buf.put_i32(x),
+        },
+        JValue::Long(x) => buf.put_i64(x),
+        JValue::Float(x) => buf.put_f32(x),
+        JValue::Double(x) => buf.put_f64(x),
+        JValue::Ref(r) => buf.put_u64(object_id(Some(r))),
+        JValue::Null | JValue::Invalid => buf.put_u64(0),
+    }
+}
+
+impl JVM {
+    /// Instances and bytes per class, the largest first.
+    pub fn heap_histogram(&self) -> Vec<HistogramEntry> {
+        let mut by_class: HashMap<&Rc<String>, (usize, usize)> = HashMap::new();
+        for (_, obj) in self.heap.iter() {
+            let entry = by_class.entry(&obj.class.this_class).or_default();
+            entry.0 += 1;
+            entry.1 += obj.size();
+        }
+        let mut entries: Vec<_> = by_class
+            .into_iter()
+            .map(|(class, (instances, bytes))| HistogramEntry {
+                class: Rc::clone(class),
+                instances,
+                bytes,
+            })
+            .collect();
+        entries.sort_by(|a, b| b.bytes.cmp(&a.bytes).then_with(|| a.class.cmp(&b.class)));
+
+        entries
+    }
+
+    /// Write the heap, the GC roots and the stacks of the threads to the
+    /// file in the HPROF binary format. `th` is the running thread, and the
+    /// threads owned by the VM follow.
+    pub fn dump_hprof(
+        &mut self,
+        th: &JThreadContext,
+        path: impl AsRef<Path>,
+    ) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        let bytes = self.hprof(th);
+        std::fs::write(path, bytes).with_context(|| format!("cannot write {}", path.display()))
+    }
+
+    fn hprof(&mut self, th: &JThreadContext) -> Vec<u8> {
+        let mut w = HprofWriter::default();
+        w.out.put_slice(b"JAVA PROFILE 1.0.2\0");
+        w.out.put_u32(ID_SIZE);
+        let now = self.clock.now();
+        w.out.put_u64(now.as_millis() as u64);
+
+        let classes = self.dump_classes();
+        let class_ids: HashMap<&str, u64> = classes
+            .iter()
+            .map(|c| (c.class.this_class.as_str(), c.id))
+            .collect();
+
+        // LOAD CLASS (serial = index + 1)
+        for (i, c) in classes.iter().enumerate() {
+            let name = w.string(&c.class.this_class);
+            let mut body = Vec::new();
+            body.put_u32(i as u32 + 1);
+            body.put_u64(c.id);
+            body.put_u32(NO_TRACE);
+            body.put_u64(name);
+            w.record(tag::LOAD_CLASS, &body);
+        }
+
+        self.write_traces(&mut w, th, &classes);
+        self.write_roots(&mut w, th, &classes);
+        for c in classes.iter() {
+            self.write_class_dump(&mut w, c, &class_ids);
+        }
+        let class_objects: HashSet<u64> = class_ids.values().copied().collect();
+        for (r, obj) in self.heap.iter() {
+            let id = object_id(Some(r));
+            if class_objects.contains(&id) {
+                continue;
+            }
+            let class_id = class_ids[obj.class.this_class.as_str()];
+            match &obj.array {
+                Some(array) => write_array_dump(&mut w.segment, id, class_id, array),
+                None => self.write_instance_dump(&mut w.segment, id, class_id, obj, &classes),
+            }
+            w.end_sub_record();
+        }
+        w.flush_segment();
+        w.record(tag::HEAP_DUMP_END, &[]);
+
+        w.out
+    }
+
+    /// All linked classes, sorted by name.
+    /// Linking only computes the layouts, and a class that cannot be linked
+    /// has no instances.
+    fn dump_classes(&mut self) -> Vec<DumpClass> {
+        let mut names: Vec<Rc<String>> = self
+            .classes
+            .values()
+            .map(|c| Rc::clone(&c.this_class))
+            .collect();
+        names.sort();
+
+        let mut classes = Vec::with_capacity(names.len());
+        for name in names {
+            let Ok(layout) = self.link_class(&name) else {
+                continue;
+            };
+            let class = Rc::clone(&self.classes[name.as_str()]);
+            let id = match self.class_objects.get(name.as_str()) {
+                Some(&r) => object_id(Some(r)),
+                None => CLASS_ID_BASE + classes.len() as u64 + 1,
+            };
+            let mut fields: Vec<_> = class
+                .fields
+                .values()
+                .filter(|f| f.access_flags & acc_field::STATIC == 0)
+                .map(|f| (Rc::clone(f), layout.slots[&f.name_desc]))
+                .collect();
+            fields.sort_by_key(|&(_, slot)| slot);
+            classes.push(DumpClass {
+                id,
+                class,
+                layout,
+                fields,
+            });
+        }
+
+        classes
+    }
+
+    /// The running thread and the threads owned by the VM, with their
+    /// serials.
+    fn dump_threads<'a>(
+        &'a self,
+        th: &'a JThreadContext,
+    ) -> impl Iterator<Item = (u32, &'a JThreadContext)> {
+        std::iter::once(th)
+            .chain(self.threads.iter().map(|t| &t.ctx))
+            .enumerate()
+            .map(|(i, th)| (i as u32 + 1, th))
+    }
+
+    /// The empty trace of objects, and the frames of each thread as a trace.
+    fn write_traces(&self, w: &mut HprofWriter, th: &JThreadContext, classes: &[DumpClass]) {
+        let mut body = Vec::new();
+        body.put_u32(NO_TRACE);
+        body.put_u32(0);
+        body.put_u32(0);
+        w.record(tag::TRACE, &body);
+
+        let serials: HashMap<&str, u32> = classes
+            .iter()
+            .enumerate()
+            .map(|(i, c)| (c.class.this_class.as_str(), i as u32 + 1))
+            .collect();
+        let source = w.string("Unknown Source");
+        let mut next_frame_id = 1;
+        for (serial, th) in self.dump_threads(th) {
+            self.write_trace(w, serial, th, &serials, source, &mut next_frame_id);
+        }
+    }
+
+    /// FRAME records of the thread, innermost first, and the TRACE.
+    fn write_trace(
+        &self,
+        w: &mut HprofWriter,
+        serial: u32,
+        th: &JThreadContext,
+        serials: &HashMap<&str, u32>,
+        source: u64,
+        next_frame_id: &mut u64,
+    ) {
+        let mut frame_ids = Vec::with_capacity(th.frames.len());
+        for frame in th.frames.iter().rev() {
+            let name = w.string(&frame.method.name);
+            let descriptor = w.string(&frame.method.descriptor);
+            let native = matches!(frame.method.method_body, MethodBody::Native(_));
+            let mut body = Vec::new();
+            body.put_u64(*next_frame_id);
+            body.put_u64(name);
+            body.put_u64(descriptor);
+            body.put_u64(source);
+            body.put_u32(
+                serials
+                    .get(frame.class.this_class.as_str())
+                    .copied()
+                    .unwrap_or(0),
+            );
+            // -3: native, -1: unknown line
+            body.put_i32(if native { -3 } else { -1 });
+            w.record(tag::FRAME, &body);
+            frame_ids.push(*next_frame_id);
+            *next_frame_id += 1;
+        }
+
+        let mut body = Vec::new();
+        body.put_u32(THREAD_TRACE_BASE + serial);
+        body.put_u32(serial);
+        body.put_u32(frame_ids.len() as u32);
+        for id in frame_ids {
+            body.put_u64(id);
+        }
+        w.record(tag::TRACE, &body);
+    }
+
+    /// The GC roots, the same as the collector uses.
+    fn write_roots(&self, w: &mut HprofWriter, th: &JThreadContext, classes: &[DumpClass]) {
+        // statics are reachable from the classes
+        for c in classes {
+            w.segment.put_u8(sub::ROOT_STICKY_CLASS);
+            w.segment.put_u64(c.id);
+            w.end_sub_record();
+        }
+        for (serial, th) in self.dump_threads(th) {
+            if let Some(object) = th.object {
+                w.segment.put_u8(sub::ROOT_THREAD_OBJECT);
+                w.segment.put_u64(object_id(Some(object)));
+                w.segment.put_u32(serial);
+                w.segment.put_u32(THREAD_TRACE_BASE + serial);
+                w.end_sub_record();
+            }
+            // frame number in the trace (innermost = 0)
+            for (i, frame) in th.frames.iter().rev().enumerate() {
+                let start = frame.range.start as usize;
+                let end = (frame.range.start + frame.sp) as usize;
+                for idx in (start..end).filter(|&idx| th.refs[idx]) {
+                    let Some(r) = JRef::from_slot(th.stack[idx]) else {
+                        continue;
+                    };
+                    w.segment.put_u8(sub::ROOT_JAVA_FRAME);
+                    w.segment.put_u64(object_id(Some(r)));
+                    w.segment.put_u32(serial);
+                    w.segment.put_u32(i as u32);
+                    w.end_sub_record();
+                }
+                if let Some(r) = frame.monitor {
+                    w.segment.put_u8(sub::ROOT_MONITOR_USED);
+                    w.segment.put_u64(object_id(Some(r)));
+                    w.end_sub_record();
+                }
+            }
+        }
+        for &r in self.global_refs.keys() {
+            w.segment.put_u8(sub::ROOT_JNI_GLOBAL);
+            w.segment.put_u64(object_id(Some(r)));
+            w.segment.put_u64(object_id(Some(r)));
+            w.end_sub_record();
+        }
+        // interned strings and Class instances are held by the VM
+        for &r in self.interned.values().chain(self.class_objects.values()) {
+            w.segment.put_u8(sub::ROOT_UNKNOWN);
+            w.segment.put_u64(object_id(Some(r)));
+            w.end_sub_record();
+        }
+    }
+
+    fn write_class_dump(&self, w: &mut HprofWriter, c: &DumpClass, class_ids: &HashMap<&str, u64>) {
+        let mut statics: Vec<_> = c
+            .class
+            .fields
+            .values()
+            .filter(|f| f.access_flags & acc_field::STATIC != 0)
+            .collect();
+        statics.sort_by(|a, b| a.name_desc.cmp(&b.name_desc));
+        let static_values = self
+            .class_rt
+            .get(c.class.this_class.as_str())
+            .map(|rt| &rt.static_fields);
+        let statics: Vec<_> = statics
+            .into_iter()
+            .map(|f| {
+                let v = static_values
+                    .and_then(|values| values.get(&f.name_desc))
+                    .cloned()
+                    .unwrap_or_else(|| f.jtype.to_default_value());
+                (w.string(&f.name), basic_type(&f.jtype), v)
+            })
+            .collect();
+        let fields: Vec<_> = c
+            .fields
+            .iter()
+            .map(|(f, _)| (w.string(&f.name), basic_type(&f.jtype)))
+            .collect();
+        let instance_size = if c.class.this_class.starts_with('[') {
+            0
+        } else {
+            heap::JObject::HEADER_SIZE + c.layout.slot_count as usize * 4
+        };
+
+        let buf = &mut w.segment;
+        buf.put_u8(sub::CLASS_DUMP);
+        buf.put_u64(c.id);
+        buf.put_u32(NO_TRACE);
+        let super_id = c
+            .class
+            .super_class
+            .as_ref()
+            .and_then(|s| class_ids.get(s.as_str()));
+        buf.put_u64(super_id.copied().unwrap_or(0));
+        // class loader, signers, protection domain and 2 reserved
+        for _ in 0..5 {
+            buf.put_u64(0);
+        }
+        buf.put_u32(instance_size as u32);
+        // constant pool
+        buf.put_u16(0);
+        buf.put_u16(statics.len() as u16);
+        for (name, ty, v) in statics {
+            buf.put_u64(name);
+            buf.put_u8(ty);
+            put_value(buf, ty, &v);
+        }
+        buf.put_u16(fields.len() as u16);
+        for (name, ty) in fields {
+            buf.put_u64(name);
+            buf.put_u8(ty);
+        }
+        w.end_sub_record();
+    }
+
+    /// Field values of the class first, then of its superclasses, each in
+    /// the order of the class dump.
+    fn write_instance_dump(
+        &self,
+        buf: &mut Vec<u8>,
+        id: u64,
+        class_id: u64,
+        obj: &heap::JObject,
+        classes: &[DumpClass],
+    ) {
+        let mut values = Vec::new();
+        let mut cur = Some(Rc::clone(&obj.class.this_class));
+        while let Some(name) = cur {
+            let Ok(i) = classes.binary_search_by(|c| c.class.this_class.cmp(&name)) else {
+                break;
+            };
+            for (field, slot) in classes[i].fields.iter() {
+                let v = obj.get_field(*slot, &field.jtype);
+                put_value(&mut values, basic_type(&field.jtype), &v);
+            }
+            cur = classes[i].class.super_class.clone();
+        }
+
+        buf.put_u8(sub::INSTANCE_DUMP);
+        buf.put_u64(id);
+        buf.put_u32(NO_TRACE);
+        buf.put_u64(class_id);
+        buf.put_u32(values.len() as u32);
+        buf.put_slice(&values);
+    }
+}
+
+fn write_array_dump(buf: &mut Vec<u8>, id: u64, class_id: u64, array: &JArray) {
+    if let JArray::Ref(elems) = array {
+        buf.put_u8(sub::OBJECT_ARRAY_DUMP);
+        buf.put_u64(id);
+        buf.put_u32(NO_TRACE);
+        buf.put_u32(elems.len() as u32);
+        buf.put_u64(class_id);
+        for &r in elems.iter() {
+            buf.put_u64(object_id(r));
+        }
+        return;
+    }
+
+    buf.put_u8(sub::PRIMITIVE_ARRAY_DUMP);
+    buf.put_u64(id);
+    buf.put_u32(NO_TRACE);
+    buf.put_u32(array.len() as u32);
+    match array {
+        JArray::Boolean(a) => {
+            buf.put_u8(basic::BOOLEAN);
+            a.iter().for_each(|&x| buf.put_u8(x as u8));
+        }
+        JArray::Byte(a) => {
+            buf.put_u8(basic::BYTE);
+            a.iter().for_each(|&x| buf.put_i8(x));
+        }
+        JArray::Char(a) => {
+            buf.put_u8(basic::CHAR);
+            a.iter().for_each(|&x| buf.put_u16(x));
+        }
+        JArray::Short(a) => {
+            buf.put_u8(basic::SHORT);
+            a.iter().for_each(|&x| buf.put_i16(x));
+        }
+        JArray::Int(a) => {
+            buf.put_u8(basic::INT);
+            a.iter().for_each(|&x| buf.put_i32(x));
+        }
+        JArray::Long(a) => {
+            buf.put_u8(basic::LONG);
+            a.iter().for_each(|&x| buf.put_i64(x));
+        }
+        JArray::Float(a) => {
+            buf.put_u8(basic::FLOAT);
+            a.iter().for_each(|&x| buf.put_f32(x));
+        }
+        JArray::Double(a) => {
+            buf.put_u8(basic::DOUBLE);
+            a.iter().for_each(|&x| buf.put_f64(x));
+        }
+        JArray::Ref(_) => unreachable!(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bytes::Buf;
+    use test_util::setup;
+
+    fn build_list(jvm: &mut JVM, n: i32) -> JRef {
+        match test_util::call(jvm, "Linked", "build", &[JValue::Int(n)]) {
+            JValue::Ref(r) => r,
+            res => panic!("{res:?}"),
+        }
+    }
+
+    #[test]
+    fn test_histogram() {
+        let mut jvm = setup();
+        let head = build_list(&mut jvm, 5);
+        jvm.new_global_ref(head);
+        jvm.alloc_array("[J", 100).unwrap();
+
+        let histogram = jvm.heap_histogram();
+        let node = histogram
+            .iter()
+            .find(|e| e.class.as_str() == "Node")
+            .unwrap();
+        assert_eq!(node.instances, 5);
+        assert_eq!(node.bytes, 5 * jvm.heap.get(head).size());
+        // the largest first
+        assert_eq!(histogram[0].class.as_str(), "[J");
+        assert!(histogram.windows(2).all(|w| w[0].bytes >= w[1].bytes));
+    }
+
+    /// Parsed sub-records of interest.
+    #[derive(Default)]
+    struct Dump {
+        strings: HashMap<u64, String>,
+        /// class id -> name
+        classes: HashMap<u64, String>,
+        /// class id -> (super id, instance field names and types)
+        class_dumps: HashMap<u64, (u64, Vec<(String, u8)>)>,
+        /// id -> (class id, values)
+        instances: HashMap<u64, (u64, Vec<u8>)>,
+        /// id -> (class id, elements)
+        object_arrays: HashMap<u64, (u64, Vec<u64>)>,
+        /// id -> (type, bytes)
+        primitive_arrays: HashMap<u64, (u8, Vec<u8>)>,
+        frame_roots: Vec<(u64, u32)>,
+        /// (thread object id, thread serial)
+        thread_roots: Vec<(u64, u32)>,
+        global_roots: Vec<u64>,
+        monitor_roots: Vec<u64>,
+        /// (trace serial, thread serial) of the traces of threads
+        traces: Vec<(u32, u32)>,
+        trace_frames: usize,
+        ended: bool,
+    }
+
+    fn value_size(ty: u8) -> usize {
+        match ty {
+            basic::OBJECT => 8,
+            basic::BOOLEAN | basic::BYTE => 1,
+            basic::CHAR | basic::SHORT => 2,
+            basic::FLOAT | basic::INT => 4,
+            _ => 8,
+        }
+    }
+
+    impl Dump {
+        /// Field values of an instance by name.
+        fn fields(&self, id: u64) -> HashMap<String, &[u8]> {
+            let (class_id, values) = &self.instances[&id];
+            let (mut class_id, mut values) = (*class_id, &values[..]);
+            let mut fields = HashMap::new();
+            while class_id != 0 {
+                let (super_id, class_fields) = &self.class_dumps[&class_id];
+                for (name, ty) in class_fields {
+                    let (v, rest) = values.split_at(value_size(*ty));
+                    fields.insert(name.clone(), v);
+                    values = rest;
+                }
+                class_id = *super_id;
+            }
+            assert!(values.is_empty());
+            fields
+        }
+    }
+
+    fn parse(mut p: &[u8]) -> Dump {
+        let mut dump = Dump::default();
+        let header = b"JAVA PROFILE 1.0.2\0";
+        assert_eq!(&p[..header.len()], header);
+        p.advance(header.len());
+        assert_eq!(p.get_u32(), 8);
+        p.get_u64();
+
+        while p.has_remaining() {
+            let tag = p.get_u8();
+            p.get_u32();
+            let len = p.get_u32() as usize;
+            let mut body = &p[..len];
+            p.advance(len);
+            match tag {
+                tag::UTF8 => {
+                    let id = body.get_u64();
+                    let s = String::from_utf8(body.to_vec()).unwrap();
+                    dump.strings.insert(id, s);
+                }
+                tag::LOAD_CLASS => {
+                    body.get_u32();
+                    let id = body.get_u64();
+                    body.get_u32();
+                    let name = &dump.strings[&body.get_u64()];
+                    dump.classes.insert(id, name.clone());
+                }
+                tag::FRAME => {}
+                tag::TRACE => {
+                    let serial = body.get_u32();
+                    let thread = body.get_u32();
+                    if thread != 0 {
+                        dump.traces.push((serial, thread));
+                    }
+                    dump.trace_frames += body.get_u32() as usize;
+                }
+                tag::HEAP_DUMP_SEGMENT => parse_segment(&mut dump, body),
+                tag::HEAP_DUMP_END => dump.ended = true,
+                _ => panic!("unknown tag {tag}"),
+            }
+        }
+
+        dump
+    }
+
+    fn parse_segment(dump: &mut Dump, mut p: &[u8]) {
+        while p.has_remaining() {
+            match p.get_u8() {
+                sub::ROOT_UNKNOWN | sub::ROOT_STICKY_CLASS => {
+                    p.get_u64();
+                }
+                sub::ROOT_MONITOR_USED => dump.monitor_roots.push(p.get_u64()),
+                sub::ROOT_JNI_GLOBAL => {
+                    dump.global_roots.push(p.get_u64());
+                    p.get_u64();
+                }
+                sub::ROOT_THREAD_OBJECT => {
+                    dump.thread_roots.push((p.get_u64(), p.get_u32()));
+                    p.get_u32();
+                }
+                sub::ROOT_JAVA_FRAME => {
+                    let id = p.get_u64();
+                    p.get_u32();
+                    dump.frame_roots.push((id, p.get_u32()));
+                }
+                sub::CLASS_DUMP => {
+                    let id = p.get_u64();
+                    p.get_u32();
+                    let super_id = p.get_u64();
+                    p.advance(8 * 5 + 4);
+                    assert_eq!(p.get_u16(), 0);
+                    for _ in 0..p.get_u16() {
+                        p.get_u64();
+                        let ty = p.get_u8();
+                        p.advance(value_size(ty));
+                    }
+                    let fields = (0..p.get_u16())
+                        .map(|_| (dump.strings[&p.get_u64()].clone(), p.get_u8()))
+                        .collect();
+                    dump.class_dumps.insert(id, (super_id, fields));
+                }
+                sub::INSTANCE_DUMP => {
+                    let id = p.get_u64();
+                    p.get_u32();
+                    let class_id = p.get_u64();
+                    let len = p.get_u32() as usize;
+                    dump.instances.insert(id, (class_id, p[..len].to_vec()));
+                    p.advance(len);
+                }
+                sub::OBJECT_ARRAY_DUMP => {
+                    let id = p.get_u64();
+                    p.get_u32();
+                    let len = p.get_u32();
+                    let class_id = p.get_u64();
+                    let elems = (0..len).map(|_| p.get_u64()).collect();
+                    dump.object_arrays.insert(id, (class_id, elems));
+                }
+                sub::PRIMITIVE_ARRAY_DUMP => {
+                    let id = p.get_u64();
+                    p.get_u32();
+                    let len = p.get_u32() as usize;
+                    let ty = p.get_u8();
+                    let bytes = p[..len * value_size(ty)].to_vec();
+                    p.advance(bytes.len());
+                    dump.primitive_arrays.insert(id, (ty, bytes));
+                }
+                t => panic!("unknown sub-record {t:#x}"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_hprof() {
+        let mut jvm = setup();
+        let head = build_list(&mut jvm, 3);
+        let array = jvm.alloc_array("[LNode;", 2).unwrap();
+        jvm.heap
+            .get_mut(array)
+            .elements_mut()
+            .set(1, JValue::Ref(head))
+            .unwrap();
+        jvm.new_global_ref(array);
+        let ints = jvm.alloc_array("[I", 2).unwrap();
+        jvm.heap
+            .get_mut(ints)
+            .elements_mut()
+            .set(0, JValue::Int(-2))
+            .unwrap();
+        // a frame holding the int array
+        let mut th = JThreadContext::default();
+        let cls = jvm.get_class("Linked").unwrap();
+        let method = cls.get_method("sum(LNode;)I").unwrap();
+        th.new_frame(cls, method).unwrap();
+        th.push(Some(ints));
+        let lock = jvm.alloc_object("java/lang/Object").unwrap();
+        th.current_frame().monitor = Some(lock);
+        // a thread owned by the VM with the same ID
+        jvm.spawn(JThreadContext::default());
+
+        let path = std::env::temp_dir().join(format!("vm-test-{}.hprof", std::process::id()));
+        jvm.dump_hprof(&th, &path).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let dump = parse(&bytes);
+        assert!(dump.ended);
+        assert_eq!(dump.trace_frames, 1);
+        // serials are unique
+        assert_eq!(
+            dump.traces,
+            [(THREAD_TRACE_BASE + 1, 1), (THREAD_TRACE_BASE + 2, 2)]
+        );
+
+        // every object is dumped with a dumped class
+        let class_id = |name: &str| {
+            *dump
+                .classes
+                .iter()
+                .find(|(_, n)| n.as_str() == name)
+                .unwrap()
+                .0
+        };
+        assert_eq!(
+            dump.instances.len() + dump.object_arrays.len() + dump.primitive_arrays.len(),
+            jvm.heap.iter().count()
+        );
+        for (class_id, _) in dump.instances.values() {
+            assert!(dump.class_dumps.contains_key(class_id));
+        }
+        let object = class_id("java/lang/Object");
+        let node = class_id("Node");
+        let (super_id, fields) = &dump.class_dumps[&node];
+        assert_eq!(*super_id, object);
+        assert_eq!(fields.len(), 2);
+
+        // head(3) -> 2 -> 1
+        let id = |r: JRef| r.index() as u64;
+        assert_eq!(dump.instances[&id(head)].0, node);
+        let fields = dump.fields(id(head));
+        assert_eq!(fields["value"], 3i32.to_be_bytes());
+        let next = u64::from_be_bytes(fields["next"].try_into().unwrap());
+        assert_eq!(dump.fields(next)["value"], 2i32.to_be_bytes());
+
+        // arrays and roots
+        assert_eq!(
+            dump.object_arrays[&id(array)],
+            (class_id("[LNode;"), vec![0, id(head)])
+        );
+        let (ty, elems) = &dump.primitive_arrays[&id(ints)];
+        assert_eq!(*ty, basic::INT);
+        assert_eq!(elems, &[0xff, 0xff, 0xff, 0xfe, 0, 0, 0, 0]);
+        assert_eq!(dump.global_roots, [id(array)]);
+        assert_eq!(dump.frame_roots, [(id(ints), 0)]);
+        assert_eq!(dump.monitor_roots, [id(lock)]);
+    }
+}
@@ -0,0 +1,821 @@
+//! Precompiled class image.
+//!
+//! A binary snapshot of parsed, structurally checked and pre-decoded classes
+//! for fast startup. Classes are parsed when the image is built, so the code of
+//! every method has passed the checks of [op::DecodedCode::decode], and the
+//! image holds the decoded ops. Loading an image skips class file parsing,
+//! constant pool resolution, bytecode decoding and the checks.
+//! All strings in the image are stored once in a symbol table and shared as
+//! the same [Rc] between classes after loading.
+//!
+//! The image is a single byte buffer, so it can be loaded from a file read
+//! at once or from a memory-mapped region.
+//!
+//! ```text
+//! Image {
+//!     u32 magic;
+//!     u16 format_version;
+//!     str vm_version;
+//!     u32 class_count;
+//!     u64 source_hash[class_count];
+//!     u32 symbol_count;
+//!     str symbols[symbol_count];
+//!     class classes[class_count];
+//! }
+//! str {
+//!     u32 length;
+//!     u1  bytes[length];
+//! }
+//! utf16 {         // the value of a CONSTANT_String
+//!     u32 length;
+//!     u16 chars[length];
+//! }
+//! op {
+//!     u32 pc;
+//!     u8  opcode;     // of the base op for wide
+//!     ... operands;   // the fields of the Op, without switch padding
+//! }
+//! ```
+//! All numbers are big-endian, the same as class files.
+
+use super::*;
+
+use anyhow::Context;
+use bytes::{Buf, BufMut};
+use op::Op;
+
+const MAGIC: u32 = 0x574c494d; // "WLIM"
+/// Bump when the layout of the image or in-memory class changes.
+const FORMAT_VERSION: u16 = 4;
+const VM_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Symbol index for None.
+const SYM_NONE: u32 = u32::MAX;
+
+mod tag {
+    pub const NONE: u8 = 0;
+    pub const CLASS: u8 = 7;
+    pub const FIELD_REF: u8 = 9;
+    pub const METHOD_REF: u8 = 10;
+    pub const INTERFACE_METHOD_REF: u8 = 11;
+    pub const STRING: u8 = 8;
+    pub const INTEGER: u8 = 3;
+    pub const FLOAT: u8 = 4;
+    pub const LONG: u8 = 5;
+    pub const DOUBLE: u8 = 6;
+    pub const NAME_AND_TYPE: u8 = 12;
+    pub const UTF8: u8 = 1;
+}
+
+impl JVM {
+    /// Create an image from class files.
+    pub fn build_image(bins: &[&[u8]]) -> anyhow::Result<Vec<u8>> {
+        let mut w = ImageWriter::default();
+        let mut body = Vec::new();
+        for bin in bins {
+            let cls = super::parse::parse_class_file(bin)?;
+            w.write_class(&mut body, &cls);
+        }
+
+        let mut buf = Vec::new();
+        buf.put_u32(MAGIC);
+        buf.put_u16(FORMAT_VERSION);
+        put_str(&mut buf, VM_VERSION);
+        buf.put_u32(bins.len() as u32);
+        for bin in bins {
+            buf.put_u64(source_hash(bin));
+        }
+        buf.put_u32(w.symbols.len() as u32);
+        for sym in w.symbols.iter() {
+            put_str(&mut buf, sym);
+        }
+        buf.extend_from_slice(&body);
+
+        Ok(buf)
+    }
+
+    /// Load classes from the image if it is valid for `bins`.
+    /// Otherwise (stale image, VM version changed, or broken image),
+    /// fall back to loading the original class files.
+    pub fn load_image_or_classes(
+        &mut self,
+        image: &[u8],
+        bins: &[&[u8]],
+    ) -> anyhow::Result<ImageLoad> {
+        match read_image(image, bins) {
+            Ok(classes) => {
+                for cls in classes {
+                    self.load_native_class(cls);
+                }
+                Ok(ImageLoad::Image)
+            }
+            Err(e) => {
+                for bin in bins {
+                    self.load_class(bin)?;
+                }
+                Ok(ImageLoad::ClassFiles(e))
+            }
+        }
+    }
+}
+
+/// Where [JVM::load_image_or_classes] loaded the classes from.
+#[derive(Debug)]
+pub enum ImageLoad {
+    Image,
+    /// The class files, since the image is not valid. (the reason)
+    ClassFiles(anyhow::Error),
+}
+
+/// FNV-1a 64.
+/// Stable across Rust versions, unlike [std::hash::DefaultHasher].
+fn source_hash(bin: &[u8]) -> u64 {
+    let mut h: u64 = 0xcbf29ce484222325;
+    for &b in bin {
+        h ^= b as u64;
+        h = h.wrapping_mul(0x100000001b3);
+    }
+    h
+}
+
+fn put_str(buf: &mut Vec<u8>, s: &str) {
+    buf.put_u32(s.len() as u32);
+    buf.put_slice(s.as_bytes());
+}
+
+fn get_str(p: &mut &[u8]) -> anyhow::Result<String> {
+    let len = p.try_get_u32()? as usize;
+    anyhow::ensure!(p.len() >= len, "unexpected end of image");
+    let s = str::from_utf8(&p[..len])?.to_string();
+    p.advance(len);
+    Ok(s)
+}
+
+#[derive(Default)]
+struct ImageWriter {
+    symbols: Vec<Rc<String>>,
+    symbol_index: HashMap<Rc<String>, u32>,
+}
+
+impl ImageWriter {
+    fn sym(&mut self, s: &Rc<String>) -> u32 {
+        if let Some(&idx) = self.symbol_index.get(s) {
+            return idx;
+        }
+        let idx = self.symbols.len() as u32;
+        self.symbols.push(Rc::clone(s));
+        self.symbol_index.insert(Rc::clone(s), idx);
+        idx
+    }
+
+    fn put_sym(&mut self, buf: &mut Vec<u8>, s: &Rc<String>) {
+        let idx = self.sym(s);
+        buf.put_u32(idx);
+    }
+
+    fn write_class(&mut self, buf: &mut Vec<u8>, cls: &JClass) {
+        buf.put_u32(cls.constant_pool.pool.len() as u32);
+        for info in cls.constant_pool.pool.iter() {
+            self.write_const(buf, info);
+        }
+
+        buf.put_u16(cls.access_flags);
+        self.put_sym(buf, &cls.this_class);
+        match &cls.super_class {
+            Some(s) => self.put_sym(buf, s),
+            None => buf.put_u32(SYM_NONE),
+        }
+        buf.put_u16(cls.interfaces.len() as u16);
+        for iface in cls.interfaces.iter() {
+            self.put_sym(buf, iface);
+        }
+
+        // sort by name_desc for a reproducible image
+        let mut fields: Vec<_> = cls.fields.values().collect();
+        fields.sort_by(|a, b| a.name_desc.cmp(&b.name_desc));
+        buf.put_u16(fields.len() as u16);
+        for f in fields {
+            buf.put_u16(f.access_flags);
+            self.put_sym(buf, &f.name);
+            self.put_sym(buf, &f.descriptor);
+            match &f.constant_value {
+                None => buf.put_u8(0),
+                Some(v) => {
+                    buf.put_u8(1);
+                    self.write_const(buf, v);
+                }
+            }
+        }
+
+        let mut methods: Vec<_> = cls.methods.values().collect();
+        methods.sort_by(|a, b| a.name_desc.cmp(&b.name_desc));
+        buf.put_u16(methods.len() as u16);
+        for m in methods {
+            buf.put_u16(m.access_flags);
+            self.put_sym(buf, &m.name);
+            self.put_sym(buf, &m.descriptor);
+            match &m.method_body {
+                MethodBody::Java(code) => {
+                    buf.put_u8(1);
+                    buf.put_u16(code.max_stack);
+                    buf.put_u16(code.max_locals);
+                    buf.put_u32(code.ops.code_length() as u32);
+                    buf.put_u32(code.ops.ops().len() as u32);
+                    for (pc, op) in code.ops.ops() {
+                        write_op(buf, *pc, op);
+                    }
+                    buf.put_u16(code.exception_table.len() as u16);
+                    for e in code.exception_table.iter() {
+                        buf.put_u16(e.start_pc);
+                        buf.put_u16(e.end_pc);
+                        buf.put_u16(e.handler_pc);
+                        buf.put_u16(e.catch_type);
+                    }
+                }
+                // native methods are registered at runtime
+                MethodBody::None | MethodBody::Native(_) => buf.put_u8(0),
+            }
+        }
+    }
+
+    fn write_const(&mut self, buf: &mut Vec<u8>, info: &ConstInfo) {
+        match info {
+            ConstInfo::None => buf.put_u8(tag::NONE),
+            ConstInfo::Class { name } => {
+                buf.put_u8(tag::CLASS);
+                self.put_sym(buf, name);
+            }
+            ConstInfo::Fieldref {
+                class,
+                name,
+                descriptor,
+            }
+            | ConstInfo::Methodref {
+                class,
+                name,
+                descriptor,
+            }
+            | ConstInfo::InterfaceMethodref {
+                class,
+                name,
+                descriptor,
+            } => {
+                let t = match info {
+                    ConstInfo::Fieldref { .. } => tag::FIELD_REF,
+                    ConstInfo::Methodref { .. } => tag::METHOD_REF,
+                    _ => tag::INTERFACE_METHOD_REF,
+                };
+                buf.put_u8(t);
+                self.put_sym(buf, class);
+                self.put_sym(buf, name);
+                self.put_sym(buf, descriptor);
+            }
+            ConstInfo::String { string } => {
+                buf.put_u8(tag::STRING);
+                buf.put_u32(string.len() as u32);
+                for &c in string.iter() {
+                    buf.put_u16(c);
+                }
+            }
+            ConstInfo::Integer { bytes } => {
+                buf.put_u8(tag::INTEGER);
+                buf.put_i32(*bytes);
+            }
+            ConstInfo::Float { bytes } => {
+                buf.put_u8(tag::FLOAT);
+                buf.put_f32(*bytes);
+            }
+            ConstInfo::Long { bytes } => {
+                buf.put_u8(tag::LONG);
+                buf.put_i64(*bytes);
+            }
+            ConstInfo::Double { bytes } => {
+                buf.put_u8(tag::DOUBLE);
+                buf.put_f64(*bytes);
+            }
+            ConstInfo::NameAndType { name, descriptor } => {
+                buf.put_u8(tag::NAME_AND_TYPE);
+                self.put_sym(buf, name);
+                self.put_sym(buf, descriptor);
+            }
+            ConstInfo::Utf8 { bytes } => {
+                buf.put_u8(tag::UTF8);
+                self.put_sym(buf, bytes);
+            }
+        }
+    }
+}
+
+fn write_op(buf: &mut Vec<u8>, pc: u32, op: &Op) {
+    buf.put_u32(pc);
+    buf.put_u8(op.opcode());
+    match *op {
+        Op::Bipush { byte: v } | Op::Ldc { index: v } | Op::Newarray { atype: v } => buf.put_u8(v),
+        Op::Sipush { bytes: v }
+        | Op::LdcW { index: v }
+        | Op::Ldc2W { index: v }
+        | Op::Iload { index: v }
+        | Op::Lload { index: v }
+        | Op::Fload { index: v }
+        | Op::Dload { index: v }
+        | Op::Aload { index: v }
+        | Op::Istore { index: v }
+        | Op::Lstore { index: v }
+        | Op::Fstore { index: v }
+        | Op::Dstore { index: v }
+        | Op::Astore { index: v }
+        | Op::Ret { index: v }
+        | Op::GetStatic { index: v }
+        | Op::PutStatic { index: v }
+        | Op::GetField { index: v }
+        | Op::PutField { index: v }
+        | Op::InvokeVirtual { index: v }
+        | Op::InvokeSpecial { index: v }
+        | Op::InvokeStatic { index: v }
+        | Op::Invokedynamic { index: v }
+        | Op::New { index: v }
+        | Op::Anewarray { index: v }
+        | Op::Checkcast { index: v }
+        | Op::Instanceof { index: v } => buf.put_u16(v),
+        Op::Iinc { index, constant } => {
+            buf.put_u16(index);
+            buf.put_i16(constant);
+        }
+        Op::Invokeinterface { index, count: n }
+        | Op::Multianewarray {
+            index,
+            dimensions: n,
+        } => {
+            buf.put_u16(index);
+            buf.put_u8(n);
+        }
+        Op::Ifeq { branch }
+        | Op::Ifne { branch }
+        | Op::Iflt { branch }
+        | Op::Ifge { branch }
+        | Op::Ifgt { branch }
+        | Op::Ifle { branch }
+        | Op::IfIcmpeq { branch }
+        | Op::IfIcmpne { branch }
+        | Op::IfIcmplt { branch }
+        | Op::IfIcmpge { branch }
+        | Op::IfIcmpgt { branch }
+        | Op::IfIcmple { branch }
+        | Op::IfAcmpeq { branch }
+        | Op::IfAcmpne { branch }
+        | Op::Goto { branch }
+        | Op::Jsr { branch }
+        | Op::Ifnull { branch }
+        | Op::Ifnonnull { branch } => buf.put_i16(branch),
+        Op::GotoW { branch } | Op::JsrW { branch } => buf.put_i32(branch),
+        Op::Tableswitch {
+            default,
+            low,
+            high,
+            ref jump_offsets,
+        } => {
+            buf.put_i32(default);
+            buf.put_i32(low);
+            buf.put_i32(high);
+            for &offset in jump_offsets {
+                buf.put_i32(offset);
+            }
+        }
+        Op::Lookupswitch {
+            default,
+            npairs,
+            ref match_offsets,
+        } => {
+            buf.put_i32(default);
+            buf.put_i32(npairs);
+            for &(m, offset) in match_offsets {
+                buf.put_i32(m);
+                buf.put_i32(offset);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Check the header and read all classes.
+/// Fails if the image is not valid for `bins`.
+fn read_image(mut p: &[u8], bins: &[&[u8]]) -> anyhow::Result<Vec<JClass>> {
+    let magic = p.try_get_u32()?;
+    anyhow::ensure!(magic == MAGIC, "bad magic");
+    let format_version = p.try_get_u16()?;
+    anyhow::ensure!(
+        format_version == FORMAT_VERSION,
+        "format version mismatch: {format_version}"
+    );
+    let vm_version = get_str(&mut p)?;
+    anyhow::ensure!(
+        vm_version == VM_VERSION,
+        "VM version mismatch: {vm_version}"
+    );
+
+    let class_count = p.try_get_u32()? as usize;
+    anyhow::ensure!(class_count == bins.len(), "stale image: class count");
+    for bin in bins {
+        let hash = p.try_get_u64()?;
+        anyhow::ensure!(hash == source_hash(bin), "stale image: class file changed");
+    }
+
+    let symbol_count = p.try_get_u32()? as usize;
+    let mut symbols = Vec::with_capacity(symbol_count);
+    for _ in 0..symbol_count {
+        symbols.push(Rc::new(get_str(&mut p)?));
+    }
+
+    let r = ImageReader { symbols };
+    let mut classes = Vec::with_capacity(class_count);
+    for _ in 0..class_count {
+        classes.push(r.read_class(&mut p)?);
+    }
+    anyhow::ensure!(p.is_empty(), "trailing data: {} bytes", p.len());
+
+    Ok(classes)
+}
+
+struct ImageReader {
+    symbols: Vec<Rc<String>>,
+}
+
+impl ImageReader {
+    fn get_sym(&self, p: &mut &[u8]) -> anyhow::Result<Rc<String>> {
+        let idx = p.try_get_u32()?;
+        self.symbols
+            .get(idx as usize)
+            .with_context(|| format!("invalid symbol index: {idx}"))
+            .map(Rc::clone)
+    }
+
+    fn get_sym_opt(&self, p: &mut &[u8]) -> anyhow::Result<Option<Rc<String>>> {
+        let idx = (&p[..]).try_get_u32()?;
+        if idx == SYM_NONE {
+            p.advance(4);
+            Ok(None)
+        } else {
+            self.get_sym(p).map(Some)
+        }
+    }
+
+    fn read_class(&self, p: &mut &[u8]) -> anyhow::Result<JClass> {
+        let pool_count = p.try_get_u32()? as usize;
+        let mut pool = Vec::with_capacity(pool_count);
+        for _ in 0..pool_count {
+            pool.push(self.read_const(p)?);
+        }
+
+        let access_flags = p.try_get_u16()?;
+        let this_class = self.get_sym(p)?;
+        let super_class = self.get_sym_opt(p)?;
+        let interfaces_count = p.try_get_u16()?;
+        let mut interfaces = Vec::with_capacity(interfaces_count as usize);
+        for _ in 0..interfaces_count {
+            interfaces.push(self.get_sym(p)?);
+        }
+
+        let fields_count = p.try_get_u16()? as usize;
+        let mut fields = HashMap::with_capacity(fields_count);
+        for _ in 0..fields_count {
+            let access_flags = p.try_get_u16()?;
+            let name = self.get_sym(p)?;
+            let descriptor = self.get_sym(p)?;
+            let constant_value = match p.try_get_u8()? {
+                0 => None,
+                _ => Some(self.read_const(p)?),
+            };
+            let name_desc = format!("{name}{descriptor}");
+            let jtype = desc::parse_field_desc(&descriptor)?;
+            let field = FieldInfo {
+                access_flags,
+                name,
+                descriptor,
+                name_desc,
+                constant_value,
+                jtype,
+            };
+            fields.insert(field.name_desc.clone(), Rc::new(field));
+        }
+
+        let methods_count = p.try_get_u16()? as usize;
+        let mut methods = HashMap::with_capacity(methods_count);
+        for _ in 0..methods_count {
+            let access_flags = p.try_get_u16()?;
+            let name = self.get_sym(p)?;
+            let descriptor = self.get_sym(p)?;
+            let method_body = match p.try_get_u8()? {
+                0 => MethodBody::None,
+                _ => MethodBody::Java(read_code(p)?),
+            };
+            let name_desc = format!("{name}{descriptor}");
+            let (param_types, ret_type) = desc::parse_method_desc(&descriptor)?;
+            let method = MethodInfo {
+                access_flags,
+                name,
+                descriptor,
+                name_desc,
+                method_body,
+                ret_type,
+                param_types,
+            };
+            methods.insert(method.name_desc.clone(), Rc::new(method));
+        }
+
+        Ok(JClass {
+            constant_pool: ConstantPool { pool },
+            access_flags,
+            this_class,
+            super_class,
+            interfaces,
+            fields,
+            methods,
+        })
+    }
+
+    fn read_const(&self, p: &mut &[u8]) -> anyhow::Result<ConstInfo> {
+        let t = p.try_get_u8()?;
+        let info = match t {
+            tag::NONE => ConstInfo::None,
+            tag::CLASS => ConstInfo::Class {
+                name: self.get_sym(p)?,
+            },
+            tag::FIELD_REF | tag::METHOD_REF | tag::INTERFACE_METHOD_REF => {
+                let class = self.get_sym(p)?;
+                let name = self.get_sym(p)?;
+                let descriptor = self.get_sym(p)?;
+                match t {
+                    tag::FIELD_REF => ConstInfo::Fieldref {
+                        class,
+                        name,
+                        descriptor,
+                    },
+                    tag::METHOD_REF => ConstInfo::Methodref {
+                        class,
+                        name,
+                        descriptor,
+                    },
+                    _ => ConstInfo::InterfaceMethodref {
+                        class,
+                        name,
+                        descriptor,
+                    },
+                }
+            }
+            tag::STRING => {
+                let len = p.try_get_u32()? as usize;
+                let string = (0..len)
+                    .map(|_| p.try_get_u16())
+                    .collect::<Result<_, _>>()?;
+                ConstInfo::String { string }
+            }
+            tag::INTEGER => ConstInfo::Integer {
+                bytes: p.try_get_i32()?,
+            },
+            tag::FLOAT => ConstInfo::Float {
+                bytes: p.try_get_f32()?,
+            },
+            tag::LONG => ConstInfo::Long {
+                bytes: p.try_get_i64()?,
+            },
+            tag::DOUBLE => ConstInfo::Double {
+                bytes: p.try_get_f64()?,
+            },
+            tag::NAME_AND_TYPE => ConstInfo::NameAndType {
+                name: self.get_sym(p)?,
+                descriptor: self.get_sym(p)?,
+            },
+            tag::UTF8 => ConstInfo::Utf8 {
+                bytes: self.get_sym(p)?,
+            },
+            _ => anyhow::bail!("unknown cp tag: {t}"),
+        };
+
+        Ok(info)
+    }
+}
+
+fn read_code(p: &mut &[u8]) -> anyhow::Result<Code> {
+    let max_stack = p.try_get_u16()?;
+    let max_locals = p.try_get_u16()?;
+    let code_length = p.try_get_u32()? as usize;
+    let op_count = p.try_get_u32()? as usize;
+    // at least 5 bytes each
+    anyhow::ensure!(p.len() >= op_count * 5, "unexpected end of image");
+    let mut ops = Vec::with_capacity(op_count);
+    for _ in 0..op_count {
+        ops.push(read_op(p)?);
+    }
+    let ops = op::DecodedCode::from_ops(ops, code_length)?;
+    let exception_table_length = p.try_get_u16()?;
+    let mut exception_table = Vec::with_capacity(exception_table_length as usize);
+    for _ in 0..exception_table_length {
+        exception_table.push(ExceptionTableEntry {
+            start_pc: p.try_get_u16()?,
+            end_pc: p.try_get_u16()?,
+            handler_pc: p.try_get_u16()?,
+            catch_type: p.try_get_u16()?,
+        });
+    }
+
+    Ok(Code {
+        max_stack,
+        max_locals,
+        ops,
+        exception_table,
+    })
+}
+
+/// Read an op written by [write_op]. The operands are read as they are,
+/// without the padding and wide forms of bytecode.
+fn read_op(p: &mut &[u8]) -> anyhow::Result<(u32, Op)> {
+    let pc = p.try_get_u32()?;
+    let opcode = p.try_get_u8()?;
+    let op = match opcode {
+        0x10 => Op::Bipush {
+            byte: p.try_get_u8()?,
+        },
+        0x11 => Op::Sipush {
+            bytes: p.try_get_u16()?,
+        },
+        0x12 => Op::Ldc {
+            index: p.try_get_u8()?,
+        },
+        0x13 => Op::LdcW {
+            index: p.try_get_u16()?,
+        },
+        0x14 => Op::Ldc2W {
+            index: p.try_get_u16()?,
+        },
+        0x15..=0x19 | 0x36..=0x3a | 0xa9 => op::local_op(opcode, p.try_get_u16()?)?,
+        0x84 => Op::Iinc {
+            index: p.try_get_u16()?,
+            constant: p.try_get_i16()?,
+        },
+        0x99..=0xa8 | 0xc6 | 0xc7 => op::branch_op(opcode, p.try_get_i16()?),
+        0xaa => {
+            let default = p.try_get_i32()?;
+            let low = p.try_get_i32()?;
+            let high = p.try_get_i32()?;
+            anyhow::ensure!(low <= high, "invalid tableswitch: low={low} high={high}");
+            let count = (high as i64 - low as i64 + 1) as usize;
+            anyhow::ensure!(p.len() >= count * 4, "unexpected end of image");
+            let jump_offsets = (0..count)
+                .map(|_| p.try_get_i32())
+                .collect::<Result<_, _>>()?;
+            Op::Tableswitch {
+                default,
+                low,
+                high,
+                jump_offsets,
+            }
+        }
+        0xab => {
+            let default = p.try_get_i32()?;
+            let npairs = p.try_get_i32()?;
+            anyhow::ensure!(npairs >= 0, "invalid lookupswitch: npairs={npairs}");
+            anyhow::ensure!(p.len() >= npairs as usize * 8, "unexpected end of image");
+            let match_offsets = (0..npairs)
+                .map(|_| Ok((p.try_get_i32()?, p.try_get_i32()?)))
+                .collect::<anyhow::Result<_>>()?;
+            Op::Lookupswitch {
+                default,
+                npairs,
+                match_offsets,
+            }
+        }
+        0xb2 => Op::GetStatic {
+            index: p.try_get_u16()?,
+        },
+        0xb3 => Op::PutStatic {
+            index: p.try_get_u16()?,
+        },
+        0xb4 => Op::GetField {
+            index: p.try_get_u16()?,
+        },
+        0xb5 => Op::PutField {
+            index: p.try_get_u16()?,
+        },
+        0xb6 => Op::InvokeVirtual {
+            index: p.try_get_u16()?,
+        },
+        0xb7 => Op::InvokeSpecial {
+            index: p.try_get_u16()?,
+        },
+        0xb8 => Op::InvokeStatic {
+            index: p.try_get_u16()?,
+        },
+        0xb9 => Op::Invokeinterface {
+            index: p.try_get_u16()?,
+            count: p.try_get_u8()?,
+        },
+        0xba => Op::Invokedynamic {
+            index: p.try_get_u16()?,
+        },
+        0xbb => Op::New {
+            index: p.try_get_u16()?,
+        },
+        0xbc => Op::Newarray {
+            atype: p.try_get_u8()?,
+        },
+        0xbd => Op::Anewarray {
+            index: p.try_get_u16()?,
+        },
+        0xc0 => Op::Checkcast {
+            index: p.try_get_u16()?,
+        },
+        0xc1 => Op::Instanceof {
+            index: p.try_get_u16()?,
+        },
+        0xc5 => Op::Multianewarray {
+            index: p.try_get_u16()?,
+            dimensions: p.try_get_u8()?,
+        },
+        0xc8 => Op::GotoW {
+            branch: p.try_get_i32()?,
+        },
+        0xc9 => Op::JsrW {
+            branch: p.try_get_i32()?,
+        },
+        // no operands (wide is not written)
+        _ => op::next_op(&[opcode], 0)?.0,
+    };
+
+    Ok((pc, op))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn class_files() -> Vec<&'static [u8]> {
+        let mut bins = Vec::new();
+        bins.extend_from_slice(crate::res::MC_CLASS_FILES);
+        bins.extend_from_slice(crate::res::SAMPLE_CLASS_FILES);
+        bins
+    }
+
+    #[test]
+    fn test_image_roundtrip() {
+        let bins = class_files();
+        let image = JVM::build_image(&bins).unwrap();
+
+        let mut jvm = JVM::new();
+        let res = jvm.load_image_or_classes(&image, &bins).unwrap();
+        assert!(matches!(res, ImageLoad::Image), "{res:?}");
+
+        for bin in bins {
+            let expected = parse::parse_class_file(bin).unwrap();
+            let actual = jvm.get_class(&expected.this_class).unwrap();
+            assert_eq!(actual.super_class, expected.super_class);
+            assert_eq!(actual.interfaces, expected.interfaces);
+            assert_eq!(
+                actual.constant_pool.pool.len(),
+                expected.constant_pool.pool.len()
+            );
+            assert_eq!(actual.fields.len(), expected.fields.len());
+            assert_eq!(actual.methods.len(), expected.methods.len());
+            for (name_desc, m) in expected.methods.iter() {
+                let am = actual.get_method(name_desc).unwrap();
+                match (&m.method_body, &am.method_body) {
+                    (MethodBody::Java(c1), MethodBody::Java(c2)) => {
+                        assert_eq!(c1.ops.ops(), c2.ops.ops());
+                        assert_eq!(c1.ops.code_length(), c2.ops.code_length());
+                        assert_eq!(c1.max_stack, c2.max_stack);
+                        assert_eq!(c1.max_locals, c2.max_locals);
+                        assert_eq!(c1.exception_table.len(), c2.exception_table.len());
+                    }
+                    (MethodBody::None, MethodBody::None) => {}
+                    _ => panic!("method body mismatch: {name_desc}"),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_image_fallback() {
+        let bins = class_files();
+        let image = JVM::build_image(&bins).unwrap();
+
+        // a class file changed
+        let mut changed = bins[0].to_vec();
+        *changed.last_mut().unwrap() ^= 1;
+        let mut stale = bins.clone();
+        stale[0] = &changed;
+        assert!(read_image(&image, &stale).is_err());
+
+        // VM version changed
+        let mut image2 = image.clone();
+        image2[10] ^= 1;
+        let mut jvm = JVM::new();
+        let ImageLoad::ClassFiles(reason) = jvm.load_image_or_classes(&image2, &bins).unwrap()
+        else {
+            panic!();
+        };
+        assert!(
+            reason.to_string().contains("VM version mismatch"),
+            "{reason}"
+        );
+        assert!(jvm.get_class("MasaoConstruction").is_ok());
+    }
+}
@@ -0,0 +1,452 @@
+//! Method invocation, including the API for the host.
+
+use super::*;
+
+use anyhow::Context;
+
+/// Result of a method invocation from the host.
+#[derive(Debug, Clone, PartialEq)]
+pub enum InvokeResult {
+    /// Returned normally. (None if void)
+    Return(Option<JValue>),
+    /// Completed abruptly by an uncaught exception.
+    Exception(JRef),
+}
+
+/// Result of [JVM::call_method].
+pub enum Called {
+    /// A new frame is pushed for a Java method.
+    Pushed,
+    /// A native method returned. (None if void)
+    Returned(Option<JValue>),
+}
+
+impl JVM {
+    /// Register the implementation of a method declared native in a class
+    /// file. It is looked up when the method is invoked, so it can be
+    /// registered before or after the class is loaded.
+    pub fn register_native<F>(&mut self, clsname: &str, name_desc: &str, func: F)
+    where
+        F: Fn(&mut JVM, &mut JThreadContext, &[JValue]) -> anyhow::Result<Option<JValue>> + 'static,
+    {
+        self.natives
+            .insert(format!("{clsname}.{name_desc}"), Rc::new(func));
+    }
+
+    /// Call the method with args (including `this` for an instance method).
+    ///
+    /// A Java method gets a new frame with args in its local variables and
+    /// will be executed by the interpreter. A native method is called
+    /// immediately.
+    ///
+    /// A synchronized Java method enters the monitor before its first op.
+    /// (a synchronized native method does not lock)
+    ///
+    /// A native method may call a Java method in its place by pushing its
+    /// frame (see [JVM::call_virtual]). Then it returns None, and the Java
+    /// method returns to the caller instead.
+    pub fn call_method(
+        &mut self,
+        th: &mut JThreadContext,
+        cls: Rc<JClass>,
+        method: Rc<MethodInfo>,
+        args: &[JValue],
+    ) -> anyhow::Result<Called> {
+        let func = match &method.method_body {
+            MethodBody::Java(_) => {
+                let monitor = if method.access_flags & acc_method::SYNCHRONIZED == 0 {
+                    None
+                } else if method.access_flags & acc_method::STATIC != 0 {
+                    // the arguments are not on the stack yet
+                    let roots: Vec<JRef> = args.iter().filter_map(JValue::as_ref).collect();
+                    Some(self.with_roots(&roots, |jvm| jvm.class_object(th, &cls.this_class))?)
+                } else {
+                    Some(args[0].as_ref().context("synchronized on null")?)
+                };
+                th.new_frame(cls, method)?.monitor = monitor;
+                th.store_args(args);
+                return Ok(Called::Pushed);
+            }
+            MethodBody::Native(func) => Rc::clone(func),
+            MethodBody::None => {
+                let key = format!("{}.{}", cls.this_class, method.name_desc);
+                if method.access_flags & acc_method::NATIVE != 0 {
+                    Rc::clone(
+                        self.natives
+                            .get(&key)
+                            .ok_or_else(|| exception!(UnsatisfiedLinkError, "{key}"))?,
+                    )
+                } else if method.access_flags & acc_method::ABSTRACT != 0 {
+                    throw!(AbstractMethodError, "{key}");
+                } else {
+                    anyhow::bail!("no code: {key}");
+                }
+            }
+        };
+
+        let depth = th.frames.len();
+        let ret = func(self, th, args)?;
+        if th.frames.len() > depth {
+            return Ok(Called::Pushed);
+        }
+        Ok(Called::Returned(ret))
+    }
+
+    /// Call the instance method selected by the class of the receiver.
+    pub(super) fn call_virtual(
+        &mut self,
+        th: &mut JThreadContext,
+        receiver: JRef,
+        name: &str,
+        descriptor: &str,
+        args: &[JValue],
+    ) -> anyhow::Result<Called> {
+        let resolved = self.resolve_in_receiver(receiver, name, descriptor)?;
+        let (cls, method) = self.select_virtual(&resolved, &resolved.class)?;
+
+        let mut this_args = Vec::with_capacity(args.len() + 1);
+        this_args.push(JValue::Ref(receiver));
+        this_args.extend_from_slice(args);
+
+        self.call_method(th, cls, method, &this_args)
+    }
+
+    /// Resolve the method as referenced by the class of the receiver, to
+    /// be selected by [JVM::select_virtual] as invokevirtual does.
+    fn resolve_in_receiver(
+        &mut self,
+        receiver: JRef,
+        name: &str,
+        descriptor: &str,
+    ) -> anyhow::Result<ResolvedMethod> {
+        let class = Rc::clone(&self.heap.get(receiver).class);
+        let (decl, method) = self.resolve_method(&class.this_class, name, descriptor)?;
+        Ok(ResolvedMethod {
+            class,
+            decl,
+            method,
+        })
+    }
+
+    /// Invoke a static method from the host and run until it returns.
+    /// The class is initialized if it has not been initialized yet.
+    pub fn invoke_static(
+        &mut self,
+        th: &mut JThreadContext,
+        cls: Rc<JClass>,
+        method: Rc<MethodInfo>,
+        args: &[JValue],
+    ) -> anyhow::Result<InvokeResult> {
+        anyhow::ensure!(
+            method.access_flags & acc_method::STATIC != 0,
+            "{}.{} is not static",
+            cls.this_class,
+            method.name_desc
+        );
+        self.check_args(&method, args)?;
+        if let Err(err) = self.initialize_class(th, &cls.this_class) {
+            return Ok(InvokeResult::Exception(self.exception_object(th, err)?));
+        }
+
+        self.invoke_and_run(th, cls, method, args)
+    }
+
+    /// Invoke an instance method on `receiver` from the host and run until
+    /// it returns. The method is selected by the class of the receiver.
+    pub fn invoke_virtual(
+        &mut self,
+        th: &mut JThreadContext,
+        receiver: JRef,
+        name: &str,
+        descriptor: &str,
+        args: &[JValue],
+    ) -> anyhow::Result<InvokeResult> {
+        let resolved = self.resolve_in_receiver(receiver, name, descriptor)?;
+        anyhow::ensure!(
+            resolved.method.access_flags & acc_method::STATIC == 0,
+            "{}.{} is static",
+            resolved.decl.this_class,
+            resolved.method.name_desc
+        );
+        self.check_args(&resolved.method, args)?;
+        let (cls, method) = self.select_virtual(&resolved, &resolved.class)?;
+
+        let mut this_args = Vec::with_capacity(args.len() + 1);
+        this_args.push(JValue::Ref(receiver));
+        this_args.extend_from_slice(args);
+
+        self.invoke_and_run(th, cls, method, &this_args)
+    }
+
+    /// Create a new instance and run the constructor with the descriptor.
+    /// Returns the new object as the return value.
+    pub fn new_instance(
+        &mut self,
+        th: &mut JThreadContext,
+        clsname: &str,
+        descriptor: &str,
+        args: &[JValue],
+    ) -> anyhow::Result<InvokeResult> {
+        let cls = self.get_class(clsname)?;
+        let method = cls.get_method(&format!("<init>{descriptor}"))?;
+        self.check_args(&method, args)?;
+        if let Err(err) = self.initialize_class(th, &cls.this_class) {
+            return Ok(InvokeResult::Exception(self.exception_object(th, err)?));
+        }
+        let obj = match self.alloc_or_collect(th, |jvm| jvm.alloc_object(clsname)) {
+            Ok(obj) => obj,
+            Err(err) => return Ok(InvokeResult::Exception(self.exception_object(th, err)?)),
+        };
+
+        let mut this_args = Vec::with_capacity(args.len() + 1);
+        this_args.push(JValue::Ref(obj));
+        this_args.extend_from_slice(args);
+
+        let res = match self.invoke_and_run(th, cls, method, &this_args)? {
+            InvokeResult::Return(_) => InvokeResult::Return(Some(JValue::Ref(obj))),
+            exception => exception,
+        };
+
+        Ok(res)
+    }
+
+    /// Invoke a method by name only, for test harnesses.
+    ///
+    /// The method is searched in the class and its superclasses, and the
+    /// overload is chosen by the types of args.
+    /// For an instance method, `args[0]` is the receiver.
+    pub fn call_method_by_name(
+        &mut self,
+        th: &mut JThreadContext,
+        clsname: &str,
+        name: &str,
+        args: &[JValue],
+    ) -> anyhow::Result<InvokeResult> {
+        let mut found: Vec<(Rc<JClass>, Rc<MethodInfo>)> = Vec::new();
+        let mut cur = Some(self.get_class(clsname)?);
+        while let Some(cls) = cur {
+            for m in cls.methods.values() {
+                if m.name.as_str() != name || found.iter().any(|(_, f)| f.name_desc == m.name_desc)
+                {
+                    continue;
+                }
+                let matched = if m.access_flags & acc_method::STATIC != 0 {
+                    self.check_args(m, args).is_ok()
+                } else {
+                    match args.split_first() {
+                        Some((JValue::Ref(r), rest)) => {
+                            let rcls = Rc::clone(&self.heap.get(*r).class);
+                            self.instance_of(&rcls, &cls.this_class)?
+                                && self.check_args(m, rest).is_ok()
+                        }
+                        _ => false,
+                    }
+                };
+                if matched {
+                    found.push((Rc::clone(&cls), Rc::clone(m)));
+                }
+            }
+            cur = match &cls.super_class {
+                Some(super_class) => Some(self.get_class(super_class)?),
+                None => None,
+            };
+        }
+
+        let (cls, method) = match &found[..] {
+            [] => anyhow::bail!("NoSuchMethodError: {clsname}.{name} for {args:?}"),
+            [found] => found.clone(),
+            _ => anyhow::bail!("ambiguous method: {clsname}.{name} for {args:?}"),
+        };
+        if method.access_flags & acc_method::STATIC != 0 {
+            self.invoke_static(th, cls, method, args)
+        } else {
+            let JValue::Ref(receiver) = args[0] else {
+                unreachable!()
+            };
+            self.invoke_virtual(th, receiver, &method.name, &method.descriptor, &args[1..])
+        }
+    }
+
+    /// Call the method and run the thread until the method returns.
+    /// Blocking there waits only for the clock. ([JVM::block_in_host])
+    pub(super) fn invoke_and_run(
+        &mut self,
+        th: &mut JThreadContext,
+        cls: Rc<JClass>,
+        method: Rc<MethodInfo>,
+        args: &[JValue],
+    ) -> anyhow::Result<InvokeResult> {
+        let depth = th.frames.len();
+        match self.call_method(th, cls, method, args) {
+            Ok(Called::Pushed) => {}
+            Ok(Called::Returned(ret)) => return Ok(InvokeResult::Return(ret)),
+            Err(err) => {
+                let ex = self.exception_object(th, err)?;
+                return Ok(InvokeResult::Exception(ex));
+            }
+        }
+        // the caller waits for the result, so no other thread runs and the
+        // budget of the slice is not applied
+        let budget = std::mem::take(&mut self.budget);
+        let deadline = self.deadline.take();
+        let turn_end = std::mem::replace(&mut self.turn_end, u64::MAX);
+        let res = self.run_invocation(th, depth);
+        self.budget = budget;
+        self.deadline = deadline;
+        self.turn_end = turn_end;
+
+        res
+    }
+
+    fn run_invocation(
+        &mut self,
+        th: &mut JThreadContext,
+        depth: usize,
+    ) -> anyhow::Result<InvokeResult> {
+        loop {
+            match self.run_until(th, depth)? {
+                ThreadState::Finished(ret) => return Ok(InvokeResult::Return(ret)),
+                ThreadState::Uncaught(ex) => return Ok(InvokeResult::Exception(ex)),
+                ThreadState::Yielded => {}
+                ThreadState::Blocked(reason) => {
+                    if let Err(err) = self.block_in_host(th, reason) {
+                        let ex = self.exception_object(th, err)?;
+                        if !self.unwind(th, ex, depth)? {
+                            return Ok(InvokeResult::Exception(ex));
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Check args from the host against the parameter types.
+    /// (`this` is not included)
+    fn check_args(&self, method: &MethodInfo, args: &[JValue]) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            args.len() == method.param_types.len(),
+            "{}: {} arguments expected, but {} given",
+            method.name_desc,
+            method.param_types.len(),
+            args.len()
+        );
+        for (i, (jtype, v)) in method.param_types.iter().zip(args).enumerate() {
+            anyhow::ensure!(
+                self.is_assignable_value(jtype, v)?,
+                "{}: argument {i} type mismatch: {v:?} for {jtype:?}",
+                method.name_desc
+            );
+        }
+
+        Ok(())
+    }
+
+    fn is_assignable_value(&self, jtype: &JType, v: &JValue) -> anyhow::Result<bool> {
+        if jtype.is_reference() {
+            return match v {
+                JValue::Null => Ok(true),
+                JValue::Ref(r) => {
+                    let cls = Rc::clone(&self.heap.get(*r).class);
+                    self.instance_of(&cls, &jtype.class_name())
+                }
+                _ => Ok(false),
+            };
+        }
+
+        let ok = match (&jtype.ctype, v) {
+            (JComponentType::Boolean, &JValue::Int(x)) => x == 0 || x == 1,
+            (JComponentType::Byte, &JValue::Int(x)) => i8::try_from(x).is_ok(),
+            (JComponentType::Char, &JValue::Int(x)) => u16::try_from(x).is_ok(),
+            (JComponentType::Short, &JValue::Int(x)) => i16::try_from(x).is_ok(),
+            (JComponentType::Int, JValue::Int(_)) => true,
+            (JComponentType::Long, JValue::Long(_)) => true,
+            (JComponentType::Float, JValue::Float(_)) => true,
+            (JComponentType::Double, JValue::Double(_)) => true,
+            _ => false,
+        };
+
+        Ok(ok)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use test_util::setup;
+
+    fn call(jvm: &mut JVM, name: &str, args: &[JValue]) -> InvokeResult {
+        test_util::invoke(jvm, "Invoke", name, args)
+    }
+
+    #[test]
+    fn test_invoke_static() {
+        let mut jvm = setup();
+
+        let res = call(&mut jvm, "second", &[JValue::Int(1), JValue::Int(2)]);
+        assert_eq!(res, InvokeResult::Return(Some(JValue::Int(2))));
+        // long and double take 2 slots
+        let args = [JValue::Int(1), JValue::Long(-2), JValue::Double(3.5)];
+        let res = call(&mut jvm, "pick", &args);
+        assert_eq!(res, InvokeResult::Return(Some(JValue::Long(-2))));
+        let res = call(&mut jvm, "pickDouble", &args);
+        assert_eq!(res, InvokeResult::Return(Some(JValue::Double(3.5))));
+        // invokestatic
+        let res = call(&mut jvm, "swap", &[JValue::Int(1), JValue::Int(2)]);
+        assert_eq!(res, InvokeResult::Return(Some(JValue::Int(1))));
+
+        // argument check
+        let mut th = JThreadContext::default();
+        assert!(
+            jvm.call_method_by_name(&mut th, "Invoke", "second", &[JValue::Int(1)])
+                .is_err()
+        );
+        assert!(
+            jvm.call_method_by_name(&mut th, "Invoke", "second", &[JValue::Int(1), JValue::Null])
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_invoke_native() {
+        let mut jvm = setup();
+
+        let res = call(&mut jvm, "callNative", &[JValue::Int(21)]);
+        let InvokeResult::Exception(ex) = res else {
+            panic!("{res:?}");
+        };
+        assert_eq!(
+            jvm.heap.get(ex).class.this_class.as_str(),
+            "java/lang/UnsatisfiedLinkError"
+        );
+
+        jvm.register_native("Invoke", "twice(I)I", |_, _, args| {
+            let JValue::Int(x) = args[0] else {
+                anyhow::bail!("invalid arg");
+            };
+            Ok(Some(JValue::Int(x * 2)))
+        });
+        let res = call(&mut jvm, "callNative", &[JValue::Int(21)]);
+        assert_eq!(res, InvokeResult::Return(Some(JValue::Int(42))));
+        let res = call(&mut jvm, "twice", &[JValue::Int(4)]);
+        assert_eq!(res, InvokeResult::Return(Some(JValue::Int(8))));
+    }
+
+    #[test]
+    fn test_new_instance() {
+        let mut jvm = setup();
+
+        let mut th = JThreadContext::default();
+        let res = jvm.new_instance(&mut th, "Invoke", "()V", &[]).unwrap();
+        let InvokeResult::Return(Some(JValue::Ref(obj))) = res else {
+            panic!("{res:?}");
+        };
+
+        let res = jvm
+            .invoke_virtual(&mut th, obj, "self", "()Ljava/lang/Object;", &[])
+            .unwrap();
+        assert_eq!(res, InvokeResult::Return(Some(JValue::Ref(obj))));
+        let res = call(&mut jvm, "echo", &[JValue::Ref(obj), JValue::Int(7)]);
+        assert_eq!(res, InvokeResult::Return(Some(JValue::Int(7))));
+        assert!(th.frames.is_empty());
+    }
+}
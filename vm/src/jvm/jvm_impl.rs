@@ -3,9 +3,29 @@ use anyhow::Context;
 
 impl JVM {
     pub fn new() -> Self {
+        Self::with_config(JVMConfig::default())
+    }
+
+    pub fn with_config(config: JVMConfig) -> Self {
         Self {
             classes: Default::default(),
             class_rt: Default::default(),
+            cp_caches: Default::default(),
+            heap: heap::Heap::new(config.max_heap),
+            natives: Default::default(),
+            exceptions: Default::default(),
+            interned: Default::default(),
+            class_objects: Default::default(),
+            gc: Default::default(),
+            global_refs: Default::default(),
+            budget: Default::default(),
+            deadline: None,
+            executed: 0,
+            turn_end: u64::MAX,
+            threads: Default::default(),
+            next_thread_id: JThreadContext::MAIN_ID + 1,
+            clock: Box::new(clock::WallClock::default()),
+            config,
         }
     }
 
@@ -27,10 +47,25 @@ impl JVM {
     pub fn get_class(&self, name: &str) -> anyhow::Result<Rc<JClass>> {
         self.classes
             .get(name)
-            .with_context(|| format!("class not found: {name}"))
+            .ok_or_else(|| exception!(NoClassDefFoundError, "{name}"))
             .map(Rc::clone)
     }
 
+    /// The cached resolutions of the class definition, if any.
+    pub(super) fn cp_cache(&self, cls: &Rc<JClass>) -> Option<&CpCache> {
+        self.cp_caches.get(&Rc::as_ptr(cls))
+    }
+
+    /// The caches of the class definition, created at the first use.
+    pub(super) fn cp_cache_mut(&mut self, cls: &Rc<JClass>) -> &mut CpCache {
+        self.cp_caches
+            .entry(Rc::as_ptr(cls))
+            .or_insert_with(|| CpCache {
+                class: Rc::downgrade(cls),
+                ..Default::default()
+            })
+    }
+
     /// 5.5. Initialization
     /// The execution of any one of the Java Virtual Machine instructions
     /// new, getstatic, putstatic, or invokestatic that references C
@@ -52,154 +87,929 @@ impl JVM {
     ///
     /// If C is a class, its designation as the initial class at
     /// Java Virtual Machine startup (§5.2).
-    fn get_class_rtinfo(&mut self, name: &String) -> anyhow::Result<&mut JClassRuntimeInfo> {
+    ///
+    /// Static fields are set to their ConstantValue or default value, the
+    /// superclass is initialized, and then `<clinit>` is run on the thread
+    /// above the current frame until it returns.
+    pub fn initialize_class(&mut self, th: &mut JThreadContext, name: &str) -> anyhow::Result<()> {
         let cls = self.get_class(name)?;
         let rtinfo = self
             .class_rt
             .get_mut(name)
             .with_context(|| format!("class rtinfo not found: {name}"))?;
-        if rtinfo.initialized {
-            return Ok(rtinfo);
+        match rtinfo.init_state {
+            // <clinit> runs to completion without switching threads, so only
+            // a recursive request sees Initializing
+            InitState::Initializing | InitState::Initialized => return Ok(()),
+            InitState::Erroneous => {
+                throw!(NoClassDefFoundError, "Could not initialize class {name}")
+            }
+            InitState::Uninitialized => {}
         }
+        rtinfo.init_state = InitState::Initializing;
 
-        // class initialize
-        // TODO: not perfect yet
+        let mut static_fields = HashMap::new();
         for field in cls.fields.values() {
             if field.access_flags & acc_field::STATIC != 0 {
-                let v = if let Some(v) = &field.constant_value {
-                    v.clone()
-                } else {
-                    field.jtype.to_default_value()
+                let v = match &field.constant_value {
+                    Some(info) => self.loadable_constant(th, info)?,
+                    None => field.jtype.to_default_value(),
                 };
-                rtinfo.static_fields.insert(field.name.to_string(), v);
+                static_fields.insert(field.name_desc.clone(), v);
             }
         }
-        rtinfo.initialized = true;
+        self.class_rt.get_mut(name).unwrap().static_fields = static_fields;
 
-        Ok(rtinfo)
+        // superinterfaces are not initialized unless they are used
+        let mut result = Ok(());
+        if !cls.is_interface()
+            && let Some(super_class) = &cls.super_class
+        {
+            result = self.initialize_class(th, super_class);
+        }
+        if result.is_ok()
+            && let Some(clinit) = cls.methods.get("<clinit>()V")
+        {
+            result = match self.invoke_and_run(th, Rc::clone(&cls), Rc::clone(clinit), &[])? {
+                InvokeResult::Return(_) => Ok(()),
+                InvokeResult::Exception(ex) => Err(self.initializer_error(th, ex)?),
+            };
+        }
+
+        self.class_rt.get_mut(name).unwrap().init_state = match result {
+            Ok(()) => InitState::Initialized,
+            Err(_) => InitState::Erroneous,
+        };
+
+        result
     }
 
-    pub fn get_static(&mut self, clsname: &String, fname: &String) -> anyhow::Result<JValue> {
-        let cls_info = self.get_class_rtinfo(clsname)?;
-        assert!(cls_info.initialized);
-        let v = cls_info
+    /// An Error from `<clinit>` is thrown as is, and any other exception is
+    /// wrapped in ExceptionInInitializerError.
+    fn initializer_error(
+        &mut self,
+        th: &JThreadContext,
+        ex: JRef,
+    ) -> anyhow::Result<anyhow::Error> {
+        let ex_class = Rc::clone(&self.heap.get(ex).class);
+        if self.instance_of(&ex_class, "java/lang/Error")? {
+            return Ok(exception::Throw::Object(ex).into());
+        }
+        let err = exception!(
+            ExceptionInInitializerError,
+            "{}",
+            ex_class.this_class.replace('/', ".")
+        );
+        let error = self.exception_object(th, err)?;
+
+        Ok(exception::Throw::Object(error).into())
+    }
+
+    /// getstatic from the host or the interpreter.
+    ///
+    /// The field is resolved through superinterfaces and superclasses,
+    /// and the class which declares it is initialized.
+    pub fn get_static(
+        &mut self,
+        th: &mut JThreadContext,
+        clsname: &str,
+        fname: &str,
+        fdesc: &str,
+    ) -> anyhow::Result<JValue> {
+        let (decl, field) = self.resolve_static_field(th, clsname, fname, fdesc)?;
+        let v = self.class_rt[decl.this_class.as_str()]
             .static_fields
-            .get(fname)
+            .get(&field.name_desc)
             .with_context(|| format!("field not found: {fname}"))?;
 
         Ok(v.clone())
     }
 
-    pub fn invoke_static(
-        &self,
+    /// putstatic from the host or the interpreter.
+    /// boolean, byte, char and short values are narrowed to the field type.
+    pub fn put_static(
+        &mut self,
         th: &mut JThreadContext,
-        cls: Rc<JClass>,
-        method: Rc<MethodInfo>, /* , args... */
+        clsname: &str,
+        fname: &str,
+        fdesc: &str,
+        v: JValue,
     ) -> anyhow::Result<()> {
-        th.new_frame(cls, method)?;
+        let (decl, field) = self.resolve_static_field(th, clsname, fname, fdesc)?;
+        let v = field.jtype.narrow(v);
+        let old = self
+            .class_rt
+            .get_mut(decl.this_class.as_str())
+            .with_context(|| format!("class rtinfo not found: {}", decl.this_class))?
+            .static_fields
+            .insert(field.name_desc.clone(), v);
+        self.write_barrier(old.and_then(|old| old.as_ref()));
 
         Ok(())
     }
 
-    pub fn run(&mut self, th: &mut JThreadContext) -> anyhow::Result<()> {
-        // tentatively pop the current frame to execute
-        let mut frame = th.pop_frame();
+    /// Resolve a static field and initialize the class which declares it.
+    fn resolve_static_field(
+        &mut self,
+        th: &mut JThreadContext,
+        clsname: &str,
+        fname: &str,
+        fdesc: &str,
+    ) -> anyhow::Result<(Rc<JClass>, Rc<FieldInfo>)> {
+        let (decl, field) = self.resolve_field(clsname, fname, fdesc)?;
+        throw_unless!(
+            field.access_flags & acc_field::STATIC != 0,
+            IncompatibleClassChangeError,
+            "{}.{fname} is not static",
+            decl.this_class
+        );
+        self.initialize_class(th, &decl.this_class)?;
+
+        Ok((decl, field))
+    }
+
+    /// Run the thread until it finishes, yields, blocks or throws.
+    /// It can be called again to continue if yielded or blocked.
+    /// An uncaught exception terminates the thread and is printed.
+    pub fn run(&mut self, th: &mut JThreadContext) -> anyhow::Result<ThreadState> {
+        let state = self.run_until(th, 0)?;
+        if let ThreadState::Uncaught(ex) = state {
+            self.print_uncaught(th, ex);
+        }
+
+        Ok(state)
+    }
+
+    /// Run the thread for a slice limited by the budget, so that the host
+    /// can keep its event loop responsive.
+    ///
+    /// Returns Yielded when the budget runs out. All state stays in the
+    /// thread, and calling it again resumes at the next instruction.
+    /// `<clinit>` and other methods run to completion inside an instruction
+    /// are not preempted.
+    ///
+    /// If `gc_time` is set, a slice of incremental GC runs first.
+    ///
+    /// The thread is driven by the host, so no other thread runs.
+    /// (see [JVM::run_for] for the threads owned by the VM)
+    pub fn run_thread_for(
+        &mut self,
+        th: &mut JThreadContext,
+        budget: Budget,
+    ) -> anyhow::Result<ThreadState> {
+        if let Some(gc_time) = budget.gc_time {
+            self.gc_slice(Some(th), gc_time);
+        }
+        self.start_slice(budget);
+        let state = self.run(th);
+        self.end_slice();
 
-        let result = self.run_internal(&mut th.stack, &mut frame);
+        state
+    }
 
-        // if return or err, do not restore the current frame (do pop)
-        let res = match result {
-            Ok(ExecOpResult::Continue) => {
-                th.push_frame(frame);
-                Ok(())
+    /// Run the thread until the frames are popped to `depth`.
+    /// Finished has the return value of the frame at `depth`.
+    /// A Java exception is handled in the frames above `depth`, or Uncaught.
+    pub fn run_until(
+        &mut self,
+        th: &mut JThreadContext,
+        depth: usize,
+    ) -> anyhow::Result<ThreadState> {
+        while th.frames.len() > depth {
+            let result = match self.run_internal(th) {
+                Ok(result) => result,
+                Err(err) => {
+                    let ex = self.exception_object(th, err)?;
+                    if !self.unwind(th, ex, depth)? {
+                        return Ok(ThreadState::Uncaught(ex));
+                    }
+                    continue;
+                }
+            };
+            match result {
+                ExecOpResult::Continue | ExecOpResult::PushFrame => {}
+                ExecOpResult::PopFrame(ret) => {
+                    self.exit_frame_monitor(th)?;
+                    th.pop_frame();
+                    if th.frames.len() <= depth {
+                        return Ok(ThreadState::Finished(ret));
+                    }
+                    if let Some(v) = ret {
+                        th.push_value(v);
+                    }
+                }
             }
-            Ok(ExecOpResult::PopFrame) => Ok(()),
-            Ok(ExecOpResult::PushFrame(new_frame)) => {
-                th.push_frame(frame);
-                th.push_frame(new_frame);
-                Ok(())
+            if let Some(state) = th.suspend.take() {
+                return Ok(state);
             }
-            Err(e) => Err(e),
-        };
+        }
 
-        res
+        Ok(ThreadState::Finished(None))
     }
 
-    fn run_internal(
-        &mut self,
-        stack: &mut Vec<u32>,
-        frame: &mut JStackFrame,
-    ) -> anyhow::Result<ExecOpResult> {
+    /// Execute the current frame until it returns or invokes another method.
+    fn run_internal(&mut self, th: &mut JThreadContext) -> anyhow::Result<ExecOpResult> {
+        let frame = th.current_frame();
+        if let (Some(r), false) = (frame.monitor, frame.locked) {
+            if !self.try_enter(th.id, r) {
+                th.block(BlockReason::Monitor(r));
+                return Ok(ExecOpResult::Continue);
+            }
+            th.current_frame().locked = true;
+        }
+
+        let method = Rc::clone(&th.current_frame().method);
+        let code = match &method.method_body {
+            MethodBody::None => anyhow::bail!("no code"),
+            // natives are called by call_method without a frame to run
+            MethodBody::Native(_) => anyhow::bail!(
+                "native method in a frame: {}.{}",
+                th.current_frame().class.this_class,
+                method.name_desc
+            ),
+            MethodBody::Java(code) => &code.ops,
+        };
+
         let result = loop {
-            let code = match &frame.method.method_body {
-                MethodBody::None => anyhow::bail!("no code"),
-                MethodBody::Native(_func) => unimplemented!("native call"),
-                MethodBody::Java(code) => &code.code,
-            };
+            if self.budget_exhausted() {
+                th.yield_now();
+                break ExecOpResult::Continue;
+            }
+            self.executed += 1;
 
             // fetch the next op
-            let (op, len) = next_op(&code[frame.pc as usize..])?;
-            println!("[{}] {:?}", frame.pc, op);
-            frame.pc += len as u32;
+            let frame = th.current_frame();
+            let (op, next_pc) = code.op_at(frame.pc)?;
+            frame.op_pc = frame.pc;
+            frame.pc = next_pc;
 
-            let result = self.exec_op(frame, op)?;
-            // TODO: make a chance to preempt during normal execution
-            if !matches!(result, ExecOpResult::Continue) {
+            let result = self.exec_op(th, op.clone())?;
+            if !matches!(result, ExecOpResult::Continue) || th.suspend.is_some() {
                 break result;
             }
         };
 
         Ok(result)
     }
+
+    /// Set the limits of a slice from now.
+    pub(super) fn start_slice(&mut self, budget: Budget) {
+        self.budget = budget;
+        self.deadline = budget.time.map(|time| self.clock.monotonic() + time);
+        self.executed = 0;
+    }
+
+    /// Back to no limits, for the host calls outside of slices.
+    pub(super) fn end_slice(&mut self) {
+        self.budget = Budget::default();
+        self.deadline = None;
+    }
+
+    pub(super) fn deadline_passed(&self) -> bool {
+        self.deadline
+            .is_some_and(|deadline| self.clock.monotonic() >= deadline)
+    }
+
+    fn budget_exhausted(&self) -> bool {
+        self.executed >= self.turn_end
+            || self.budget.instructions.is_some_and(|n| self.executed >= n)
+            || (self.deadline.is_some()
+                && self
+                    .executed
+                    .is_multiple_of(Budget::DEADLINE_CHECK_INTERVAL)
+                && self.deadline_passed())
+    }
+}
+
+impl Default for JVM {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Default for JVMConfig {
+    fn default() -> Self {
+        Self {
+            max_heap: 256 << 20,
+            max_object_size: 64 << 20,
+        }
+    }
+}
+
+impl Budget {
+    pub const DEADLINE_CHECK_INTERVAL: u64 = 256;
+
+    /// At most `n` instructions.
+    pub fn instructions(n: u64) -> Self {
+        Self {
+            instructions: Some(n),
+            ..Default::default()
+        }
+    }
+
+    /// For `duration` from the start of the slice.
+    pub fn duration(duration: std::time::Duration) -> Self {
+        Self {
+            time: Some(duration),
+            ..Default::default()
+        }
+    }
 }
 
 enum ExecOpResult {
     Continue,
-    /// Return from the method.
-    PopFrame,
-    /// Invoke a new method.
-    PushFrame(JStackFrame),
+    /// Return from the method with the return value. (None if void)
+    PopFrame(Option<JValue>),
+    /// A new frame is pushed to invoke a method.
+    PushFrame,
 }
 
 impl JVM {
-    fn exec_op(&mut self, frame: &mut JStackFrame, op: op::Op) -> anyhow::Result<ExecOpResult> {
+    fn exec_op(&mut self, th: &mut JThreadContext, op: op::Op) -> anyhow::Result<ExecOpResult> {
         use op::Op;
-        let cls = &frame.class;
+        let cls = &Rc::clone(&th.current_frame().class);
 
         let res = match op {
+            Op::Nop => ExecOpResult::Continue,
+            Op::AconstNull => {
+                th.push(0u32);
+                ExecOpResult::Continue
+            }
+            Op::IconstM1
+            | Op::Iconst0
+            | Op::Iconst1
+            | Op::Iconst2
+            | Op::Iconst3
+            | Op::Iconst4
+            | Op::Iconst5 => {
+                let v = match op {
+                    Op::IconstM1 => -1,
+                    Op::Iconst0 => 0,
+                    Op::Iconst1 => 1,
+                    Op::Iconst2 => 2,
+                    Op::Iconst3 => 3,
+                    Op::Iconst4 => 4,
+                    _ => 5,
+                };
+                th.push::<i32>(v);
+                ExecOpResult::Continue
+            }
+            Op::Lconst0 | Op::Lconst1 => {
+                th.push::<i64>(if matches!(op, Op::Lconst0) { 0 } else { 1 });
+                ExecOpResult::Continue
+            }
+            Op::Fconst0 | Op::Fconst1 | Op::Fconst2 => {
+                let v = match op {
+                    Op::Fconst0 => 0.0,
+                    Op::Fconst1 => 1.0,
+                    _ => 2.0,
+                };
+                th.push::<f32>(v);
+                ExecOpResult::Continue
+            }
+            Op::Dconst0 | Op::Dconst1 => {
+                th.push::<f64>(if matches!(op, Op::Dconst0) { 0.0 } else { 1.0 });
+                ExecOpResult::Continue
+            }
+            Op::Bipush { byte } => {
+                // sign-extended
+                th.push(byte as i8 as i32);
+                ExecOpResult::Continue
+            }
+            Op::Sipush { bytes } => {
+                // sign-extended
+                th.push(bytes as i16 as i32);
+                ExecOpResult::Continue
+            }
+            // int, float, reference: 1 slot
+            Op::Iload { index } | Op::Fload { index } | Op::Aload { index } => {
+                let v = th.load_slot(index);
+                th.push_slot(v);
+                ExecOpResult::Continue
+            }
+            // long, double: 2 slots
+            Op::Lload { index } | Op::Dload { index } => {
+                let v: i64 = th.load(index);
+                th.push(v);
+                ExecOpResult::Continue
+            }
+            Op::Iload0 | Op::Fload0 | Op::Aload0 => self.exec_load1(th, 0),
+            Op::Iload1 | Op::Fload1 | Op::Aload1 => self.exec_load1(th, 1),
+            Op::Iload2 | Op::Fload2 | Op::Aload2 => self.exec_load1(th, 2),
+            Op::Iload3 | Op::Fload3 | Op::Aload3 => self.exec_load1(th, 3),
+            Op::Lload0 | Op::Dload0 => self.exec_load2(th, 0),
+            Op::Lload1 | Op::Dload1 => self.exec_load2(th, 1),
+            Op::Lload2 | Op::Dload2 => self.exec_load2(th, 2),
+            Op::Lload3 | Op::Dload3 => self.exec_load2(th, 3),
+            Op::Istore { index } | Op::Fstore { index } | Op::Astore { index } => {
+                let v = th.pop_slot();
+                th.store_slot(index, v);
+                ExecOpResult::Continue
+            }
+            Op::Lstore { index } | Op::Dstore { index } => {
+                let v: i64 = th.pop();
+                th.store(index, v);
+                ExecOpResult::Continue
+            }
+            Op::Istore0 | Op::Fstore0 | Op::Astore0 => self.exec_store1(th, 0),
+            Op::Istore1 | Op::Fstore1 | Op::Astore1 => self.exec_store1(th, 1),
+            Op::Istore2 | Op::Fstore2 | Op::Astore2 => self.exec_store1(th, 2),
+            Op::Istore3 | Op::Fstore3 | Op::Astore3 => self.exec_store1(th, 3),
+            Op::Lstore0 | Op::Dstore0 => self.exec_store2(th, 0),
+            Op::Lstore1 | Op::Dstore1 => self.exec_store2(th, 1),
+            Op::Lstore2 | Op::Dstore2 => self.exec_store2(th, 2),
+            Op::Lstore3 | Op::Dstore3 => self.exec_store2(th, 3),
+            Op::Iadd
+            | Op::Ladd
+            | Op::Fadd
+            | Op::Dadd
+            | Op::Isub
+            | Op::Lsub
+            | Op::Fsub
+            | Op::Dsub
+            | Op::Imul
+            | Op::Lmul
+            | Op::Fmul
+            | Op::Dmul
+            | Op::Idiv
+            | Op::Ldiv
+            | Op::Fdiv
+            | Op::Ddiv
+            | Op::Irem
+            | Op::Lrem
+            | Op::Frem
+            | Op::Drem
+            | Op::Ineg
+            | Op::Lneg
+            | Op::Fneg
+            | Op::Dneg
+            | Op::Ishl
+            | Op::Lshl
+            | Op::Ishr
+            | Op::Lshr
+            | Op::Iushr
+            | Op::Lushr
+            | Op::Iand
+            | Op::Land
+            | Op::Ior
+            | Op::Lor
+            | Op::Ixor
+            | Op::Lxor
+            | Op::I2L
+            | Op::I2F
+            | Op::I2D
+            | Op::L2I
+            | Op::L2F
+            | Op::L2D
+            | Op::F2I
+            | Op::F2L
+            | Op::F2D
+            | Op::D2I
+            | Op::D2L
+            | Op::D2F
+            | Op::I2B
+            | Op::I2C
+            | Op::I2S
+            | Op::Lcmp
+            | Op::Fcmpl
+            | Op::Fcmpg
+            | Op::Dcmpl
+            | Op::Dcmpg => {
+                self.exec_arith(th, op)?;
+                ExecOpResult::Continue
+            }
+            Op::Newarray { .. }
+            | Op::Anewarray { .. }
+            | Op::Multianewarray { .. }
+            | Op::Arraylength
+            | Op::Iaload
+            | Op::Laload
+            | Op::Faload
+            | Op::Daload
+            | Op::Aaload
+            | Op::Baload
+            | Op::Caload
+            | Op::Saload
+            | Op::Iastore
+            | Op::Lastore
+            | Op::Fastore
+            | Op::Dastore
+            | Op::Aastore
+            | Op::Bastore
+            | Op::Castore
+            | Op::Sastore => {
+                self.exec_array(th, cls, op)?;
+                ExecOpResult::Continue
+            }
+            Op::Iinc { index, constant } => {
+                let v: i32 = th.load(index);
+                th.store(index, v.wrapping_add(constant as i32));
+                ExecOpResult::Continue
+            }
+            // Stack manipulation works on raw slots (with the reference tags).
+            // A category 2 value is two slots, so each form in the spec
+            // (e.g. dup2 for two category 1 values or one category 2 value)
+            // is the same slot operation.
+            Op::Pop => {
+                th.pop::<u32>();
+                ExecOpResult::Continue
+            }
+            Op::Pop2 => {
+                th.pop::<u32>();
+                th.pop::<u32>();
+                ExecOpResult::Continue
+            }
+            Op::Dup => {
+                // ..., v1 -> ..., v1, v1
+                let v1 = th.pop_slot();
+                th.push_slot(v1);
+                th.push_slot(v1);
+                ExecOpResult::Continue
+            }
+            Op::DupX1 => {
+                // ..., v2, v1 -> ..., v1, v2, v1
+                let v1 = th.pop_slot();
+                let v2 = th.pop_slot();
+                th.push_slot(v1);
+                th.push_slot(v2);
+                th.push_slot(v1);
+                ExecOpResult::Continue
+            }
+            Op::DupX2 => {
+                // ..., v3, v2, v1 -> ..., v1, v3, v2, v1
+                let v1 = th.pop_slot();
+                let v2 = th.pop_slot();
+                let v3 = th.pop_slot();
+                th.push_slot(v1);
+                th.push_slot(v3);
+                th.push_slot(v2);
+                th.push_slot(v1);
+                ExecOpResult::Continue
+            }
+            Op::Dup2 => {
+                // ..., v2, v1 -> ..., v2, v1, v2, v1
+                let v1 = th.pop_slot();
+                let v2 = th.pop_slot();
+                th.push_slot(v2);
+                th.push_slot(v1);
+                th.push_slot(v2);
+                th.push_slot(v1);
+                ExecOpResult::Continue
+            }
+            Op::Dup2X1 => {
+                // ..., v3, v2, v1 -> ..., v2, v1, v3, v2, v1
+                let v1 = th.pop_slot();
+                let v2 = th.pop_slot();
+                let v3 = th.pop_slot();
+                th.push_slot(v2);
+                th.push_slot(v1);
+                th.push_slot(v3);
+                th.push_slot(v2);
+                th.push_slot(v1);
+                ExecOpResult::Continue
+            }
+            Op::Dup2X2 => {
+                // ..., v4, v3, v2, v1 -> ..., v2, v1, v4, v3, v2, v1
+                let v1 = th.pop_slot();
+                let v2 = th.pop_slot();
+                let v3 = th.pop_slot();
+                let v4 = th.pop_slot();
+                th.push_slot(v2);
+                th.push_slot(v1);
+                th.push_slot(v4);
+                th.push_slot(v3);
+                th.push_slot(v2);
+                th.push_slot(v1);
+                ExecOpResult::Continue
+            }
+            Op::Swap => {
+                // ..., v2, v1 -> ..., v1, v2
+                let v1 = th.pop_slot();
+                let v2 = th.pop_slot();
+                th.push_slot(v1);
+                th.push_slot(v2);
+                ExecOpResult::Continue
+            }
             Op::Ldc { index } => {
-                let value = frame.class.constant_pool.get(index as u16)?;
-                match value {
-                    ConstInfo::Integer { bytes } => todo!(),
-                    ConstInfo::Float { bytes } => todo!(),
-                    ConstInfo::String { string } => todo!(),
-                    _ => anyhow::bail!("Unknown runtime constant info: {value:?}"),
-                }
-
+                let v = self.ldc(th, cls, index as u16)?;
+                th.push_value(v);
+                ExecOpResult::Continue
+            }
+            Op::LdcW { index } | Op::Ldc2W { index } => {
+                let v = self.ldc(th, cls, index)?;
+                th.push_value(v);
                 ExecOpResult::Continue
             }
             Op::GetStatic { index } => {
-                let (fcls, fname, _fdesc) = cls.constant_pool.get_field(index)?;
-                println!("GetStatic #{index} {fcls} {fname}");
-
-                let v = self.get_static(&fcls, &fname)?;
-                println!("GetStatic: {v:?}");
+                let (fcls, fname, fdesc) = cls.constant_pool.get_field(index)?;
+                let v = self.get_static(th, &fcls, &fname, &fdesc)?;
+                th.push_value(v);
+                ExecOpResult::Continue
+            }
+            Op::PutStatic { index } => {
+                let (fcls, fname, fdesc) = cls.constant_pool.get_field(index)?;
+                let v = th.pop_value(&desc::parse_field_desc(&fdesc)?);
+                self.put_static(th, &fcls, &fname, &fdesc, v)?;
+                ExecOpResult::Continue
+            }
+            Op::GetField { index } => {
+                let ResolvedField { field, slot, .. } = self.resolve_instance_field(cls, index)?;
+                let Some(obj) = th.pop::<Option<JRef>>() else {
+                    throw!(
+                        NullPointerException,
+                        "Cannot read field \"{}\" because the reference is null",
+                        field.name
+                    );
+                };
+                let v = self.heap.get(obj).get_field(slot, &field.jtype);
+                th.push_value(v);
+                ExecOpResult::Continue
+            }
+            Op::PutField { index } => {
+                let ResolvedField { field, slot, .. } = self.resolve_instance_field(cls, index)?;
+                let v = field.jtype.narrow(th.pop_value(&field.jtype));
+                let Some(obj) = th.pop::<Option<JRef>>() else {
+                    throw!(
+                        NullPointerException,
+                        "Cannot assign field \"{}\" because the reference is null",
+                        field.name
+                    );
+                };
+                if field.jtype.is_reference() {
+                    let old = JRef::from_slot(self.heap.get(obj).fields[slot as usize]);
+                    self.write_barrier(old);
+                }
+                self.heap.get_mut(obj).set_field(slot, v);
+                ExecOpResult::Continue
+            }
+            Op::InvokeVirtual { index }
+            | Op::InvokeSpecial { index }
+            | Op::Invokeinterface { index, .. } => {
+                let resolved = self.resolve_method_ref(cls, index)?;
+                let args = th.pop_args(&resolved.method, true);
+                let JValue::Ref(receiver) = args[0] else {
+                    throw!(
+                        NullPointerException,
+                        "Cannot invoke \"{}.{}\" because the receiver is null",
+                        resolved.decl.this_class.replace('/', "."),
+                        resolved.method.name
+                    );
+                };
+                let rcls = Rc::clone(&self.heap.get(receiver).class);
+                let (decl, method) = match op {
+                    Op::InvokeVirtual { .. } => self.select_virtual(&resolved, &rcls)?,
+                    Op::InvokeSpecial { .. } => self.select_special(&resolved, cls)?,
+                    _ => self.select_interface(&resolved, &rcls)?,
+                };
+                self.exec_invoke(th, decl, method, &args)?
+            }
+            Op::InvokeStatic { index } => {
+                let ResolvedMethod { decl, method, .. } = self.resolve_method_ref(cls, index)?;
+                throw_unless!(
+                    method.access_flags & acc_method::STATIC != 0,
+                    IncompatibleClassChangeError,
+                    "{}.{} is not static",
+                    decl.this_class,
+                    method.name_desc
+                );
+                self.initialize_class(th, &decl.this_class)?;
 
+                let args = th.pop_args(&method, false);
+                self.exec_invoke(th, decl, method, &args)?
+            }
+            Op::Ifeq { branch }
+            | Op::Ifne { branch }
+            | Op::Iflt { branch }
+            | Op::Ifge { branch }
+            | Op::Ifgt { branch }
+            | Op::Ifle { branch } => {
+                let v: i32 = th.pop();
+                let cond = match op {
+                    Op::Ifeq { .. } => v == 0,
+                    Op::Ifne { .. } => v != 0,
+                    Op::Iflt { .. } => v < 0,
+                    Op::Ifge { .. } => v >= 0,
+                    Op::Ifgt { .. } => v > 0,
+                    _ => v <= 0,
+                };
+                Self::exec_branch(th, cond, branch as i32)
+            }
+            Op::IfIcmpeq { branch }
+            | Op::IfIcmpne { branch }
+            | Op::IfIcmplt { branch }
+            | Op::IfIcmpge { branch }
+            | Op::IfIcmpgt { branch }
+            | Op::IfIcmple { branch } => {
+                let b: i32 = th.pop();
+                let a: i32 = th.pop();
+                let cond = match op {
+                    Op::IfIcmpeq { .. } => a == b,
+                    Op::IfIcmpne { .. } => a != b,
+                    Op::IfIcmplt { .. } => a < b,
+                    Op::IfIcmpge { .. } => a >= b,
+                    Op::IfIcmpgt { .. } => a > b,
+                    _ => a <= b,
+                };
+                Self::exec_branch(th, cond, branch as i32)
+            }
+            Op::IfAcmpeq { branch } | Op::IfAcmpne { branch } => {
+                let b: Option<JRef> = th.pop();
+                let a: Option<JRef> = th.pop();
+                let cond = (a == b) == matches!(op, Op::IfAcmpeq { .. });
+                Self::exec_branch(th, cond, branch as i32)
+            }
+            Op::Ifnull { branch } | Op::Ifnonnull { branch } => {
+                let v: Option<JRef> = th.pop();
+                let cond = v.is_none() == matches!(op, Op::Ifnull { .. });
+                Self::exec_branch(th, cond, branch as i32)
+            }
+            Op::Goto { branch } => Self::exec_branch(th, true, branch as i32),
+            Op::GotoW { branch } => Self::exec_branch(th, true, branch),
+            // The returnAddress (the next op) is pushed as a raw slot,
+            // and astore stores it to a local for ret.
+            Op::Jsr { branch } => {
+                let ret = th.current_frame().pc;
+                th.push(ret);
+                Self::exec_branch(th, true, branch as i32)
+            }
+            Op::JsrW { branch } => {
+                let ret = th.current_frame().pc;
+                th.push(ret);
+                Self::exec_branch(th, true, branch)
+            }
+            Op::Ret { index } => {
+                let ret: u32 = th.load(index);
+                th.current_frame().pc = ret;
+                ExecOpResult::Continue
+            }
+            Op::Tableswitch {
+                default,
+                low,
+                high,
+                jump_offsets,
+            } => {
+                let index: i32 = th.pop();
+                let offset = if (low..=high).contains(&index) {
+                    jump_offsets[(index as i64 - low as i64) as usize]
+                } else {
+                    default
+                };
+                Self::exec_branch(th, true, offset)
+            }
+            Op::Lookupswitch {
+                default,
+                match_offsets,
+                ..
+            } => {
+                let key: i32 = th.pop();
+                // pairs are sorted by match
+                let offset = match match_offsets.binary_search_by_key(&key, |&(m, _)| m) {
+                    Ok(i) => match_offsets[i].1,
+                    Err(_) => default,
+                };
+                Self::exec_branch(th, true, offset)
+            }
+            Op::Athrow => {
+                let Some(ex) = th.pop::<Option<JRef>>() else {
+                    throw!(
+                        NullPointerException,
+                        "Cannot throw exception because it is null"
+                    );
+                };
+                return Err(exception::Throw::Object(ex).into());
+            }
+            Op::Monitorenter => {
+                let Some(r) = th.pop::<Option<JRef>>() else {
+                    throw!(
+                        NullPointerException,
+                        "Cannot enter synchronized block because the reference is null"
+                    );
+                };
+                // blocked: executed again when the monitor is free
+                if !self.try_enter(th.id, r) {
+                    th.push(Some(r));
+                    let frame = th.current_frame();
+                    frame.pc = frame.op_pc;
+                    th.block(BlockReason::Monitor(r));
+                }
+                ExecOpResult::Continue
+            }
+            Op::Monitorexit => {
+                let Some(r) = th.pop::<Option<JRef>>() else {
+                    throw!(
+                        NullPointerException,
+                        "Cannot exit synchronized block because the reference is null"
+                    );
+                };
+                self.exit_monitor(th.id, r)?;
+                ExecOpResult::Continue
+            }
+            Op::Checkcast { index } => {
+                let target = cls.constant_pool.get_class(index)?;
+                let r: Option<JRef> = th.pop();
+                if let Some(r) = r {
+                    let rcls = Rc::clone(&self.heap.get(r).class);
+                    throw_unless!(
+                        self.instance_of(&rcls, &target)?,
+                        ClassCastException,
+                        "class {} cannot be cast to class {}",
+                        rcls.this_class.replace('/', "."),
+                        target.replace('/', ".")
+                    );
+                }
+                th.push(r);
+                ExecOpResult::Continue
+            }
+            Op::Instanceof { index } => {
+                let target = cls.constant_pool.get_class(index)?;
+                let r: Option<JRef> = th.pop();
+                let v = match r {
+                    Some(r) => {
+                        let rcls = Rc::clone(&self.heap.get(r).class);
+                        self.instance_of(&rcls, &target)?
+                    }
+                    None => false,
+                };
+                th.push(v as i32);
+                ExecOpResult::Continue
+            }
+            Op::New { index } => {
+                let clsname = cls.constant_pool.get_class(index)?;
+                self.initialize_class(th, &clsname)?;
+                let obj = self.alloc_or_collect(th, |jvm| jvm.alloc_object(&clsname))?;
+                th.push(Some(obj));
                 ExecOpResult::Continue
             }
-            Op::Return => ExecOpResult::PopFrame,
-            _ => {
-                println!("(op not implemented)");
+            Op::Ireturn => ExecOpResult::PopFrame(Some(JValue::Int(th.pop()))),
+            Op::Lreturn => ExecOpResult::PopFrame(Some(JValue::Long(th.pop()))),
+            Op::Freturn => ExecOpResult::PopFrame(Some(JValue::Float(th.pop()))),
+            Op::Dreturn => ExecOpResult::PopFrame(Some(JValue::Double(th.pop()))),
+            Op::Areturn => ExecOpResult::PopFrame(Some(JValue::from_ref(th.pop()))),
+            Op::Return => ExecOpResult::PopFrame(None),
+            op => throw!(InternalError, "unsupported op: {op:?}"),
+        };
+
+        Ok(res)
+    }
+
+    /// Call the selected method. The return value of a native method is
+    /// pushed immediately.
+    fn exec_invoke(
+        &mut self,
+        th: &mut JThreadContext,
+        cls: Rc<JClass>,
+        method: Rc<MethodInfo>,
+        args: &[JValue],
+    ) -> anyhow::Result<ExecOpResult> {
+        let res = match self.call_method(th, cls, method, args)? {
+            invoke::Called::Pushed => ExecOpResult::PushFrame,
+            invoke::Called::Returned(ret) => {
+                if let Some(v) = ret {
+                    th.push_value(v);
+                }
                 ExecOpResult::Continue
             }
         };
 
         Ok(res)
     }
+
+    /// Branch to the offset from the current op if `cond` holds.
+    fn exec_branch(th: &mut JThreadContext, cond: bool, offset: i32) -> ExecOpResult {
+        if cond {
+            let frame = th.current_frame();
+            frame.pc = frame.op_pc.wrapping_add_signed(offset);
+        }
+        ExecOpResult::Continue
+    }
+
+    fn exec_load1(&mut self, th: &mut JThreadContext, index: u16) -> ExecOpResult {
+        let v = th.load_slot(index);
+        th.push_slot(v);
+        ExecOpResult::Continue
+    }
+
+    fn exec_load2(&mut self, th: &mut JThreadContext, index: u16) -> ExecOpResult {
+        let v: i64 = th.load(index);
+        th.push(v);
+        ExecOpResult::Continue
+    }
+
+    fn exec_store1(&mut self, th: &mut JThreadContext, index: u16) -> ExecOpResult {
+        let v = th.pop_slot();
+        th.store_slot(index, v);
+        ExecOpResult::Continue
+    }
+
+    fn exec_store2(&mut self, th: &mut JThreadContext, index: u16) -> ExecOpResult {
+        let v: i64 = th.pop();
+        th.store(index, v);
+        ExecOpResult::Continue
+    }
 }
 
 impl JThreadContext {
-    fn new_frame(
+    pub(super) fn new_frame(
         &mut self,
         class: Rc<JClass>,
         method: Rc<MethodInfo>,
@@ -215,36 +1025,57 @@ impl JThreadContext {
         let size = max_locals + max_stack;
         let stack_consume = size as usize;
         let base = self.stack.len() as u32;
-        anyhow::ensure!(
+        throw_unless!(
             self.stack.len() + stack_consume <= Self::MAX_STACK as usize,
-            "stack overflow"
+            StackOverflowError,
+            "{}.{}",
+            class.this_class,
+            method.name_desc
         );
         let top = base + size;
 
         self.stack.resize(self.stack.len() + stack_consume, 0);
+        self.refs.resize(self.stack.len(), false);
         self.frames.push(JStackFrame {
             range: std::ops::Range {
                 start: base,
                 end: top,
             },
             sp: max_locals,
+            max_locals,
             pc: 0,
+            op_pc: 0,
             class,
             method,
+            monitor: None,
+            locked: false,
         });
 
         Ok(self.current_frame())
     }
 
-    fn current_frame(&mut self) -> &mut JStackFrame {
+    pub(super) fn current_frame(&mut self) -> &mut JStackFrame {
         self.frames.last_mut().expect("no frames")
     }
-    fn push_frame(&mut self, frame: JStackFrame) {
-        self.frames.push(frame);
+    /// Pop the current frame and release its locals and operand stack.
+    pub(super) fn pop_frame(&mut self) -> JStackFrame {
+        let frame = self.frames.pop().expect("no frames");
+        self.stack.truncate(frame.range.start as usize);
+        self.refs.truncate(frame.range.start as usize);
+
+        frame
     }
 
-    fn pop_frame(&mut self) -> JStackFrame {
-        self.frames.pop().expect("no frames")
+    /// Called by a native method to give other threads the chance to run.
+    /// The thread is suspended after the native method returns.
+    pub fn yield_now(&mut self) {
+        self.suspend = Some(ThreadState::Yielded);
+    }
+
+    /// Called by a native method to block the thread.
+    /// The thread is suspended after the native method returns.
+    pub fn block(&mut self, reason: BlockReason) {
+        self.suspend = Some(ThreadState::Blocked(reason));
     }
 }
 
@@ -252,7 +1083,480 @@ impl JClass {
     pub fn get_method(&self, name_desc: &str) -> anyhow::Result<Rc<MethodInfo>> {
         self.methods
             .get(name_desc)
-            .context("method {name_desc} not found")
+            .with_context(|| format!("method {name_desc} not found"))
             .map(Rc::clone)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use op::Op;
+
+    /// A thread with a frame of the given size.
+    fn thread_with_frame(max_locals: u16, max_stack: u16) -> JThreadContext {
+        thread_with_code(max_locals, max_stack, vec![])
+    }
+
+    /// A thread with a frame to execute the code.
+    fn thread_with_code(max_locals: u16, max_stack: u16, code: Vec<u8>) -> JThreadContext {
+        let cls = Rc::new(parse::define_native_class(
+            "T",
+            Some("java/lang/Object"),
+            HashMap::new(),
+            HashMap::new(),
+        ));
+        let mut method = stdlib::define_method(acc_method::STATIC, "t", "()V", |_, _, _| Ok(None));
+        method.method_body = MethodBody::Java(Code {
+            max_stack,
+            max_locals,
+            ops: op::DecodedCode::decode(&code, &[]).unwrap(),
+            exception_table: vec![],
+        });
+
+        let mut th = JThreadContext::default();
+        th.new_frame(cls, Rc::new(method)).unwrap();
+        th
+    }
+
+    fn exec(jvm: &mut JVM, th: &mut JThreadContext, ops: Vec<Op>) {
+        for op in ops {
+            jvm.exec_op(th, op).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_load_store() {
+        let mut jvm = JVM::new();
+        let mut th = thread_with_frame(4, 4);
+        exec(
+            &mut jvm,
+            &mut th,
+            vec![
+                Op::Bipush { byte: 0xff },
+                Op::Istore0,
+                Op::Sipush { bytes: 0x8000 },
+                Op::Fconst2,
+                Op::Fstore { index: 1 },
+            ],
+        );
+        assert_eq!(th.pop::<i32>(), -32768);
+        assert_eq!(th.load::<i32>(0), -1);
+        assert_eq!(th.load::<f32>(1), 2.0);
+
+        exec(
+            &mut jvm,
+            &mut th,
+            vec![
+                Op::Iinc {
+                    index: 0,
+                    constant: -3,
+                },
+                Op::Lconst1,
+                Op::Lstore2,
+                Op::Lload { index: 2 },
+                Op::Dconst1,
+            ],
+        );
+        assert_eq!(th.load::<i32>(0), -4);
+        assert_eq!(th.load::<i64>(2), 1);
+        assert_eq!(th.pop::<f64>(), 1.0);
+        assert_eq!(th.pop::<i64>(), 1);
+    }
+
+    #[test]
+    fn test_stack_manipulation() {
+        let mut jvm = JVM::new();
+        let mut th = thread_with_frame(0, 8);
+
+        // dup_x2 form 2: value1 (cat 1), value2 (cat 2)
+        exec(&mut jvm, &mut th, vec![Op::Lconst1, Op::Iconst2, Op::DupX2]);
+        assert_eq!(th.pop::<i32>(), 2);
+        assert_eq!(th.pop::<i64>(), 1);
+        assert_eq!(th.pop::<i32>(), 2);
+
+        // dup2_x1 form 2: value1 (cat 2), value2 (cat 1)
+        exec(
+            &mut jvm,
+            &mut th,
+            vec![Op::Iconst3, Op::Dconst1, Op::Dup2X1],
+        );
+        assert_eq!(th.pop::<f64>(), 1.0);
+        assert_eq!(th.pop::<i32>(), 3);
+        assert_eq!(th.pop::<f64>(), 1.0);
+
+        // dup2_x2 form 4: value1, value2 (cat 2)
+        exec(
+            &mut jvm,
+            &mut th,
+            vec![Op::Lconst0, Op::Lconst1, Op::Dup2X2],
+        );
+        assert_eq!(th.pop::<i64>(), 1);
+        assert_eq!(th.pop::<i64>(), 0);
+        assert_eq!(th.pop::<i64>(), 1);
+
+        // dup2 (2 x cat 1), swap, pop2 (cat 2), pop
+        exec(
+            &mut jvm,
+            &mut th,
+            vec![
+                Op::Iconst1,
+                Op::Iconst2,
+                Op::Dup2,
+                Op::Swap,
+                Op::Lconst0,
+                Op::Pop2,
+                Op::Pop,
+                Op::DupX1,
+            ],
+        );
+        // 1, 2, 1, 2 -> 1, 2, 2, 1 -> 1, 2, 2 -> 1, 2, 2, 2
+        assert_eq!(th.pop::<i32>(), 2);
+        assert_eq!(th.pop::<i32>(), 2);
+        assert_eq!(th.pop::<i32>(), 2);
+        assert_eq!(th.pop::<i32>(), 1);
+        assert_eq!(th.frames[0].sp, 0);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "operand stack underflow")]
+    fn test_stack_underflow() {
+        let mut jvm = JVM::new();
+        let mut th = thread_with_frame(1, 1);
+        exec(&mut jvm, &mut th, vec![Op::Pop]);
+    }
+
+    #[test]
+    fn test_unsupported_op() {
+        let mut jvm = JVM::new();
+        stdlib::load_core(&mut jvm);
+        // invokedynamic #1
+        let mut th = thread_with_code(0, 1, vec![0xba, 0x00, 0x01, 0x00, 0x00]);
+        let ThreadState::Uncaught(ex) = jvm.run(&mut th).unwrap() else {
+            panic!();
+        };
+        assert_eq!(
+            jvm.heap.get(ex).class.this_class.as_str(),
+            "java/lang/InternalError"
+        );
+    }
+
+    #[test]
+    fn test_array_type_mismatch() {
+        let mut jvm = JVM::new();
+        stdlib::load_core(&mut jvm);
+        // iconst_1; newarray long; iconst_0; iconst_0; iastore; return
+        let code = vec![0x04, 0xbc, 0x0b, 0x03, 0x03, 0x4f, 0xb1];
+        let mut th = thread_with_code(0, 3, code);
+        let ThreadState::Uncaught(ex) = jvm.run(&mut th).unwrap() else {
+            panic!();
+        };
+        assert_eq!(
+            jvm.heap.get(ex).class.this_class.as_str(),
+            "java/lang/VerifyError"
+        );
+    }
+
+    #[test]
+    fn test_run() {
+        let mut jvm = test_util::setup();
+        // yield once in the native method
+        jvm.register_native("Invoke", "twice(I)I", |_, th, args| {
+            th.yield_now();
+            let JValue::Int(x) = args[0] else {
+                anyhow::bail!("invalid arg");
+            };
+            Ok(Some(JValue::Int(x * 2)))
+        });
+
+        let cls = jvm.get_class("Invoke").unwrap();
+        let method = cls.get_method("callNative(I)I").unwrap();
+        let mut th = JThreadContext::default();
+        jvm.call_method(&mut th, cls, method, &[JValue::Int(21)])
+            .unwrap();
+
+        assert_eq!(jvm.run(&mut th).unwrap(), ThreadState::Yielded);
+        assert_eq!(th.frames.len(), 1);
+        assert_eq!(
+            jvm.run(&mut th).unwrap(),
+            ThreadState::Finished(Some(JValue::Int(42)))
+        );
+        // the stack is released
+        assert!(th.frames.is_empty());
+        assert!(th.stack.is_empty());
+        assert_eq!(jvm.run(&mut th).unwrap(), ThreadState::Finished(None));
+    }
+
+    #[test]
+    fn test_run_for() {
+        let mut jvm = test_util::setup();
+
+        // resumed slice by slice with the same result
+        let cls = jvm.get_class("Branch").unwrap();
+        let method = cls.get_method("sum(I)I").unwrap();
+        let mut th = JThreadContext::default();
+        jvm.call_method(&mut th, cls, method, &[JValue::Int(10)])
+            .unwrap();
+        let mut slices = 1;
+        let res = loop {
+            match jvm
+                .run_thread_for(&mut th, Budget::instructions(7))
+                .unwrap()
+            {
+                ThreadState::Yielded => slices += 1,
+                res => break res,
+            }
+        };
+        assert_eq!(res, ThreadState::Finished(Some(JValue::Int(45))));
+        assert!(slices > 10);
+        assert!(jvm.executed <= 7);
+
+        // 0: goto 0
+        let mut th = thread_with_code(0, 0, vec![0xa7, 0x00, 0x00]);
+        let res = jvm
+            .run_thread_for(&mut th, Budget::instructions(0))
+            .unwrap();
+        assert_eq!(res, ThreadState::Yielded);
+        assert_eq!(jvm.executed, 0);
+        let res = jvm
+            .run_thread_for(&mut th, Budget::instructions(1000))
+            .unwrap();
+        assert_eq!(res, ThreadState::Yielded);
+        assert_eq!(jvm.executed, 1000);
+        assert_eq!(th.frames.len(), 1);
+
+        let duration = std::time::Duration::from_millis(10);
+        let start = jvm.clock.monotonic();
+        let res = jvm
+            .run_thread_for(&mut th, Budget::duration(duration))
+            .unwrap();
+        assert_eq!(res, ThreadState::Yielded);
+        assert!(jvm.clock.monotonic() - start >= duration);
+        assert!(jvm.executed > 0);
+    }
+
+    /// Execute a binary op on a and b, and pop the result.
+    fn binary<T: stack::SlotValue>(jvm: &mut JVM, a: T, b: T, op: Op) -> anyhow::Result<T> {
+        let mut th = thread_with_frame(0, 4);
+        th.push(a);
+        th.push(b);
+        jvm.exec_op(&mut th, op)?;
+        Ok(th.pop())
+    }
+
+    fn unary<T: stack::SlotValue, U: stack::SlotValue>(jvm: &mut JVM, a: T, op: Op) -> U {
+        let mut th = thread_with_frame(0, 4);
+        th.push(a);
+        jvm.exec_op(&mut th, op).unwrap();
+        th.pop()
+    }
+
+    #[test]
+    fn test_int_arith() {
+        let mut jvm = JVM::new();
+        let jvm = &mut jvm;
+
+        assert_eq!(binary(jvm, i32::MAX, 1, Op::Iadd).unwrap(), i32::MIN);
+        assert_eq!(binary(jvm, i32::MIN, 1, Op::Isub).unwrap(), i32::MAX);
+        assert_eq!(binary(jvm, 0x10000, 0x10000, Op::Imul).unwrap(), 0);
+        assert_eq!(binary(jvm, i32::MIN, -1, Op::Idiv).unwrap(), i32::MIN);
+        assert_eq!(binary(jvm, i32::MIN, -1, Op::Irem).unwrap(), 0);
+        assert_eq!(binary(jvm, -7, 2, Op::Idiv).unwrap(), -3);
+        assert_eq!(binary(jvm, -7, 2, Op::Irem).unwrap(), -1);
+        assert_eq!(binary(jvm, i64::MIN, -1, Op::Ldiv).unwrap(), i64::MIN);
+        assert_eq!(binary(jvm, i64::MIN, -1, Op::Lrem).unwrap(), 0);
+        assert_eq!(unary::<i32, i32>(jvm, i32::MIN, Op::Ineg), i32::MIN);
+        assert_eq!(unary::<i64, i64>(jvm, i64::MIN, Op::Lneg), i64::MIN);
+
+        let err = binary(jvm, 1, 0, Op::Idiv).unwrap_err();
+        assert_eq!(err.to_string(), "java.lang.ArithmeticException: / by zero");
+        assert!(binary(jvm, 1, 0, Op::Irem).is_err());
+        assert!(binary(jvm, 1i64, 0, Op::Ldiv).is_err());
+        assert!(binary(jvm, 1i64, 0, Op::Lrem).is_err());
+
+        // shift counts are masked
+        assert_eq!(binary(jvm, 1, 33, Op::Ishl).unwrap(), 2);
+        assert_eq!(binary(jvm, -1, 28, Op::Iushr).unwrap(), 0xf);
+        assert_eq!(binary(jvm, -16, -1, Op::Ishr).unwrap(), -1);
+        let mut th = thread_with_frame(0, 4);
+        th.push(-1i64);
+        th.push(65);
+        jvm.exec_op(&mut th, Op::Lushr).unwrap();
+        assert_eq!(th.pop::<i64>(), i64::MAX);
+        th.push(1i64);
+        th.push(-1);
+        jvm.exec_op(&mut th, Op::Lshl).unwrap();
+        assert_eq!(th.pop::<i64>(), i64::MIN);
+
+        assert_eq!(unary::<i32, i32>(jvm, 0x1ff, Op::I2B), -1);
+        assert_eq!(unary::<i32, i32>(jvm, -1, Op::I2C), 0xffff);
+        assert_eq!(unary::<i32, i32>(jvm, 0x18000, Op::I2S), -0x8000);
+        assert_eq!(unary::<i64, i32>(jvm, 0x1_0000_0002, Op::L2I), 2);
+    }
+
+    #[test]
+    fn test_float_arith() {
+        let mut jvm = JVM::new();
+        let jvm = &mut jvm;
+
+        // fmod: the sign of the dividend
+        assert_eq!(binary(jvm, -5.5f32, 2.0, Op::Frem).unwrap(), -1.5);
+        assert_eq!(binary(jvm, 5.5f64, -2.0, Op::Drem).unwrap(), 1.5);
+        assert!(binary(jvm, 1.0f64, 0.0, Op::Drem).unwrap().is_nan());
+        assert_eq!(binary(jvm, 1.0f32, 0.0, Op::Fdiv).unwrap(), f32::INFINITY);
+        // -0.0
+        let v = unary::<f64, f64>(jvm, 0.0, Op::Dneg);
+        assert_eq!(v.to_bits(), (-0.0f64).to_bits());
+
+        // saturating and NaN conversions
+        assert_eq!(unary::<f32, i32>(jvm, f32::NAN, Op::F2I), 0);
+        assert_eq!(unary::<f32, i32>(jvm, 1e20, Op::F2I), i32::MAX);
+        assert_eq!(unary::<f64, i64>(jvm, f64::NEG_INFINITY, Op::D2L), i64::MIN);
+        assert_eq!(unary::<f64, i64>(jvm, f64::NAN, Op::D2L), 0);
+        assert_eq!(unary::<f64, i32>(jvm, -2.9, Op::D2I), -2);
+        assert_eq!(unary::<i64, f32>(jvm, i64::MAX, Op::L2F), 9.223372e18);
+        assert_eq!(unary::<i32, f32>(jvm, 16777217, Op::I2F), 16777216.0);
+        assert_eq!(unary::<f64, f32>(jvm, 1e40, Op::D2F), f32::INFINITY);
+
+        // comparisons
+        let cmp = |jvm: &mut JVM, op: Op, a: f32, b: f32| {
+            let mut th = thread_with_frame(0, 4);
+            th.push(a);
+            th.push(b);
+            jvm.exec_op(&mut th, op).unwrap();
+            th.pop::<i32>()
+        };
+        assert_eq!(cmp(jvm, Op::Fcmpl, 1.0, 2.0), -1);
+        assert_eq!(cmp(jvm, Op::Fcmpg, 0.0, -0.0), 0);
+        assert_eq!(cmp(jvm, Op::Fcmpl, f32::NAN, 1.0), -1);
+        assert_eq!(cmp(jvm, Op::Fcmpg, f32::NAN, 1.0), 1);
+        let mut th = thread_with_frame(0, 4);
+        th.push(f64::NAN);
+        th.push(0.0f64);
+        jvm.exec_op(&mut th, Op::Dcmpg).unwrap();
+        assert_eq!(th.pop::<i32>(), 1);
+        th.push(-1i64);
+        th.push(1i64);
+        jvm.exec_op(&mut th, Op::Lcmp).unwrap();
+        assert_eq!(th.pop::<i32>(), -1);
+    }
+
+    #[test]
+    fn test_branch() {
+        let mut jvm = test_util::setup();
+        let obj = jvm.alloc_object("java/lang/Object").unwrap();
+
+        let mut call = |name, args: &[JValue]| match test_util::call(&mut jvm, "Branch", name, args)
+        {
+            JValue::Int(v) => v,
+            res => panic!("{res:?}"),
+        };
+        assert_eq!(call("sum", &[JValue::Int(10)]), 45);
+        assert_eq!(call("sum", &[JValue::Int(-1)]), 0);
+        for (x, v) in [(0, -1), (1, 10), (4, 40), (5, -1), (i32::MIN, -1)] {
+            assert_eq!(call("table", &[JValue::Int(x)]), v);
+        }
+        for (x, v) in [(-1000, 1), (0, 2), (1000000, 3), (1, 0)] {
+            assert_eq!(call("lookup", &[JValue::Int(x)]), v);
+        }
+        let (a, b) = (JValue::Long(-1), JValue::Long(1));
+        assert_eq!(call("compare", &[a.clone(), b.clone()]), -1);
+        assert_eq!(call("compare", &[b.clone(), b.clone()]), 0);
+        assert_eq!(call("compare", &[b, a]), 1);
+        let nan = JValue::Double(f64::NAN);
+        assert_eq!(call("less", &[JValue::Double(1.0), JValue::Double(2.0)]), 1);
+        assert_eq!(call("less", &[nan.clone(), JValue::Double(2.0)]), 0);
+        assert_eq!(call("less", &[JValue::Double(1.0), nan]), 0);
+        assert_eq!(call("isNull", &[JValue::Null]), 1);
+        assert_eq!(call("isNull", &[JValue::Ref(obj)]), 0);
+        assert_eq!(call("same", &[JValue::Ref(obj), JValue::Ref(obj)]), 1);
+        assert_eq!(call("same", &[JValue::Ref(obj), JValue::Null]), 0);
+    }
+
+    #[test]
+    fn test_subroutine() {
+        // The code a pre-Java 6 javac emits for finally blocks.
+        #[rustfmt::skip]
+        let code = vec![
+            0xc8, 0x00, 0x00, 0x00, 0x0b, // 0: goto_w 11
+            0x4c,                         // 5: astore_1 (returnAddress)
+            0x84, 0x00, 0x05,             // 6: iinc 0, 5
+            0xa9, 0x01,                   // 9: ret 1
+            0x04,                         // 11: iconst_1
+            0x3b,                         // 12: istore_0
+            0xc9, 0xff, 0xff, 0xff, 0xf8, // 13: jsr_w 5
+            0xa8, 0xff, 0xf3,             // 18: jsr 5
+            0x1a,                         // 21: iload_0
+            0xac,                         // 22: ireturn
+        ];
+        let mut jvm = JVM::new();
+        let mut th = thread_with_code(2, 1, code);
+
+        let res = jvm.run(&mut th).unwrap();
+        assert_eq!(res, ThreadState::Finished(Some(JValue::Int(11))));
+    }
+
+    #[test]
+    fn test_fields() {
+        let mut jvm = test_util::setup();
+        let call = |jvm: &mut JVM, name, args: &[JValue]| match test_util::invoke(
+            jvm, "Fields", name, args,
+        ) {
+            invoke::InvokeResult::Return(v) => Ok(v),
+            invoke::InvokeResult::Exception(ex) => {
+                Err(jvm.heap.get(ex).class.this_class.to_string())
+            }
+        };
+
+        // the superclass is initialized first, once
+        assert_eq!(call(&mut jvm, "trace", &[]), Ok(Some(JValue::Int(12))));
+        assert_eq!(call(&mut jvm, "trace", &[]), Ok(Some(JValue::Int(12))));
+        assert_eq!(
+            call(&mut jvm, "big", &[]),
+            Ok(Some(JValue::Long((1 << 40) + 42)))
+        );
+        // resolved through the superinterface, which runs its <clinit>
+        assert_eq!(call(&mut jvm, "count", &[]), Ok(Some(JValue::Int(42))));
+
+        // an exception in <clinit>, and then the class is erroneous
+        let res = call(&mut jvm, "bad", &[]);
+        assert_eq!(res.unwrap_err(), "java/lang/ExceptionInInitializerError");
+        let res = call(&mut jvm, "bad", &[]);
+        assert_eq!(res.unwrap_err(), "java/lang/NoClassDefFoundError");
+
+        // instance fields, including a hidden field of the superclass
+        let mut th = JThreadContext::default();
+        let res = jvm.new_instance(&mut th, "Fields", "()V", &[]).unwrap();
+        let invoke::InvokeResult::Return(Some(obj)) = res else {
+            panic!("{res:?}");
+        };
+        let this = [obj.clone()];
+        assert_eq!(call(&mut jvm, "sum", &this), Ok(Some(JValue::Int(123))));
+        let s = JValue::Ref(jvm.new_string(&th, "s").unwrap());
+        let args = [
+            obj.clone(),
+            JValue::Long(300),
+            JValue::Double(0.5),
+            s.clone(),
+        ];
+        assert_eq!(call(&mut jvm, "set", &args), Ok(None));
+        assert_eq!(call(&mut jvm, "sum", &this), Ok(Some(JValue::Int(423))));
+        assert_eq!(call(&mut jvm, "z", &this), Ok(Some(JValue::Double(0.5))));
+        assert_eq!(call(&mut jvm, "s", &this), Ok(Some(s)));
+        let res = call(&mut jvm, "nullRead", &[JValue::Null]);
+        assert_eq!(res.unwrap_err(), "java/lang/NullPointerException");
+
+        // narrowed on put
+        for (name, desc, v, narrowed) in [
+            ("b", "B", 300, 44),
+            ("c", "C", -1, 0xffff),
+            ("flag", "Z", 2, 0),
+        ] {
+            jvm.put_static(&mut th, "Fields", name, desc, JValue::Int(v))
+                .unwrap();
+            let res = jvm.get_static(&mut th, "Fields", name, desc).unwrap();
+            assert_eq!(res, JValue::Int(narrowed));
+        }
+    }
+}
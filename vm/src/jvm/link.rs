@@ -0,0 +1,156 @@
+use super::*;
+
+use anyhow::Context;
+
+impl JVM {
+    /// 5.4. Linking
+    ///
+    /// Compute the instance field layout of the class.
+    /// The superclass is linked first (recursively) and the fields declared
+    /// in this class are placed after the slots of the superclass.
+    ///
+    /// Interfaces cannot have instance fields, so only the superclass chain
+    /// is considered.
+    pub fn link_class(&mut self, name: &str) -> anyhow::Result<Rc<ClassLayout>> {
+        let rtinfo = self
+            .class_rt
+            .get(name)
+            .with_context(|| format!("class rtinfo not found: {name}"))?;
+        if let Some(layout) = &rtinfo.layout {
+            return Ok(Rc::clone(layout));
+        }
+
+        let cls = self.get_class(name)?;
+        let base = match &cls.super_class {
+            Some(super_class) => Some(self.link_class(super_class)?),
+            None => None,
+        };
+        let layout = Rc::new(ClassLayout::new(&cls, base.as_deref()));
+
+        let rtinfo = self
+            .class_rt
+            .get_mut(name)
+            .with_context(|| format!("class rtinfo not found: {name}"))?;
+        rtinfo.layout = Some(Rc::clone(&layout));
+
+        Ok(layout)
+    }
+
+    /// Resolve a Fieldref for getfield/putfield to a direct slot.
+    ///
+    /// The field is resolved by 5.4.3.2 and the declaring class is linked.
+    /// The result is cached per constant pool index of the referencing class
+    /// definition.
+    pub fn resolve_instance_field(
+        &mut self,
+        cls: &Rc<JClass>,
+        index: u16,
+    ) -> anyhow::Result<ResolvedField> {
+        let cache = self
+            .cp_cache(cls)
+            .and_then(|cache| cache.fields.get(&index));
+        if let Some(resolved) = cache {
+            return Ok(resolved.clone());
+        }
+
+        let (fcls, fname, fdesc) = cls.constant_pool.get_field(index)?;
+        let (decl, field) = self.resolve_field(&fcls, &fname, &fdesc)?;
+        throw_unless!(
+            field.access_flags & acc_field::STATIC == 0,
+            IncompatibleClassChangeError,
+            "{}.{fname} is static",
+            decl.this_class
+        );
+        let layout = self.link_class(&decl.this_class)?;
+        let slot = *layout
+            .slots
+            .get(&field.name_desc)
+            .with_context(|| format!("slot not found: {}.{fname}", decl.this_class))?;
+        let resolved = ResolvedField {
+            class: Rc::clone(&decl.this_class),
+            field,
+            slot,
+        };
+
+        self.cp_cache_mut(cls)
+            .fields
+            .insert(index, resolved.clone());
+
+        Ok(resolved)
+    }
+}
+
+impl ClassLayout {
+    /// Place the instance fields declared in `cls` after the slots of the
+    /// superclass layout.
+    ///
+    /// Fields are ordered by name_desc so that the layout does not depend on
+    /// HashMap iteration order.
+    fn new(cls: &JClass, base: Option<&ClassLayout>) -> Self {
+        let mut fields: Vec<_> = cls
+            .fields
+            .values()
+            .filter(|f| f.access_flags & acc_field::STATIC == 0)
+            .collect();
+        fields.sort_by(|a, b| a.name_desc.cmp(&b.name_desc));
+
+        let mut slot_count = base.map_or(0, |base| base.slot_count);
+        let mut ref_slots = base.map_or_else(Vec::new, |base| base.ref_slots.clone());
+        let mut slots = HashMap::with_capacity(fields.len());
+        for field in fields {
+            slots.insert(field.name_desc.clone(), slot_count);
+            if field.jtype.is_reference() {
+                ref_slots.push(slot_count);
+            }
+            slot_count += field.jtype.slot_size();
+        }
+
+        Self {
+            slot_count,
+            slots,
+            ref_slots,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use test_util::define_class;
+
+    #[test]
+    fn test_layout() {
+        let mut jvm = JVM::new();
+        jvm.load_native_class(define_class(0, "java/lang/Object", None, &[], &[], &[]));
+        jvm.load_native_class(define_class(
+            0,
+            "A",
+            Some("java/lang/Object"),
+            &[],
+            &[(0, "a", "I"), (0, "b", "J"), (acc_field::STATIC, "s", "D")],
+            &[],
+        ));
+        jvm.load_native_class(define_class(
+            0,
+            "B",
+            Some("A"),
+            &[],
+            &[(0, "a", "I"), (0, "c", "D"), (0, "d", "Ljava/lang/Object;")],
+            &[],
+        ));
+
+        let a = jvm.link_class("A").unwrap();
+        assert_eq!(a.slot_count, 3);
+        assert_eq!(a.slots["aI"], 0);
+        assert_eq!(a.slots["bJ"], 1);
+        assert!(!a.slots.contains_key("sD"));
+
+        let b = jvm.link_class("B").unwrap();
+        assert_eq!(b.slot_count, 3 + 4);
+        // B.a hides A.a
+        assert_eq!(b.slots["aI"], 3);
+        assert_eq!(b.slots["cD"], 4);
+        assert_eq!(b.slots["dLjava/lang/Object;"], 6);
+        assert_eq!(b.ref_slots, [6]);
+    }
+}
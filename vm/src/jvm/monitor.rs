@@ -0,0 +1,319 @@
+//! Object monitors.
+//!
+//! A monitor is inflated (allocated) on the first synchronization on the
+//! object, so an object that is never locked carries only an empty header
+//! slot.
+//!
+//! A thread which cannot enter a monitor blocks, and the scheduler resumes
+//! it when the monitor is free: monitorenter is executed again, and a
+//! synchronized method enters before its first op.
+//! Object.wait releases the monitor and puts the thread in the wait set.
+//! notify moves the threads out of it in the order they waited, and a
+//! notified thread resumes when it reenters the monitor.
+
+use super::*;
+
+/// Identifies the thread owning a monitor.
+pub type ThreadId = u32;
+
+#[derive(Debug, Default)]
+pub struct Monitor {
+    /// The thread holding the monitor.
+    pub owner: Option<ThreadId>,
+    /// Number of times the owner has entered it. (reentrant)
+    pub count: u32,
+    /// Threads in Object.wait, in the order they waited.
+    pub waiters: VecDeque<ThreadId>,
+}
+
+impl JVM {
+    /// The monitor of the object, inflated on first use.
+    pub(super) fn monitor(&mut self, r: JRef) -> &mut Monitor {
+        self.heap
+            .get_mut(r)
+            .monitor
+            .get_or_insert_with(Default::default)
+    }
+
+    /// Whether the thread can enter the monitor without blocking.
+    pub(super) fn can_enter(&self, id: ThreadId, r: JRef) -> bool {
+        match &self.heap.get(r).monitor {
+            Some(monitor) => monitor.owner.is_none_or(|owner| owner == id),
+            None => true,
+        }
+    }
+
+    /// monitorenter. Returns false if another thread owns the monitor.
+    pub(super) fn try_enter(&mut self, id: ThreadId, r: JRef) -> bool {
+        if !self.can_enter(id, r) {
+            return false;
+        }
+        let monitor = self.monitor(r);
+        monitor.owner = Some(id);
+        monitor.count += 1;
+        true
+    }
+
+    /// monitorexit.
+    pub(super) fn exit_monitor(&mut self, id: ThreadId, r: JRef) -> anyhow::Result<()> {
+        let monitor = self.owned_monitor(id, r)?;
+        monitor.count -= 1;
+        if monitor.count == 0 {
+            monitor.owner = None;
+        }
+
+        Ok(())
+    }
+
+    /// The monitor, or IllegalMonitorStateException if the thread does not
+    /// own it.
+    fn owned_monitor(&mut self, id: ThreadId, r: JRef) -> anyhow::Result<&mut Monitor> {
+        let monitor = self.monitor(r);
+        throw_unless!(
+            monitor.owner == Some(id),
+            IllegalMonitorStateException,
+            "current thread is not owner"
+        );
+        Ok(monitor)
+    }
+
+    /// Exit the monitor of a synchronized method before its frame is popped.
+    pub(super) fn exit_frame_monitor(&mut self, th: &mut JThreadContext) -> anyhow::Result<()> {
+        let frame = th.current_frame();
+        if let (Some(r), true) = (frame.monitor, frame.locked) {
+            frame.locked = false;
+            self.exit_monitor(th.id, r)?;
+        }
+
+        Ok(())
+    }
+
+    /// Object.wait: release the monitor and block until notified, or for
+    /// `millis` if not 0.
+    pub(super) fn wait(
+        &mut self,
+        th: &mut JThreadContext,
+        r: JRef,
+        millis: u64,
+    ) -> anyhow::Result<()> {
+        let monitor = self.owned_monitor(th.id, r)?;
+        th.wait_count = monitor.count;
+        monitor.owner = None;
+        monitor.count = 0;
+        monitor.waiters.push_back(th.id);
+        let until = (millis > 0).then(|| self.millis().saturating_add(millis));
+        th.block(BlockReason::Wait(r, until));
+
+        Ok(())
+    }
+
+    /// Object.notify and notifyAll: move the first or all of the waiters
+    /// out of the wait set.
+    pub(super) fn notify(&mut self, id: ThreadId, r: JRef, all: bool) -> anyhow::Result<()> {
+        let monitor = self.owned_monitor(id, r)?;
+        if all {
+            monitor.waiters.clear();
+        } else {
+            monitor.waiters.pop_front();
+        }
+
+        Ok(())
+    }
+
+    /// Whether a waiting thread is notified or timed out, and can reenter
+    /// the monitor.
+    pub(super) fn can_reenter(&self, th: &JThreadContext, r: JRef, until: Option<u64>) -> bool {
+        let Some(monitor) = &self.heap.get(r).monitor else {
+            return false;
+        };
+        let notified = !monitor.waiters.contains(&th.id);
+        let timed_out = until.is_some_and(|until| self.millis() >= until);
+        (notified || timed_out) && monitor.owner.is_none()
+    }
+
+    /// Return from Object.wait: leave the wait set if timed out, and take
+    /// the monitor back with the entry count.
+    pub(super) fn reenter(&mut self, th: &JThreadContext, r: JRef) {
+        let monitor = self.monitor(r);
+        monitor.waiters.retain(|&id| id != th.id);
+        monitor.owner = Some(th.id);
+        monitor.count = th.wait_count;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use test_util::setup;
+
+    #[test]
+    fn test_monitor() {
+        let mut jvm = JVM::new();
+        stdlib::load_core(&mut jvm);
+        let r = jvm.alloc_object("java/lang/Object").unwrap();
+        assert!(jvm.heap.get(r).monitor.is_none());
+
+        // reentrant, and exclusive until the count is back to 0
+        assert!(jvm.try_enter(1, r));
+        assert!(jvm.try_enter(1, r));
+        assert!(!jvm.try_enter(2, r));
+        jvm.exit_monitor(1, r).unwrap();
+        assert!(!jvm.can_enter(2, r));
+        jvm.exit_monitor(1, r).unwrap();
+        assert!(jvm.try_enter(2, r));
+        assert_eq!(jvm.monitor(r).owner, Some(2));
+
+        let err = jvm.exit_monitor(1, r).unwrap_err();
+        let Some(exception::Throw::New { class, .. }) = err.downcast_ref() else {
+            panic!("{err:?}");
+        };
+        assert_eq!(class, "java/lang/IllegalMonitorStateException");
+        assert!(jvm.notify(1, r, false).is_err());
+    }
+
+    /// Run the static method of Sync on the main thread and the threads it
+    /// starts, until they terminate or deadlock.
+    fn run_main(jvm: &mut JVM, name: &str) -> SchedulerState {
+        let mut th = JThreadContext::default();
+        jvm.initialize_class(&mut th, "Sync").unwrap();
+        let cls = jvm.get_class("Sync").unwrap();
+        let method = cls.get_method(name).unwrap();
+        jvm.call_method(&mut th, cls, method, &[]).unwrap();
+        jvm.spawn(th);
+        loop {
+            match jvm.run_for(Budget::default()).unwrap() {
+                SchedulerState::Idle(duration) => std::thread::sleep(duration),
+                SchedulerState::Yielded => {}
+                state => return state,
+            }
+        }
+    }
+
+    fn get_static(jvm: &mut JVM, name: &str, desc: &str) -> JValue {
+        let mut th = JThreadContext::default();
+        jvm.get_static(&mut th, "Sync", name, desc).unwrap()
+    }
+
+    fn log(jvm: &mut JVM) -> Vec<i32> {
+        let JValue::Ref(log) = get_static(jvm, "log", "[I") else {
+            panic!();
+        };
+        let array::JArray::Int(a) = jvm.heap.get(log).elements() else {
+            panic!();
+        };
+        a.to_vec()
+    }
+
+    #[test]
+    fn test_synchronized() {
+        let mut jvm = setup();
+        // a synchronized method, a synchronized block and a static
+        // synchronized method, each yielding while it holds the monitor
+        assert_eq!(
+            run_main(&mut jvm, "exclusion()V"),
+            SchedulerState::Terminated
+        );
+        assert_eq!(get_static(&mut jvm, "ok", "Z"), JValue::Int(1));
+
+        let mut th = JThreadContext::default();
+        let res = jvm.call_method_by_name(&mut th, "Sync", "reentrancy", &[]);
+        assert_eq!(res.unwrap(), InvokeResult::Return(Some(JValue::Int(1))));
+        let res = jvm.call_method_by_name(&mut th, "Sync", "illegalState", &[]);
+        assert_eq!(res.unwrap(), InvokeResult::Return(Some(JValue::Int(3))));
+        // every monitor is released
+        let JValue::Ref(lock) = get_static(&mut jvm, "lock", "Ljava/lang/Object;") else {
+            panic!();
+        };
+        assert_eq!(jvm.monitor(lock).owner, None);
+        let class = jvm.class_object(&th, "Sync").unwrap();
+        assert_eq!(jvm.monitor(class).owner, None);
+
+        let mut jvm = setup();
+        assert_eq!(
+            run_main(&mut jvm, "releaseOnThrow()V"),
+            SchedulerState::Terminated
+        );
+        assert_eq!(get_static(&mut jvm, "ok", "Z"), JValue::Int(1));
+    }
+
+    #[test]
+    fn test_wait_notify() {
+        let mut jvm = setup();
+        assert_eq!(
+            run_main(&mut jvm, "notifyOrder()V"),
+            SchedulerState::Terminated
+        );
+        assert_eq!(log(&mut jvm), [1, 2, 3, 0]);
+
+        let mut jvm = setup();
+        assert_eq!(
+            run_main(&mut jvm, "notifyAllWaiters()V"),
+            SchedulerState::Terminated
+        );
+        assert_eq!(get_static(&mut jvm, "ok", "Z"), JValue::Int(1));
+        let mut log = log(&mut jvm);
+        log.sort();
+        assert_eq!(log, [0, 1, 2, 3]);
+
+        let mut jvm = setup();
+        let start = jvm.clock.monotonic();
+        assert_eq!(
+            run_main(&mut jvm, "timedWait()V"),
+            SchedulerState::Terminated
+        );
+        assert!(jvm.clock.monotonic() - start >= std::time::Duration::from_millis(20));
+        assert_eq!(get_static(&mut jvm, "ok", "Z"), JValue::Int(1));
+
+        let mut jvm = setup();
+        assert_eq!(
+            run_main(&mut jvm, "lonelyWait()V"),
+            SchedulerState::Deadlocked
+        );
+    }
+
+    #[test]
+    fn test_block_in_host() {
+        let mut jvm = setup();
+        let mut th = JThreadContext::default();
+        let is_illegal_state = |jvm: &JVM, res: anyhow::Result<InvokeResult>| match res {
+            Ok(InvokeResult::Exception(ex)) => {
+                jvm.heap.get(ex).class.this_class.as_str()
+                    == "java/lang/IllegalThreadStateException"
+            }
+            _ => false,
+        };
+
+        // another thread holds the monitor of the class
+        let mut holder = JThreadContext::new(2, "holder");
+        jvm.initialize_class(&mut holder, "Sync").unwrap();
+        let cls = jvm.get_class("Sync").unwrap();
+        let method = cls.get_method("addStatic()V").unwrap();
+        jvm.call_method(&mut holder, cls, method, &[]).unwrap();
+        jvm.spawn(holder);
+        let res = jvm.run_for(Budget::instructions(2)).unwrap();
+        assert_eq!(res, SchedulerState::Yielded);
+        let res = jvm.call_method_by_name(&mut th, "Sync", "addStatic", &[]);
+        assert!(is_illegal_state(&jvm, res));
+        assert!(th.frames.is_empty());
+        let res = jvm.run_for(Budget::default()).unwrap();
+        assert_eq!(res, SchedulerState::Terminated);
+        assert_eq!(get_static(&mut jvm, "count", "I"), JValue::Int(1));
+
+        // a wait nobody notifies throws with the monitor taken back, and the
+        // synchronized block releases it
+        let res = jvm.call_method_by_name(&mut th, "Sync", "lonelyWait", &[]);
+        assert!(is_illegal_state(&jvm, res));
+        let JValue::Ref(lock) = get_static(&mut jvm, "lock", "Ljava/lang/Object;") else {
+            panic!();
+        };
+        assert_eq!(jvm.monitor(lock).owner, None);
+        assert!(jvm.monitor(lock).waiters.is_empty());
+
+        // a timeout passes at once on a virtual clock
+        jvm.set_clock(clock::VirtualClock::default());
+        let res = jvm.call_method_by_name(&mut th, "Sync", "timedWait", &[]);
+        assert_eq!(res.unwrap(), InvokeResult::Return(None));
+        assert_eq!(get_static(&mut jvm, "ok", "Z"), JValue::Int(1));
+        assert_eq!(jvm.millis(), 20);
+    }
+}
@@ -1,10 +1,12 @@
+use super::ExceptionTableEntry;
+
 use anyhow::{Context, Ok};
 use bytes::Buf;
+use std::{collections::HashMap, mem::Discriminant, sync::OnceLock};
 
 // https://docs.oracle.com/javase/specs/jvms/se7/html/jvms-6.html
 
-#[allow(dead_code)]
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Op {
     /// Do nothing.
     Nop,
@@ -41,19 +43,19 @@ pub enum Op {
         index: u16,
     },
     Iload {
-        index: u8,
+        index: u16,
     },
     Lload {
-        index: u8,
+        index: u16,
     },
     Fload {
-        index: u8,
+        index: u16,
     },
     Dload {
-        index: u8,
+        index: u16,
     },
     Aload {
-        index: u8,
+        index: u16,
     },
     Iload0,
     Iload1,
@@ -84,19 +86,19 @@ pub enum Op {
     Caload,
     Saload,
     Istore {
-        index: u8,
+        index: u16,
     },
     Lstore {
-        index: u8,
+        index: u16,
     },
     Fstore {
-        index: u8,
+        index: u16,
     },
     Dstore {
-        index: u8,
+        index: u16,
     },
     Astore {
-        index: u8,
+        index: u16,
     },
     Istore0,
     Istore1,
@@ -172,8 +174,8 @@ pub enum Op {
     Ixor,
     Lxor,
     Iinc {
-        index: u8,
-        constant: i8,
+        index: u16,
+        constant: i16,
     },
     I2L,
     I2F,
@@ -244,7 +246,7 @@ pub enum Op {
         branch: i16,
     },
     Ret {
-        index: u8,
+        index: u16,
     },
     Tableswitch {
         default: i32,
@@ -318,9 +320,6 @@ pub enum Op {
     },
     Monitorenter,
     Monitorexit,
-    Wide {
-        modified_opcode: Box<Op>,
-    },
     Multianewarray {
         index: u16,
         dimensions: u8,
@@ -339,38 +338,247 @@ pub enum Op {
     },
 }
 
-pub fn next_op(bcode: &[u8]) -> anyhow::Result<(Op, usize)> {
+/// Decode an instruction at `pc`.
+/// `code` is the whole code of the method, since tableswitch and
+/// lookupswitch are aligned to the start of the code.
+///
+/// wide is decoded as the modified instruction with a 16-bit index.
+///
+/// Returns (op, length).
+pub fn next_op(code: &[u8], pc: usize) -> anyhow::Result<(Op, usize)> {
+    let bcode = code.get(pc..).context("invalid pc")?;
     let mut rest = bcode;
     let opcode = rest.try_get_u8().context("invalid pc")?;
 
     let op = match opcode {
         0x00 => Op::Nop,
         0x01 => Op::AconstNull,
+        0x02 => Op::IconstM1,
         0x03 => Op::Iconst0,
+        0x04 => Op::Iconst1,
+        0x05 => Op::Iconst2,
+        0x06 => Op::Iconst3,
+        0x07 => Op::Iconst4,
+        0x08 => Op::Iconst5,
+        0x09 => Op::Lconst0,
+        0x0a => Op::Lconst1,
+        0x0b => Op::Fconst0,
+        0x0c => Op::Fconst1,
+        0x0d => Op::Fconst2,
+        0x0e => Op::Dconst0,
+        0x0f => Op::Dconst1,
         0x10 => Op::Bipush {
             byte: rest.try_get_u8().context("invalid op")?,
         },
+        0x11 => Op::Sipush {
+            bytes: rest.try_get_u16().context("invalid op")?,
+        },
         0x12 => Op::Ldc {
             index: rest.try_get_u8().context("invalid op")?,
         },
+        0x13 => Op::LdcW {
+            index: rest.try_get_u16().context("invalid op")?,
+        },
+        0x14 => Op::Ldc2W {
+            index: rest.try_get_u16().context("invalid op")?,
+        },
+        0x15..=0x19 | 0x36..=0x3a | 0xa9 => {
+            let index = rest.try_get_u8().context("invalid op")? as u16;
+            local_op(opcode, index)?
+        }
+        0x1a => Op::Iload0,
         0x1b => Op::Iload1,
+        0x1c => Op::Iload2,
+        0x1d => Op::Iload3,
+        0x1e => Op::Lload0,
+        0x1f => Op::Lload1,
+        0x20 => Op::Lload2,
+        0x21 => Op::Lload3,
+        0x22 => Op::Fload0,
+        0x23 => Op::Fload1,
+        0x24 => Op::Fload2,
+        0x25 => Op::Fload3,
+        0x26 => Op::Dload0,
+        0x27 => Op::Dload1,
+        0x28 => Op::Dload2,
+        0x29 => Op::Dload3,
         0x2a => Op::Aload0,
+        0x2b => Op::Aload1,
+        0x2c => Op::Aload2,
+        0x2d => Op::Aload3,
+        0x2e => Op::Iaload,
+        0x2f => Op::Laload,
+        0x30 => Op::Faload,
+        0x31 => Op::Daload,
         0x32 => Op::Aaload,
-        0x3c => Op::Astore1,
+        0x33 => Op::Baload,
+        0x34 => Op::Caload,
+        0x35 => Op::Saload,
+        0x3b => Op::Istore0,
+        0x3c => Op::Istore1,
+        0x3d => Op::Istore2,
+        0x3e => Op::Istore3,
+        0x3f => Op::Lstore0,
+        0x40 => Op::Lstore1,
+        0x41 => Op::Lstore2,
+        0x42 => Op::Lstore3,
+        0x43 => Op::Fstore0,
+        0x44 => Op::Fstore1,
+        0x45 => Op::Fstore2,
+        0x46 => Op::Fstore3,
+        0x47 => Op::Dstore0,
+        0x48 => Op::Dstore1,
+        0x49 => Op::Dstore2,
+        0x4a => Op::Dstore3,
+        0x4b => Op::Astore0,
+        0x4c => Op::Astore1,
+        0x4d => Op::Astore2,
+        0x4e => Op::Astore3,
+        0x4f => Op::Iastore,
+        0x50 => Op::Lastore,
+        0x51 => Op::Fastore,
+        0x52 => Op::Dastore,
+        0x53 => Op::Aastore,
+        0x54 => Op::Bastore,
+        0x55 => Op::Castore,
+        0x56 => Op::Sastore,
+        0x57 => Op::Pop,
+        0x58 => Op::Pop2,
+        0x59 => Op::Dup,
+        0x5a => Op::DupX1,
+        0x5b => Op::DupX2,
+        0x5c => Op::Dup2,
+        0x5d => Op::Dup2X1,
+        0x5e => Op::Dup2X2,
+        0x5f => Op::Swap,
+        0x60 => Op::Iadd,
+        0x61 => Op::Ladd,
+        0x62 => Op::Fadd,
+        0x63 => Op::Dadd,
+        0x64 => Op::Isub,
+        0x65 => Op::Lsub,
+        0x66 => Op::Fsub,
+        0x67 => Op::Dsub,
+        0x68 => Op::Imul,
+        0x69 => Op::Lmul,
+        0x6a => Op::Fmul,
+        0x6b => Op::Dmul,
+        0x6c => Op::Idiv,
+        0x6d => Op::Ldiv,
+        0x6e => Op::Fdiv,
+        0x6f => Op::Ddiv,
+        0x70 => Op::Irem,
+        0x71 => Op::Lrem,
+        0x72 => Op::Frem,
+        0x73 => Op::Drem,
+        0x74 => Op::Ineg,
+        0x75 => Op::Lneg,
+        0x76 => Op::Fneg,
+        0x77 => Op::Dneg,
+        0x78 => Op::Ishl,
+        0x79 => Op::Lshl,
+        0x7a => Op::Ishr,
+        0x7b => Op::Lshr,
+        0x7c => Op::Iushr,
+        0x7d => Op::Lushr,
+        0x7e => Op::Iand,
+        0x7f => Op::Land,
+        0x80 => Op::Ior,
+        0x81 => Op::Lor,
+        0x82 => Op::Ixor,
+        0x83 => Op::Lxor,
         0x84 => Op::Iinc {
-            index: rest.try_get_u8().context("invalid op")?,
-            constant: rest.try_get_i8().context("invalid op")?,
-        },
-        0xa2 => Op::IfIcmpge {
-            branch: rest.try_get_i16().context("invalid op")?,
-        },
-        0xa7 => Op::Goto {
-            branch: rest.try_get_i16().context("invalid op")?,
+            index: rest.try_get_u8().context("invalid op")? as u16,
+            constant: rest.try_get_i8().context("invalid op")? as i16,
         },
+        0x85 => Op::I2L,
+        0x86 => Op::I2F,
+        0x87 => Op::I2D,
+        0x88 => Op::L2I,
+        0x89 => Op::L2F,
+        0x8a => Op::L2D,
+        0x8b => Op::F2I,
+        0x8c => Op::F2L,
+        0x8d => Op::F2D,
+        0x8e => Op::D2I,
+        0x8f => Op::D2L,
+        0x90 => Op::D2F,
+        0x91 => Op::I2B,
+        0x92 => Op::I2C,
+        0x93 => Op::I2S,
+        0x94 => Op::Lcmp,
+        0x95 => Op::Fcmpl,
+        0x96 => Op::Fcmpg,
+        0x97 => Op::Dcmpl,
+        0x98 => Op::Dcmpg,
+        0x99..=0xa8 | 0xc6 | 0xc7 => {
+            let branch = rest.try_get_i16().context("invalid op")?;
+            branch_op(opcode, branch)
+        }
+        0xaa => {
+            let padding = switch_padding(pc);
+            anyhow::ensure!(rest.len() >= padding, "invalid op");
+            rest.advance(padding);
+            let default = rest.try_get_i32().context("invalid op")?;
+            let low = rest.try_get_i32().context("invalid op")?;
+            let high = rest.try_get_i32().context("invalid op")?;
+            anyhow::ensure!(low <= high, "invalid tableswitch: low={low} high={high}");
+            let count = (high as i64 - low as i64 + 1) as usize;
+            anyhow::ensure!(rest.len() >= count * 4, "invalid op");
+            let mut jump_offsets = Vec::with_capacity(count);
+            for _ in 0..count {
+                jump_offsets.push(rest.try_get_i32().context("invalid op")?);
+            }
+            Op::Tableswitch {
+                default,
+                low,
+                high,
+                jump_offsets,
+            }
+        }
+        0xab => {
+            let padding = switch_padding(pc);
+            anyhow::ensure!(rest.len() >= padding, "invalid op");
+            rest.advance(padding);
+            let default = rest.try_get_i32().context("invalid op")?;
+            let npairs = rest.try_get_i32().context("invalid op")?;
+            anyhow::ensure!(npairs >= 0, "invalid lookupswitch: npairs={npairs}");
+            anyhow::ensure!(rest.len() >= npairs as usize * 8, "invalid op");
+            let mut match_offsets = Vec::with_capacity(npairs as usize);
+            for _ in 0..npairs {
+                let m = rest.try_get_i32().context("invalid op")?;
+                let offset = rest.try_get_i32().context("invalid op")?;
+                match_offsets.push((m, offset));
+            }
+            // executed by binary search
+            anyhow::ensure!(
+                match_offsets.windows(2).all(|w| w[0].0 < w[1].0),
+                "invalid lookupswitch: keys are not sorted"
+            );
+            Op::Lookupswitch {
+                default,
+                npairs,
+                match_offsets,
+            }
+        }
+        0xac => Op::Ireturn,
+        0xad => Op::Lreturn,
+        0xae => Op::Freturn,
+        0xaf => Op::Dreturn,
+        0xb0 => Op::Areturn,
         0xb1 => Op::Return,
         0xb2 => Op::GetStatic {
             index: rest.try_get_u16().context("invalid op")?,
         },
+        0xb3 => Op::PutStatic {
+            index: rest.try_get_u16().context("invalid op")?,
+        },
+        0xb4 => Op::GetField {
+            index: rest.try_get_u16().context("invalid op")?,
+        },
+        0xb5 => Op::PutField {
+            index: rest.try_get_u16().context("invalid op")?,
+        },
         0xb6 => {
             let index = rest.try_get_u16().context("invalid op")?;
             Op::InvokeVirtual { index }
@@ -383,9 +591,418 @@ pub fn next_op(bcode: &[u8]) -> anyhow::Result<(Op, usize)> {
             let index = rest.try_get_u16().context("invalid op")?;
             Op::InvokeStatic { index }
         }
+        0xb9 => {
+            let index = rest.try_get_u16().context("invalid op")?;
+            let count = rest.try_get_u8().context("invalid op")?;
+            // must be zero
+            let _ = rest.try_get_u8().context("invalid op")?;
+            Op::Invokeinterface { index, count }
+        }
+        0xba => {
+            let index = rest.try_get_u16().context("invalid op")?;
+            // must be zero
+            let _ = rest.try_get_u16().context("invalid op")?;
+            Op::Invokedynamic { index }
+        }
+        0xbb => Op::New {
+            index: rest.try_get_u16().context("invalid op")?,
+        },
+        0xbc => Op::Newarray {
+            atype: rest.try_get_u8().context("invalid op")?,
+        },
+        0xbd => Op::Anewarray {
+            index: rest.try_get_u16().context("invalid op")?,
+        },
         0xbe => Op::Arraylength,
+        0xbf => Op::Athrow,
+        0xc0 => Op::Checkcast {
+            index: rest.try_get_u16().context("invalid op")?,
+        },
+        0xc1 => Op::Instanceof {
+            index: rest.try_get_u16().context("invalid op")?,
+        },
+        0xc2 => Op::Monitorenter,
+        0xc3 => Op::Monitorexit,
+        0xc4 => {
+            let opcode = rest.try_get_u8().context("invalid op")?;
+            let index = rest.try_get_u16().context("invalid op")?;
+            if opcode == 0x84 {
+                Op::Iinc {
+                    index,
+                    constant: rest.try_get_i16().context("invalid op")?,
+                }
+            } else {
+                local_op(opcode, index)?
+            }
+        }
+        0xc5 => {
+            let index = rest.try_get_u16().context("invalid op")?;
+            let dimensions = rest.try_get_u8().context("invalid op")?;
+            anyhow::ensure!(dimensions >= 1, "invalid multianewarray: dimensions=0");
+            Op::Multianewarray { index, dimensions }
+        }
+        0xc8 => Op::GotoW {
+            branch: rest.try_get_i32().context("invalid op")?,
+        },
+        0xc9 => Op::JsrW {
+            branch: rest.try_get_i32().context("invalid op")?,
+        },
         _ => anyhow::bail!("unsupported opcode: 0x{opcode:02x}"),
     };
 
     Ok((op, bcode.len() - rest.len()))
 }
+
+/// The code of a method, decoded once when the class is loaded.
+///
+/// Decoding checks the structural constraints which the interpreter relies
+/// on (4.9.1): every op is valid, the last op ends at the end of the code,
+/// every branch target, exception handler and exception range boundary is
+/// the start of an op, and the keys of every lookupswitch are sorted. This
+/// is a structural check only: the types of the operand stack and locals
+/// are not checked.
+#[derive(Debug, Default)]
+pub struct DecodedCode {
+    /// The ops and their pc, in the order of the code.
+    ops: Vec<(u32, Op)>,
+    /// Index in `ops` of the op starting at each pc. (NO_OP inside an op)
+    index: Box<[u32]>,
+}
+
+impl DecodedCode {
+    const NO_OP: u32 = u32::MAX;
+
+    /// Decode and check the code.
+    pub fn decode(code: &[u8], exception_table: &[ExceptionTableEntry]) -> anyhow::Result<Self> {
+        let mut ops = Vec::new();
+        let mut pc = 0;
+        while pc < code.len() {
+            let (op, len) = next_op(code, pc).with_context(|| format!("pc={pc}"))?;
+            ops.push((pc as u32, op));
+            pc += len;
+        }
+        let decoded = Self::from_ops(ops, code.len())?;
+
+        for &(pc, ref op) in decoded.ops.iter() {
+            for target in op.branch_targets(pc) {
+                anyhow::ensure!(
+                    decoded.is_op_start(target),
+                    "pc={pc}: invalid branch target {target}"
+                );
+            }
+        }
+        for e in exception_table {
+            let (start, end, handler) = (e.start_pc as i64, e.end_pc as i64, e.handler_pc as i64);
+            anyhow::ensure!(
+                start < end
+                    && decoded.is_op_start(start)
+                    && (decoded.is_op_start(end) || end == code.len() as i64)
+                    && decoded.is_op_start(handler),
+                "invalid exception handler: {e:?}"
+            );
+        }
+
+        Ok(decoded)
+    }
+
+    /// The code from ops already decoded and checked. (a class image)
+    /// `code_length` is the length of the original code in bytes.
+    pub fn from_ops(ops: Vec<(u32, Op)>, code_length: usize) -> anyhow::Result<Self> {
+        let mut index = vec![Self::NO_OP; code_length].into_boxed_slice();
+        let mut end = 0;
+        for (i, &(pc, _)) in ops.iter().enumerate() {
+            anyhow::ensure!(
+                pc as usize >= end && (pc as usize) < code_length,
+                "invalid op order: pc={pc}"
+            );
+            index[pc as usize] = i as u32;
+            end = pc as usize + 1;
+        }
+
+        Ok(Self { ops, index })
+    }
+
+    /// The op at `pc` and the pc of the next op.
+    pub fn op_at(&self, pc: u32) -> anyhow::Result<(&Op, u32)> {
+        let i = *self
+            .index
+            .get(pc as usize)
+            .filter(|&&i| i != Self::NO_OP)
+            .with_context(|| format!("invalid pc: {pc}"))? as usize;
+        let next_pc = match self.ops.get(i + 1) {
+            Some(&(next_pc, _)) => next_pc,
+            None => self.index.len() as u32,
+        };
+
+        Ok((&self.ops[i].1, next_pc))
+    }
+
+    /// The ops and their pc.
+    pub fn ops(&self) -> &[(u32, Op)] {
+        &self.ops
+    }
+
+    /// Length of the original code in bytes.
+    pub fn code_length(&self) -> usize {
+        self.index.len()
+    }
+
+    fn is_op_start(&self, pc: i64) -> bool {
+        usize::try_from(pc)
+            .ok()
+            .and_then(|pc| self.index.get(pc))
+            .is_some_and(|&i| i != Self::NO_OP)
+    }
+}
+
+impl Op {
+    /// The opcode of the op. (the opcode of the base op for wide)
+    pub fn opcode(&self) -> u8 {
+        // every opcode which decodes with zero operands (or ones, for the
+        // dimensions of multianewarray), by variant
+        static OPCODES: OnceLock<HashMap<Discriminant<Op>, u8>> = OnceLock::new();
+        let opcodes = OPCODES.get_or_init(|| {
+            let decode = |opcode, operand| {
+                let mut code = [operand; 32];
+                code[0] = opcode;
+                next_op(&code, 0).ok()
+            };
+            (0..=u8::MAX)
+                .filter_map(|opcode| {
+                    let (op, _) = decode(opcode, 0).or_else(|| decode(opcode, 1))?;
+                    Some((std::mem::discriminant(&op), opcode))
+                })
+                .collect()
+        });
+
+        opcodes[&std::mem::discriminant(self)]
+    }
+
+    /// The addresses the op may jump to. (ret returns to the op after a
+    /// jsr, which is always the start of an op)
+    fn branch_targets(&self, pc: u32) -> Vec<i64> {
+        let pc = pc as i64;
+        match self {
+            Op::Ifeq { branch }
+            | Op::Ifne { branch }
+            | Op::Iflt { branch }
+            | Op::Ifge { branch }
+            | Op::Ifgt { branch }
+            | Op::Ifle { branch }
+            | Op::IfIcmpeq { branch }
+            | Op::IfIcmpne { branch }
+            | Op::IfIcmplt { branch }
+            | Op::IfIcmpge { branch }
+            | Op::IfIcmpgt { branch }
+            | Op::IfIcmple { branch }
+            | Op::IfAcmpeq { branch }
+            | Op::IfAcmpne { branch }
+            | Op::Goto { branch }
+            | Op::Jsr { branch }
+            | Op::Ifnull { branch }
+            | Op::Ifnonnull { branch } => vec![pc + *branch as i64],
+            Op::GotoW { branch } | Op::JsrW { branch } => vec![pc + *branch as i64],
+            Op::Tableswitch {
+                default,
+                jump_offsets,
+                ..
+            } => std::iter::once(default)
+                .chain(jump_offsets)
+                .map(|&offset| pc + offset as i64)
+                .collect(),
+            Op::Lookupswitch {
+                default,
+                match_offsets,
+                ..
+            } => std::iter::once(default)
+                .chain(match_offsets.iter().map(|(_, offset)| offset))
+                .map(|&offset| pc + offset as i64)
+                .collect(),
+            _ => vec![],
+        }
+    }
+}
+
+/// Load, store and ret which take a local variable index.
+/// (the index is 16-bit if modified by wide)
+pub(super) fn local_op(opcode: u8, index: u16) -> anyhow::Result<Op> {
+    let op = match opcode {
+        0x15 => Op::Iload { index },
+        0x16 => Op::Lload { index },
+        0x17 => Op::Fload { index },
+        0x18 => Op::Dload { index },
+        0x19 => Op::Aload { index },
+        0x36 => Op::Istore { index },
+        0x37 => Op::Lstore { index },
+        0x38 => Op::Fstore { index },
+        0x39 => Op::Dstore { index },
+        0x3a => Op::Astore { index },
+        0xa9 => Op::Ret { index },
+        _ => anyhow::bail!("invalid wide opcode: 0x{opcode:02x}"),
+    };
+
+    Ok(op)
+}
+
+/// Branch instructions with a 16-bit offset.
+pub(super) fn branch_op(opcode: u8, branch: i16) -> Op {
+    match opcode {
+        0x99 => Op::Ifeq { branch },
+        0x9a => Op::Ifne { branch },
+        0x9b => Op::Iflt { branch },
+        0x9c => Op::Ifge { branch },
+        0x9d => Op::Ifgt { branch },
+        0x9e => Op::Ifle { branch },
+        0x9f => Op::IfIcmpeq { branch },
+        0xa0 => Op::IfIcmpne { branch },
+        0xa1 => Op::IfIcmplt { branch },
+        0xa2 => Op::IfIcmpge { branch },
+        0xa3 => Op::IfIcmpgt { branch },
+        0xa4 => Op::IfIcmple { branch },
+        0xa5 => Op::IfAcmpeq { branch },
+        0xa6 => Op::IfAcmpne { branch },
+        0xa7 => Op::Goto { branch },
+        0xa8 => Op::Jsr { branch },
+        0xc6 => Op::Ifnull { branch },
+        0xc7 => Op::Ifnonnull { branch },
+        _ => unreachable!(),
+    }
+}
+
+/// 0-3 bytes of padding after tableswitch/lookupswitch opcode,
+/// so that the operands start at an address that is a multiple of 4.
+fn switch_padding(pc: usize) -> usize {
+    (4 - (pc + 1) % 4) % 4
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_decode_switch_padding() {
+        // [0] nop
+        // [1] tableswitch (2 bytes padding) default=+100 low=1 high=2 (+10, +20)
+        let code = [
+            0x00, 0xaa, 0, 0, 0, 0, 0, 100, 0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0, 10, 0, 0, 0, 20,
+        ];
+        let (op, len) = next_op(&code, 1).unwrap();
+        assert_eq!(len, code.len() - 1);
+        let Op::Tableswitch {
+            default,
+            low,
+            high,
+            jump_offsets,
+        } = op
+        else {
+            panic!("{op:?}");
+        };
+        assert_eq!((default, low, high), (100, 1, 2));
+        assert_eq!(jump_offsets, [10, 20]);
+
+        // [3] lookupswitch (no padding) default=-1 npairs=1 (7: +8)
+        let code = [
+            0, 0, 0, 0xab, 0xff, 0xff, 0xff, 0xff, 0, 0, 0, 1, 0, 0, 0, 7, 0, 0, 0, 8,
+        ];
+        let (op, len) = next_op(&code, 3).unwrap();
+        assert_eq!(len, code.len() - 3);
+        let Op::Lookupswitch {
+            default,
+            match_offsets,
+            ..
+        } = op
+        else {
+            panic!("{op:?}");
+        };
+        assert_eq!(default, -1);
+        assert_eq!(match_offsets, [(7, 8)]);
+
+        // [0] lookupswitch (3 bytes padding) default=+1 npairs=2 (1: +2, 2: +3)
+        let mut code = [
+            0xab, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0, 2, 0, 0, 0, 3,
+        ];
+        assert!(next_op(&code, 0).is_ok());
+        // the keys must be sorted, without duplicates
+        code[15] = 3;
+        assert!(next_op(&code, 0).is_err());
+        code[15] = 2;
+        assert!(next_op(&code, 0).is_err());
+    }
+
+    #[test]
+    fn test_decode_wide() {
+        // wide iload 0x0102
+        let (op, len) = next_op(&[0xc4, 0x15, 0x01, 0x02], 0).unwrap();
+        assert_eq!(len, 4);
+        assert!(matches!(op, Op::Iload { index: 0x0102 }));
+        // wide iinc 0x0102 -2
+        let (op, len) = next_op(&[0xc4, 0x84, 0x01, 0x02, 0xff, 0xfe], 0).unwrap();
+        assert_eq!(len, 6);
+        assert!(matches!(
+            op,
+            Op::Iinc {
+                index: 0x0102,
+                constant: -2
+            }
+        ));
+        // wide with a non-local opcode
+        assert!(next_op(&[0xc4, 0x60, 0x00, 0x00], 0).is_err());
+    }
+
+    #[test]
+    fn test_decode_all_classes() {
+        let bins = crate::res::MC_CLASS_FILES
+            .iter()
+            .chain(crate::res::SAMPLE_CLASS_FILES);
+        for bin in bins {
+            let cls = crate::jvm::parse::parse_class_file(bin).unwrap();
+            for m in cls.methods.values() {
+                let crate::jvm::MethodBody::Java(code) = &m.method_body else {
+                    continue;
+                };
+                // decoded and checked by the parser
+                let mut end = 0;
+                for (pc, op) in code.ops.ops() {
+                    assert_eq!(*pc, end);
+                    let (at, next_pc) = code.ops.op_at(*pc).unwrap();
+                    assert_eq!(at, op);
+                    op.opcode();
+                    end = next_pc;
+                }
+                assert_eq!(end as usize, code.ops.code_length());
+            }
+        }
+    }
+
+    #[test]
+    fn test_decoded_code() {
+        // [0] goto +3
+        // [3] nop
+        // [4] return
+        let code = [0xa7, 0x00, 0x03, 0x00, 0xb1];
+        let decoded = DecodedCode::decode(&code, &[]).unwrap();
+        assert_eq!(decoded.op_at(0).unwrap(), (&Op::Goto { branch: 3 }, 3));
+        assert!(decoded.op_at(1).is_err());
+        assert_eq!(decoded.op_at(4).unwrap(), (&Op::Return, 5));
+        assert_eq!(Op::Return.opcode(), 0xb1);
+        assert_eq!(Op::Iload { index: 0x0102 }.opcode(), 0x15);
+
+        // a branch into an op, and an op cut off by the end
+        assert!(DecodedCode::decode(&[0xa7, 0x00, 0x02, 0x00, 0xb1], &[]).is_err());
+        assert!(DecodedCode::decode(&[0x00, 0x10], &[]).is_err());
+        // multianewarray #1 with 1 and 0 dimensions
+        assert!(DecodedCode::decode(&[0xc5, 0x00, 0x01, 0x01, 0xb0], &[]).is_ok());
+        assert!(DecodedCode::decode(&[0xc5, 0x00, 0x01, 0x00, 0xb0], &[]).is_err());
+
+        let entry = |start_pc, end_pc, handler_pc| ExceptionTableEntry {
+            start_pc,
+            end_pc,
+            handler_pc,
+            catch_type: 0,
+        };
+        assert!(DecodedCode::decode(&code, &[entry(3, 5, 4)]).is_ok());
+        assert!(DecodedCode::decode(&code, &[entry(1, 5, 4)]).is_err());
+        assert!(DecodedCode::decode(&code, &[entry(3, 3, 4)]).is_err());
+        assert!(DecodedCode::decode(&code, &[entry(3, 5, 6)]).is_err());
+    }
+}
@@ -199,7 +199,7 @@ enum ConstInfoRaw {
         descriptor_index: u16,
     },
     Utf8 {
-        bytes: String,
+        chars: Rc<[u16]>,
     },
 }
 
@@ -288,11 +288,10 @@ fn parse_cp_info(mut p: &[u8]) -> anyhow::Result<(&[u8], ConstantPool)> {
                 let length = p.try_get_u16()? as usize;
                 anyhow::ensure!(p.len() >= length);
                 let bytes = p.copy_to_bytes(length);
-                let s = str::from_utf8(&bytes)?;
                 (
                     1,
                     ConstInfoRaw::Utf8 {
-                        bytes: s.to_string(),
+                        chars: decode_modified_utf8(&bytes)?.into(),
                     },
                 )
             }
@@ -312,6 +311,32 @@ fn parse_cp_info(mut p: &[u8]) -> anyhow::Result<(&[u8], ConstantPool)> {
     Ok((p, pool))
 }
 
+/// 4.4.7. The CONSTANT_Utf8_info Structure
+/// Modified UTF-8: NUL is encoded in two bytes and a supplementary character
+/// as its surrogate pair, three bytes each. The result is UTF-16 code units,
+/// so an unpaired surrogate is kept as is.
+fn decode_modified_utf8(bytes: &[u8]) -> anyhow::Result<Vec<u16>> {
+    let mut chars = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let b = bytes[i] as u16;
+        let cont = |n: usize| match bytes.get(i + n) {
+            Some(&c) if c & 0xc0 == 0x80 => Ok((c & 0x3f) as u16),
+            _ => anyhow::bail!("invalid modified UTF-8 at {i}"),
+        };
+        let (c, len) = match b {
+            0x01..=0x7f => (b, 1),
+            0xc0..=0xdf => (((b & 0x1f) << 6) | cont(1)?, 2),
+            0xe0..=0xef => (((b & 0x0f) << 12) | (cont(1)? << 6) | cont(2)?, 3),
+            _ => anyhow::bail!("invalid modified UTF-8 at {i}"),
+        };
+        chars.push(c);
+        i += len;
+    }
+
+    Ok(chars)
+}
+
 fn resolve_cp(
     pool_raw: &[ConstInfoRaw],
     pool: &mut [ConstInfo],
@@ -381,9 +406,10 @@ fn resolve_cp(
         }
 
         ConstInfoRaw::String { string_index } => {
-            if let ConstInfo::Utf8 { bytes } = resolve_cp(pool_raw, pool, *string_index as usize)? {
+            // the code units, not the name text of the Utf8
+            if let Some(ConstInfoRaw::Utf8 { chars }) = pool_raw.get(*string_index as usize) {
                 ConstInfo::String {
-                    string: Rc::clone(&bytes),
+                    string: Rc::clone(chars),
                 }
             } else {
                 anyhow::bail!("#{string_index} is not Utf8");
@@ -410,8 +436,9 @@ fn resolve_cp(
                 anyhow::bail!("#{name_index} is not Utf8 or #{descriptor_index} is not Utf8");
             }
         }
-        ConstInfoRaw::Utf8 { bytes } => ConstInfo::Utf8 {
-            bytes: Rc::new(bytes.clone()),
+        // names and descriptors, where an unpaired surrogate becomes U+FFFD
+        ConstInfoRaw::Utf8 { chars } => ConstInfo::Utf8 {
+            bytes: Rc::new(String::from_utf16_lossy(chars)),
         },
     };
 
@@ -419,6 +446,15 @@ fn resolve_cp(
     Ok(info)
 }
 
+/// A symbolic reference to a method.
+pub struct MethodRef {
+    pub class: Rc<String>,
+    pub name: Rc<String>,
+    pub descriptor: Rc<String>,
+    /// InterfaceMethodref or not.
+    pub is_interface: bool,
+}
+
 impl ConstantPool {
     fn new_empty() -> Self {
         Self { pool: vec![] }
@@ -427,7 +463,7 @@ impl ConstantPool {
     fn new(pool_raw: &[ConstInfoRaw]) -> anyhow::Result<Self> {
         let mut pool = vec![ConstInfo::None; pool_raw.len()];
         for i in 1..pool_raw.len() {
-            let _ = resolve_cp(&pool_raw, &mut pool, i)?;
+            let _ = resolve_cp(pool_raw, &mut pool, i)?;
         }
 
         Ok(Self { pool })
@@ -453,6 +489,30 @@ impl ConstantPool {
         }
     }
 
+    /// Methodref or InterfaceMethodref.
+    pub fn get_method(&self, idx: u16) -> anyhow::Result<MethodRef> {
+        let (class, name, descriptor, is_interface) = match self.get(idx)? {
+            ConstInfo::Methodref {
+                class,
+                name,
+                descriptor,
+            } => (class, name, descriptor, false),
+            ConstInfo::InterfaceMethodref {
+                class,
+                name,
+                descriptor,
+            } => (class, name, descriptor, true),
+            _ => anyhow::bail!("#{idx} is not Methodref or InterfaceMethodref"),
+        };
+
+        Ok(MethodRef {
+            class: Rc::clone(class),
+            name: Rc::clone(name),
+            descriptor: Rc::clone(descriptor),
+            is_interface,
+        })
+    }
+
     pub fn get_field(&self, idx: u16) -> anyhow::Result<(Rc<String>, Rc<String>, Rc<String>)> {
         if let ConstInfo::Fieldref {
             class,
@@ -610,17 +670,22 @@ ConstantValue_attribute {
     u2 constantvalue_index;
 }
 */
-fn parse_attribute_constant_value(mut p: &[u8], cp: &ConstantPool) -> anyhow::Result<JValue> {
+fn parse_attribute_constant_value(mut p: &[u8], cp: &ConstantPool) -> anyhow::Result<ConstInfo> {
     let constantvalue_index = p.try_get_u16()?;
-    let v = match cp.get(constantvalue_index)? {
-        &ConstInfo::Long { bytes } => JValue::Long(bytes),
-        &ConstInfo::Float { bytes } => JValue::Float(bytes),
-        &ConstInfo::Double { bytes } => JValue::Double(bytes),
-        &ConstInfo::Integer { bytes } => JValue::Int(bytes),
-        _ => anyhow::bail!("invalid constant type"),
-    };
+    let v = cp.get(constantvalue_index)?;
+    anyhow::ensure!(
+        matches!(
+            v,
+            ConstInfo::Long { .. }
+                | ConstInfo::Float { .. }
+                | ConstInfo::Double { .. }
+                | ConstInfo::Integer { .. }
+                | ConstInfo::String { .. }
+        ),
+        "invalid constant type: {v:?}"
+    );
 
-    Ok(v)
+    Ok(v.clone())
 }
 
 /*
@@ -673,10 +738,13 @@ fn parse_attribute_code(mut p: &[u8], cp: &ConstantPool) -> anyhow::Result<Code>
 
     parse_attributes(p, cp)?;
 
+    let ops = op::DecodedCode::decode(code, &exception_table)
+        .map_err(|e| exception!(VerifyError, "{e:#}"))?;
+
     Ok(Code {
         max_stack,
         max_locals,
-        code: code.to_vec(),
+        ops,
         exception_table,
     })
 }
@@ -0,0 +1,194 @@
+use super::*;
+
+use anyhow::Context;
+
+impl JVM {
+    /// Replace method bodies of an already loaded class.
+    /// (like JVMTI RedefineClasses)
+    ///
+    /// The new class file must have the same schema as the old one:
+    /// the same superclass, interfaces, fields and method signatures.
+    ///
+    /// Frames already running keep the [Rc] of the old class and method, so
+    /// they continue executing the old code. New invocations look up the
+    /// class by name and use the new code.
+    /// Static field values and the initialization state are kept.
+    pub fn redefine_class(&mut self, bin: &[u8]) -> anyhow::Result<()> {
+        let cls = super::parse::parse_class_file(bin)?;
+        let clsname = cls.this_class.to_string();
+        let old = self
+            .get_class(&clsname)
+            .with_context(|| format!("class is not loaded: {clsname}"))?;
+        check_schema(&old, &cls)?;
+
+        self.classes.insert(clsname.clone(), Rc::new(cls));
+        self.invalidate_class_cache(&clsname);
+
+        Ok(())
+    }
+
+    /// Drop cached resolutions which may refer to the old definition.
+    ///
+    /// The new definition starts with empty caches of its own (keyed by cp
+    /// index), and the caches of old definitions no frame runs any more are
+    /// dropped.
+    /// Cache entries of the other definitions resolved to this class are
+    /// dropped too.
+    /// The layout is kept since the schema is unchanged.
+    /// The vtables of all classes are rebuilt since subclasses may select
+    /// the old methods.
+    fn invalidate_class_cache(&mut self, clsname: &str) {
+        self.cp_caches
+            .retain(|_, cache| cache.class.strong_count() > 0);
+        for cache in self.cp_caches.values_mut() {
+            cache
+                .fields
+                .retain(|_, resolved| resolved.class.as_str() != clsname);
+            cache.methods.retain(|_, resolved| {
+                resolved.class.this_class.as_str() != clsname
+                    && resolved.decl.this_class.as_str() != clsname
+            });
+        }
+        for rtinfo in self.class_rt.values_mut() {
+            rtinfo.vtable = None;
+        }
+    }
+}
+
+/// JVMTI RedefineClasses restrictions:
+/// the redefinition must not add, remove or rename fields or methods,
+/// change their signatures or modifiers, or change inheritance.
+fn check_schema(old: &JClass, new: &JClass) -> anyhow::Result<()> {
+    let name = &old.this_class;
+    anyhow::ensure!(
+        old.access_flags == new.access_flags,
+        "{name}: class modifiers changed"
+    );
+    anyhow::ensure!(
+        old.super_class == new.super_class,
+        "{name}: superclass changed"
+    );
+    anyhow::ensure!(
+        old.interfaces == new.interfaces,
+        "{name}: interfaces changed"
+    );
+
+    anyhow::ensure!(
+        old.fields.len() == new.fields.len(),
+        "{name}: field added or deleted"
+    );
+    for (name_desc, f) in old.fields.iter() {
+        let nf = new
+            .fields
+            .get(name_desc)
+            .with_context(|| format!("{name}: field deleted: {name_desc}"))?;
+        anyhow::ensure!(
+            f.access_flags == nf.access_flags,
+            "{name}: field modifiers changed: {name_desc}"
+        );
+    }
+
+    anyhow::ensure!(
+        old.methods.len() == new.methods.len(),
+        "{name}: method added or deleted"
+    );
+    for (name_desc, m) in old.methods.iter() {
+        let nm = new
+            .methods
+            .get(name_desc)
+            .with_context(|| format!("{name}: method deleted: {name_desc}"))?;
+        anyhow::ensure!(
+            m.access_flags == nm.access_flags,
+            "{name}: method modifiers changed: {name_desc}"
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn call(jvm: &mut JVM, name: &str, args: &[JValue]) -> JValue {
+        test_util::call(jvm, "Hot", name, args)
+    }
+
+    #[test]
+    fn test_redefine_class() {
+        let mut jvm = test_util::setup();
+
+        // the vtables of Hot and HotSub and the Methodref cache of Hot are
+        // built by the first calls
+        let mut th = JThreadContext::default();
+        let Ok(InvokeResult::Return(Some(JValue::Ref(sub)))) =
+            jvm.new_instance(&mut th, "HotSub", "()V", &[])
+        else {
+            panic!();
+        };
+        assert_eq!(
+            call(&mut jvm, "callValue", &[JValue::Ref(sub)]),
+            JValue::Int(1)
+        );
+        assert_eq!(call(&mut jvm, "version", &[]), JValue::Int(1));
+
+        // a frame which is running the old code
+        let mut running = JThreadContext::default();
+        let cls = jvm.get_class("Hot").unwrap();
+        let method = cls.get_method("running()I").unwrap();
+        jvm.call_method(&mut running, cls, method, &[]).unwrap();
+        assert_eq!(jvm.run(&mut running).unwrap(), ThreadState::Yielded);
+
+        jvm.redefine_class(crate::res::REDEFINED_CLASS_FILES[0])
+            .unwrap();
+
+        // new invocations run the new code, also through the vtables
+        assert_eq!(
+            call(&mut jvm, "callValue", &[JValue::Ref(sub)]),
+            JValue::Int(2)
+        );
+        assert_eq!(call(&mut jvm, "version", &[]), JValue::Int(2));
+        // a frame of the new code starts while the old one still runs.
+        // The new pool has Thread.yield at #18, where the old pool has
+        // Hot.version, and each definition resolves its own entries.
+        let mut fresh = JThreadContext::default();
+        let cls = jvm.get_class("Hot").unwrap();
+        let method = cls.get_method("running()I").unwrap();
+        jvm.call_method(&mut fresh, cls, method, &[]).unwrap();
+        assert_eq!(jvm.run(&mut fresh).unwrap(), ThreadState::Yielded);
+        // the running frame continues the old code (10 + ...), and the
+        // method it invokes is the new one
+        assert_eq!(
+            jvm.run(&mut running).unwrap(),
+            ThreadState::Finished(Some(JValue::Int(12)))
+        );
+        assert_eq!(
+            jvm.run(&mut fresh).unwrap(),
+            ThreadState::Finished(Some(JValue::Int(22)))
+        );
+        assert_eq!(call(&mut jvm, "running", &[]), JValue::Int(22));
+
+        // not loaded
+        assert!(jvm.redefine_class(crate::res::MC_CLASS_FILES[0]).is_err());
+    }
+
+    #[test]
+    fn test_check_schema() {
+        let define = |fields: &[(u16, &str, &str)]| {
+            let fields = fields
+                .iter()
+                .map(|&(access_flags, name, desc)| {
+                    let f = stdlib::define_field(access_flags, name, desc);
+                    (f.name_desc.clone(), Rc::new(f))
+                })
+                .collect();
+            parse::define_native_class("A", Some("java/lang/Object"), fields, HashMap::new())
+        };
+
+        let old = define(&[(0, "x", "I")]);
+        assert!(check_schema(&old, &define(&[(0, "x", "I")])).is_ok());
+        assert!(check_schema(&old, &define(&[(0, "x", "J")])).is_err());
+        assert!(check_schema(&old, &define(&[(acc_field::STATIC, "x", "I")])).is_err());
+        assert!(check_schema(&old, &define(&[(0, "x", "I"), (0, "y", "I")])).is_err());
+    }
+}
@@ -0,0 +1,380 @@
+// https://docs.oracle.com/javase/specs/jvms/se21/html/jvms-5.html#jvms-5.4.3
+
+use super::*;
+
+impl JVM {
+    /// 5.4.3.2. Field Resolution
+    ///
+    /// Returns the class which declares the field and the field itself.
+    pub fn resolve_field(
+        &self,
+        clsname: &str,
+        name: &str,
+        descriptor: &str,
+    ) -> anyhow::Result<(Rc<JClass>, Rc<FieldInfo>)> {
+        let name_desc = format!("{name}{descriptor}");
+        self.lookup_field(clsname, &name_desc)?
+            .ok_or_else(|| exception!(NoSuchFieldError, "{clsname}.{name}"))
+    }
+
+    fn lookup_field(
+        &self,
+        clsname: &str,
+        name_desc: &str,
+    ) -> anyhow::Result<Option<(Rc<JClass>, Rc<FieldInfo>)>> {
+        let cls = self.get_class(clsname)?;
+        // 1. If C declares a field with the name and descriptor
+        if let Some(field) = cls.fields.get(name_desc) {
+            let field = Rc::clone(field);
+            return Ok(Some((cls, field)));
+        }
+        // 2. Otherwise, applied recursively to the direct superinterfaces of C
+        for iface in cls.interfaces.iter() {
+            if let Some(res) = self.lookup_field(iface, name_desc)? {
+                return Ok(Some(res));
+            }
+        }
+        // 3. Otherwise, applied recursively to the direct superclass of C
+        if let Some(super_class) = &cls.super_class {
+            return self.lookup_field(super_class, name_desc);
+        }
+
+        Ok(None)
+    }
+
+    /// 5.4.3.3. Method Resolution
+    ///
+    /// Resolve a Methodref (C is a class).
+    /// Returns the class which declares the method and the method itself.
+    pub fn resolve_method(
+        &self,
+        clsname: &str,
+        name: &str,
+        descriptor: &str,
+    ) -> anyhow::Result<(Rc<JClass>, Rc<MethodInfo>)> {
+        let cls = self.get_class(clsname)?;
+        // 1. If C is an interface, IncompatibleClassChangeError.
+        throw_unless!(
+            !cls.is_interface(),
+            IncompatibleClassChangeError,
+            "{clsname} is an interface"
+        );
+
+        // 2. Otherwise, look up in C and its superclasses.
+        let name_desc = format!("{name}{descriptor}");
+        let mut cur = Some(Rc::clone(&cls));
+        while let Some(c) = cur {
+            if let Some(m) = c.get_signature_polymorphic_method(name) {
+                return Ok((c, m));
+            }
+            if let Some(m) = c.methods.get(&name_desc) {
+                let m = Rc::clone(m);
+                return Ok((c, m));
+            }
+            cur = match &c.super_class {
+                Some(super_class) => Some(self.get_class(super_class)?),
+                None => None,
+            };
+        }
+
+        // 3. Otherwise, look up in the superinterfaces of C.
+        self.lookup_superinterface_method(&cls, &name_desc)?
+            .ok_or_else(|| exception!(NoSuchMethodError, "{clsname}.{name}{descriptor}"))
+    }
+
+    /// 5.4.3.4. Interface Method Resolution
+    ///
+    /// Resolve an InterfaceMethodref (C is an interface).
+    /// Returns the class which declares the method and the method itself.
+    pub fn resolve_interface_method(
+        &self,
+        clsname: &str,
+        name: &str,
+        descriptor: &str,
+    ) -> anyhow::Result<(Rc<JClass>, Rc<MethodInfo>)> {
+        let cls = self.get_class(clsname)?;
+        // 1. If C is not an interface, IncompatibleClassChangeError.
+        throw_unless!(
+            cls.is_interface(),
+            IncompatibleClassChangeError,
+            "{clsname} is not an interface"
+        );
+
+        // 2. If C declares a method with the name and descriptor
+        let name_desc = format!("{name}{descriptor}");
+        if let Some(m) = cls.methods.get(&name_desc) {
+            let m = Rc::clone(m);
+            return Ok((cls, m));
+        }
+
+        // 3. Otherwise, if the class Object declares a public instance method
+        let object = self.get_class("java/lang/Object")?;
+        if let Some(m) = object.methods.get(&name_desc)
+            && m.access_flags & acc_method::PUBLIC != 0
+            && m.access_flags & acc_method::STATIC == 0
+        {
+            let m = Rc::clone(m);
+            return Ok((object, m));
+        }
+
+        // 4, 5. Otherwise, look up in the superinterfaces of C.
+        self.lookup_superinterface_method(&cls, &name_desc)?
+            .ok_or_else(|| exception!(NoSuchMethodError, "{clsname}.{name}{descriptor}"))
+    }
+
+    /// If the maximally-specific superinterface methods of C for the name and
+    /// descriptor include exactly one method that does not have its
+    /// ACC_ABSTRACT flag set, then this method is chosen.
+    ///
+    /// Otherwise, if any superinterface of C declares a method with the name
+    /// and descriptor that has neither its ACC_PRIVATE flag nor its
+    /// ACC_STATIC flag set, one of these is arbitrarily chosen.
+    pub(super) fn lookup_superinterface_method(
+        &self,
+        cls: &JClass,
+        name_desc: &str,
+    ) -> anyhow::Result<Option<(Rc<JClass>, Rc<MethodInfo>)>> {
+        let mut candidates = Vec::new();
+        for iface in self.superinterfaces(cls)? {
+            if let Some(m) = iface.methods.get(name_desc)
+                && m.access_flags & (acc_method::PRIVATE | acc_method::STATIC) == 0
+            {
+                let m = Rc::clone(m);
+                candidates.push((iface, m));
+            }
+        }
+
+        // A method is maximally-specific if no other candidate is declared in
+        // a subinterface of its declaring interface.
+        let mut maximally_specific = Vec::new();
+        for (iface, m) in candidates.iter() {
+            let mut specific = true;
+            for (other, _) in candidates.iter() {
+                if !Rc::ptr_eq(iface, other) && self.implements(other, &iface.this_class)? {
+                    specific = false;
+                    break;
+                }
+            }
+            if specific {
+                maximally_specific.push((iface, m));
+            }
+        }
+        let non_abstract: Vec<_> = maximally_specific
+            .iter()
+            .filter(|(_, m)| m.access_flags & acc_method::ABSTRACT == 0)
+            .collect();
+        if let [(iface, m)] = non_abstract[..] {
+            return Ok(Some((Rc::clone(iface), Rc::clone(m))));
+        }
+
+        Ok(candidates.into_iter().next())
+    }
+
+    /// All superinterfaces of the class or interface, direct or indirect,
+    /// including the ones of its superclasses.
+    /// Each interface appears only once.
+    pub fn superinterfaces(&self, cls: &JClass) -> anyhow::Result<Vec<Rc<JClass>>> {
+        let mut result: Vec<Rc<JClass>> = Vec::new();
+        let mut queue: Vec<Rc<String>> = cls.interfaces.clone();
+        let mut super_class = cls.super_class.clone();
+        loop {
+            while let Some(name) = queue.pop() {
+                if result.iter().any(|i| i.this_class == name) {
+                    continue;
+                }
+                let iface = self.get_class(&name)?;
+                queue.extend(iface.interfaces.iter().rev().cloned());
+                result.push(iface);
+            }
+            let Some(name) = super_class else {
+                break;
+            };
+            let sup = self.get_class(&name)?;
+            queue.extend(sup.interfaces.iter().rev().cloned());
+            super_class = sup.super_class.clone();
+        }
+
+        Ok(result)
+    }
+
+    /// Whether `sup` is a (direct or indirect) superclass of `sub`.
+    /// A class is not a subclass of itself.
+    pub fn is_subclass_of(&self, sub: &str, sup: &str) -> anyhow::Result<bool> {
+        let mut cur = self.get_class(sub)?.super_class.clone();
+        while let Some(name) = cur {
+            if name.as_str() == sup {
+                return Ok(true);
+            }
+            cur = self.get_class(&name)?.super_class.clone();
+        }
+
+        Ok(false)
+    }
+
+    /// Whether an instance of `cls` is an instance of the class, interface or
+    /// array class `target`. (`cls` itself, a subclass or an implementation)
+    ///
+    /// An array is an instance of Object, Cloneable and Serializable, and of
+    /// an array class whose component type is the same primitive type or a
+    /// supertype of its reference component type.
+    pub fn instance_of(&self, cls: &JClass, target: &str) -> anyhow::Result<bool> {
+        if target.starts_with('[') {
+            return self.array_instance_of(cls, target);
+        }
+        Ok(cls.this_class.as_str() == target
+            || self.is_subclass_of(&cls.this_class, target)?
+            || self.implements(cls, target)?)
+    }
+
+    fn array_instance_of(&self, cls: &JClass, target: &str) -> anyhow::Result<bool> {
+        if !cls.this_class.starts_with('[') {
+            return Ok(false);
+        }
+        if cls.this_class.as_str() == target {
+            return Ok(true);
+        }
+        // both components must be references (classes, interfaces or arrays)
+        let is_ref_array = |name: &str| matches!(name.as_bytes()[1], b'L' | b'[');
+        if !is_ref_array(&cls.this_class) || !is_ref_array(target) {
+            return Ok(false);
+        }
+        let comp = self.get_class(array::component_class(&cls.this_class))?;
+
+        self.instance_of(&comp, array::component_class(target))
+    }
+
+    /// Whether the class or interface implements (extends) the interface
+    /// `iface` directly or indirectly.
+    pub fn implements(&self, cls: &JClass, iface: &str) -> anyhow::Result<bool> {
+        Ok(self
+            .superinterfaces(cls)?
+            .iter()
+            .any(|i| i.this_class.as_str() == iface))
+    }
+}
+
+impl JClass {
+    pub fn is_interface(&self) -> bool {
+        self.access_flags & acc_class::INTERFACE != 0
+    }
+
+    /// 2.9.3. Signature Polymorphic Methods
+    ///
+    /// If the class declares exactly one method with the name, and the
+    /// declaration is signature polymorphic, method lookup succeeds
+    /// regardless of the descriptor.
+    fn get_signature_polymorphic_method(&self, name: &str) -> Option<Rc<MethodInfo>> {
+        const CLASSES: &[&str] = &[
+            "java/lang/invoke/MethodHandle",
+            "java/lang/invoke/VarHandle",
+        ];
+        const FLAGS: u16 = acc_method::VARARGS | acc_method::NATIVE;
+
+        if !CLASSES.contains(&self.this_class.as_str()) {
+            return None;
+        }
+        let mut it = self.methods.values().filter(|m| m.name.as_str() == name);
+        let m = it.next()?;
+        if it.next().is_some() {
+            return None;
+        }
+        let sig_poly = m.access_flags & FLAGS == FLAGS
+            && m.param_types.len() == 1
+            && m.param_types[0]
+                == JType::array_of(JComponentType::Object("java/lang/Object".to_string()), 1);
+
+        sig_poly.then(|| Rc::clone(m))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use test_util::define_class;
+
+    const IFACE: u16 = acc_class::INTERFACE | acc_class::ABSTRACT;
+    const ABSTRACT: u16 = acc_method::PUBLIC | acc_method::ABSTRACT;
+
+    fn setup() -> JVM {
+        let mut jvm = JVM::new();
+        let classes = [
+            define_class(
+                0,
+                "java/lang/Object",
+                None,
+                &[],
+                &[],
+                &[(1, "toString", "()V")],
+            ),
+            // I <- J (default m)
+            define_class(IFACE, "I", None, &[], &[], &[(ABSTRACT, "m", "()V")]),
+            define_class(IFACE, "J", None, &["I"], &[], &[(1, "m", "()V")]),
+            define_class(IFACE, "K", None, &[], &[], &[(ABSTRACT, "k", "()V")]),
+            // A implements J, B extends A implements K
+            define_class(
+                0,
+                "A",
+                Some("java/lang/Object"),
+                &["J"],
+                &[],
+                &[(1, "a", "()V")],
+            ),
+            define_class(0, "B", Some("A"), &["K"], &[], &[]),
+        ];
+        for cls in classes {
+            jvm.load_native_class(cls);
+        }
+        jvm
+    }
+
+    #[test]
+    fn test_resolve_method() {
+        let jvm = setup();
+
+        // superclass
+        let (c, m) = jvm.resolve_method("B", "a", "()V").unwrap();
+        assert_eq!(c.this_class.as_str(), "A");
+        assert_eq!(m.name.as_str(), "a");
+        let (c, _) = jvm.resolve_method("B", "toString", "()V").unwrap();
+        assert_eq!(c.this_class.as_str(), "java/lang/Object");
+
+        // maximally-specific: J.m overrides I.m
+        let (c, _) = jvm.resolve_method("B", "m", "()V").unwrap();
+        assert_eq!(c.this_class.as_str(), "J");
+        // abstract superinterface method
+        let (c, _) = jvm.resolve_method("B", "k", "()V").unwrap();
+        assert_eq!(c.this_class.as_str(), "K");
+
+        assert!(jvm.resolve_method("B", "x", "()V").is_err());
+        assert!(jvm.resolve_method("I", "m", "()V").is_err());
+    }
+
+    #[test]
+    fn test_resolve_interface_method() {
+        let jvm = setup();
+
+        let (c, _) = jvm.resolve_interface_method("J", "m", "()V").unwrap();
+        assert_eq!(c.this_class.as_str(), "J");
+        let (c, _) = jvm
+            .resolve_interface_method("J", "toString", "()V")
+            .unwrap();
+        assert_eq!(c.this_class.as_str(), "java/lang/Object");
+        assert!(jvm.resolve_interface_method("A", "m", "()V").is_err());
+    }
+
+    #[test]
+    fn test_hierarchy() {
+        let jvm = setup();
+
+        assert!(jvm.is_subclass_of("B", "A").unwrap());
+        assert!(jvm.is_subclass_of("B", "java/lang/Object").unwrap());
+        assert!(!jvm.is_subclass_of("A", "A").unwrap());
+        assert!(!jvm.is_subclass_of("A", "B").unwrap());
+
+        let b = jvm.get_class("B").unwrap();
+        assert!(jvm.implements(&b, "I").unwrap());
+        assert!(jvm.implements(&b, "K").unwrap());
+        let a = jvm.get_class("A").unwrap();
+        assert!(!jvm.implements(&a, "K").unwrap());
+    }
+}
@@ -0,0 +1,244 @@
+//! Operand stack and local variables of the current frame.
+//!
+//! Both are arrays of u32 slots in [JThreadContext] stack.
+//! long and double (category 2) values occupy two slots (high word first).
+//! Overflow, underflow and local variable index are checked in debug builds
+//! only. (valid class files never violate them, but the code is only
+//! structurally checked when it is loaded)
+//!
+//! Each slot is tagged with whether it holds a reference, so that the GC can
+//! find the roots precisely. The tag is set by the type of the pushed or
+//! stored value, and ops which move values without knowing their types
+//! (dup, swap, aload/astore shared with int and float) move it with [Slot].
+//! A returnAddress is not a reference.
+
+use super::*;
+
+/// A value which can be stored in u32 slots.
+pub trait SlotValue: Copy {
+    /// Number of slots. (1 or 2)
+    const SLOTS: u32;
+    /// Whether the value is a reference. (or null)
+    const IS_REF: bool = false;
+
+    fn to_slots(self, dst: &mut [u32]);
+    fn from_slots(src: &[u32]) -> Self;
+}
+
+/// A raw slot. (int, float, reference or returnAddress, or a half of long/double)
+impl SlotValue for u32 {
+    const SLOTS: u32 = 1;
+
+    fn to_slots(self, dst: &mut [u32]) {
+        dst[0] = self;
+    }
+    fn from_slots(src: &[u32]) -> Self {
+        src[0]
+    }
+}
+
+impl SlotValue for i32 {
+    const SLOTS: u32 = 1;
+
+    fn to_slots(self, dst: &mut [u32]) {
+        dst[0] = self as u32;
+    }
+    fn from_slots(src: &[u32]) -> Self {
+        src[0] as i32
+    }
+}
+
+impl SlotValue for f32 {
+    const SLOTS: u32 = 1;
+
+    fn to_slots(self, dst: &mut [u32]) {
+        dst[0] = self.to_bits();
+    }
+    fn from_slots(src: &[u32]) -> Self {
+        f32::from_bits(src[0])
+    }
+}
+
+impl SlotValue for i64 {
+    const SLOTS: u32 = 2;
+
+    fn to_slots(self, dst: &mut [u32]) {
+        dst[0] = (self as u64 >> 32) as u32;
+        dst[1] = self as u32;
+    }
+    fn from_slots(src: &[u32]) -> Self {
+        (((src[0] as u64) << 32) | src[1] as u64) as i64
+    }
+}
+
+impl SlotValue for f64 {
+    const SLOTS: u32 = 2;
+
+    fn to_slots(self, dst: &mut [u32]) {
+        (self.to_bits() as i64).to_slots(dst);
+    }
+    fn from_slots(src: &[u32]) -> Self {
+        f64::from_bits(i64::from_slots(src) as u64)
+    }
+}
+
+/// A raw slot with its reference tag.
+#[derive(Debug, Clone, Copy)]
+pub struct Slot {
+    value: u32,
+    is_ref: bool,
+}
+
+impl JThreadContext {
+    /// Push a value onto the operand stack of the current frame.
+    pub fn push<T: SlotValue>(&mut self, v: T) {
+        let frame = self.frames.last_mut().expect("no frames");
+        let pos = frame.range.start + frame.sp;
+        debug_assert!(pos + T::SLOTS <= frame.range.end, "operand stack overflow");
+        let range = pos as usize..(pos + T::SLOTS) as usize;
+        v.to_slots(&mut self.stack[range.clone()]);
+        self.refs[range].fill(T::IS_REF);
+        frame.sp += T::SLOTS;
+    }
+
+    /// Pop a value from the operand stack of the current frame.
+    pub fn pop<T: SlotValue>(&mut self) -> T {
+        let frame = self.frames.last_mut().expect("no frames");
+        debug_assert!(
+            frame.sp >= frame.max_locals + T::SLOTS,
+            "operand stack underflow"
+        );
+        frame.sp -= T::SLOTS;
+        let pos = frame.range.start + frame.sp;
+        T::from_slots(&self.stack[pos as usize..(pos + T::SLOTS) as usize])
+    }
+
+    /// Read a local variable of the current frame.
+    pub fn load<T: SlotValue>(&self, index: u16) -> T {
+        let frame = self.frames.last().expect("no frames");
+        debug_assert!(
+            index as u32 + T::SLOTS <= frame.max_locals,
+            "invalid local variable index: {index}"
+        );
+        let pos = frame.range.start + index as u32;
+        T::from_slots(&self.stack[pos as usize..(pos + T::SLOTS) as usize])
+    }
+
+    /// Write a local variable of the current frame.
+    pub fn store<T: SlotValue>(&mut self, index: u16, v: T) {
+        let frame = self.frames.last().expect("no frames");
+        debug_assert!(
+            index as u32 + T::SLOTS <= frame.max_locals,
+            "invalid local variable index: {index}"
+        );
+        let pos = frame.range.start + index as u32;
+        let range = pos as usize..(pos + T::SLOTS) as usize;
+        v.to_slots(&mut self.stack[range.clone()]);
+        self.refs[range].fill(T::IS_REF);
+    }
+
+    /// Push a raw slot onto the operand stack.
+    pub fn push_slot(&mut self, slot: Slot) {
+        self.push(slot.value);
+        let frame = self.frames.last().expect("no frames");
+        self.refs[(frame.range.start + frame.sp - 1) as usize] = slot.is_ref;
+    }
+
+    /// Pop a raw slot from the operand stack.
+    pub fn pop_slot(&mut self) -> Slot {
+        let value = self.pop();
+        let frame = self.frames.last().expect("no frames");
+        let is_ref = self.refs[(frame.range.start + frame.sp) as usize];
+        Slot { value, is_ref }
+    }
+
+    /// Read a raw slot of a local variable.
+    pub fn load_slot(&self, index: u16) -> Slot {
+        let frame = self.frames.last().expect("no frames");
+        Slot {
+            value: self.load(index),
+            is_ref: self.refs[(frame.range.start + index as u32) as usize],
+        }
+    }
+
+    /// Write a raw slot to a local variable.
+    pub fn store_slot(&mut self, index: u16, slot: Slot) {
+        self.store(index, slot.value);
+        let frame = self.frames.last().expect("no frames");
+        self.refs[(frame.range.start + index as u32) as usize] = slot.is_ref;
+    }
+
+    /// References (non-null) in the locals and operand stacks of all frames.
+    pub fn stack_refs(&self) -> impl Iterator<Item = JRef> + '_ {
+        self.frames.iter().flat_map(|frame| {
+            let live = frame.range.start as usize..(frame.range.start + frame.sp) as usize;
+            self.stack[live.clone()]
+                .iter()
+                .zip(&self.refs[live])
+                .filter(|&(_, &is_ref)| is_ref)
+                .filter_map(|(&v, _)| JRef::from_slot(v))
+        })
+    }
+
+    pub fn push_value(&mut self, v: JValue) {
+        match v {
+            JValue::Int(x) => self.push(x),
+            JValue::Long(x) => self.push(x),
+            JValue::Float(x) => self.push(x),
+            JValue::Double(x) => self.push(x),
+            JValue::Null => self.push::<Option<JRef>>(None),
+            JValue::Ref(r) => self.push(Some(r)),
+            JValue::Invalid => panic!("invalid value"),
+        }
+    }
+
+    /// Pop a value of the type.
+    /// boolean, byte, char and short are int on the operand stack.
+    pub fn pop_value(&mut self, jtype: &JType) -> JValue {
+        if jtype.is_reference() {
+            return JValue::from_ref(self.pop());
+        }
+        match jtype.ctype {
+            JComponentType::Long => JValue::Long(self.pop()),
+            JComponentType::Float => JValue::Float(self.pop()),
+            JComponentType::Double => JValue::Double(self.pop()),
+            _ => JValue::Int(self.pop()),
+        }
+    }
+
+    /// Pop arguments of a method in the declared order.
+    /// If `this` is true, the receiver is popped too and placed at first.
+    pub fn pop_args(&mut self, method: &MethodInfo, this: bool) -> Vec<JValue> {
+        let mut args = Vec::with_capacity(method.param_types.len() + this as usize);
+        for jtype in method.param_types.iter().rev() {
+            args.push(self.pop_value(jtype));
+        }
+        if this {
+            args.push(JValue::from_ref(self.pop()));
+        }
+        args.reverse();
+
+        args
+    }
+
+    /// Store arguments to the local variables of the current frame.
+    /// (category 2 values take two local variables)
+    pub fn store_args(&mut self, args: &[JValue]) {
+        let mut index = 0;
+        for v in args {
+            match *v {
+                JValue::Int(x) => self.store(index, x),
+                JValue::Long(x) => self.store(index, x),
+                JValue::Float(x) => self.store(index, x),
+                JValue::Double(x) => self.store(index, x),
+                JValue::Null => self.store::<Option<JRef>>(index, None),
+                JValue::Ref(r) => self.store(index, Some(r)),
+                JValue::Invalid => panic!("invalid value"),
+            }
+            index += match v {
+                JValue::Long(_) | JValue::Double(_) => 2,
+                _ => 1,
+            };
+        }
+    }
+}
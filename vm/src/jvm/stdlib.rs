@@ -1,12 +1,73 @@
 use super::*;
+use anyhow::Context;
 use std::collections::HashMap;
 use std::rc::Rc;
 
 pub fn load_core(jvm: &mut JVM) {
+    jvm.load_native_class(java_lang_object());
+    jvm.load_native_class(marker_interface("java/lang/Cloneable"));
+    jvm.load_native_class(marker_interface("java/io/Serializable"));
     jvm.load_native_class(java_lang_system());
+    jvm.load_native_class(java_lang_string());
+    jvm.load_native_class(java_lang_class());
+    jvm.load_native_class(java_lang_runtime());
+    jvm.load_native_class(java_lang_throwable());
+    jvm.load_native_class(java_lang_runnable());
+    jvm.load_native_class(java_lang_thread());
+    jvm.load_native_class(java_util_date());
+    for &(name, super_class) in EXCEPTION_CLASSES {
+        jvm.load_native_class(parse::define_native_class(
+            &format!("java/lang/{name}"),
+            Some(&format!("java/lang/{super_class}")),
+            HashMap::new(),
+            throwable_constructors(),
+        ));
+    }
 }
 
-fn define_field(access_flags: u16, name: &str, descriptor: &str) -> FieldInfo {
+/// Subclasses of Throwable in java/lang. (name, superclass)
+const EXCEPTION_CLASSES: &[(&str, &str)] = &[
+    ("Exception", "Throwable"),
+    ("Error", "Throwable"),
+    ("RuntimeException", "Exception"),
+    ("CloneNotSupportedException", "Exception"),
+    ("InterruptedException", "Exception"),
+    ("ArithmeticException", "RuntimeException"),
+    ("ArrayStoreException", "RuntimeException"),
+    ("ClassCastException", "RuntimeException"),
+    ("IllegalArgumentException", "RuntimeException"),
+    ("IllegalMonitorStateException", "RuntimeException"),
+    ("IllegalStateException", "RuntimeException"),
+    ("IllegalThreadStateException", "IllegalArgumentException"),
+    ("IndexOutOfBoundsException", "RuntimeException"),
+    (
+        "ArrayIndexOutOfBoundsException",
+        "IndexOutOfBoundsException",
+    ),
+    (
+        "StringIndexOutOfBoundsException",
+        "IndexOutOfBoundsException",
+    ),
+    ("NegativeArraySizeException", "RuntimeException"),
+    ("NullPointerException", "RuntimeException"),
+    ("LinkageError", "Error"),
+    ("ExceptionInInitializerError", "LinkageError"),
+    ("NoClassDefFoundError", "LinkageError"),
+    ("UnsatisfiedLinkError", "LinkageError"),
+    ("VerifyError", "LinkageError"),
+    ("IncompatibleClassChangeError", "LinkageError"),
+    ("AbstractMethodError", "IncompatibleClassChangeError"),
+    ("IllegalAccessError", "IncompatibleClassChangeError"),
+    ("InstantiationError", "IncompatibleClassChangeError"),
+    ("NoSuchFieldError", "IncompatibleClassChangeError"),
+    ("NoSuchMethodError", "IncompatibleClassChangeError"),
+    ("VirtualMachineError", "Error"),
+    ("OutOfMemoryError", "VirtualMachineError"),
+    ("InternalError", "VirtualMachineError"),
+    ("StackOverflowError", "VirtualMachineError"),
+];
+
+pub fn define_field(access_flags: u16, name: &str, descriptor: &str) -> FieldInfo {
     let name = name.to_string();
     let descriptor = descriptor.to_string();
     let name_desc = format!("{name}{descriptor}");
@@ -17,14 +78,14 @@ fn define_field(access_flags: u16, name: &str, descriptor: &str) -> FieldInfo {
         name: Rc::new(name),
         descriptor: Rc::new(descriptor),
         name_desc,
-        constant_value: Some(JValue::Null),
+        constant_value: None,
         jtype,
     }
 }
 
-fn define_method<F>(access_flags: u16, name: &str, descriptor: &str, func: F) -> MethodInfo
+pub fn define_method<F>(access_flags: u16, name: &str, descriptor: &str, func: F) -> MethodInfo
 where
-    F: FnMut() + 'static,
+    F: Fn(&mut JVM, &mut JThreadContext, &[JValue]) -> anyhow::Result<Option<JValue>> + 'static,
 {
     let name = name.to_string();
     let descriptor = descriptor.to_string();
@@ -37,23 +98,166 @@ where
         name: Rc::new(name),
         descriptor: Rc::new(descriptor),
         name_desc,
-        method_body: MethodBody::Native(Box::new(func)),
+        method_body: MethodBody::Native(Rc::new(func)),
         ret_type,
         param_types,
     }
 }
 
+type NativeFn = fn(&mut JVM, &mut JThreadContext, &[JValue]) -> anyhow::Result<Option<JValue>>;
+
+fn this_ref(args: &[JValue]) -> anyhow::Result<JRef> {
+    match args[0] {
+        JValue::Ref(this) => Ok(this),
+        _ => anyhow::bail!("invalid this"),
+    }
+}
+
+fn java_lang_object() -> JClass {
+    let mut methods = HashMap::new();
+
+    let method = define_method(acc_method::PUBLIC, "<init>", "()V", |_, _, _| Ok(None));
+    methods.insert(method.name_desc.clone(), Rc::new(method));
+
+    let method = define_method(
+        acc_method::PUBLIC | acc_method::FINAL,
+        "getClass",
+        "()Ljava/lang/Class;",
+        |jvm, th, args| {
+            let JValue::Ref(this) = args[0] else {
+                anyhow::bail!("invalid this");
+            };
+            let name = Rc::clone(&jvm.heap.get(this).class.this_class);
+            Ok(Some(JValue::Ref(jvm.class_object(th, &name)?)))
+        },
+    );
+    methods.insert(method.name_desc.clone(), Rc::new(method));
+
+    let method = define_method(acc_method::PUBLIC, "hashCode", "()I", |jvm, _, args| {
+        let JValue::Ref(this) = args[0] else {
+            anyhow::bail!("invalid this");
+        };
+        Ok(Some(JValue::Int(jvm.heap.identity_hash(this))))
+    });
+    methods.insert(method.name_desc.clone(), Rc::new(method));
+
+    let method = define_method(
+        acc_method::PROTECTED,
+        "clone",
+        "()Ljava/lang/Object;",
+        |jvm, th, args| {
+            let JValue::Ref(this) = args[0] else {
+                anyhow::bail!("invalid this");
+            };
+            Ok(Some(JValue::Ref(jvm.clone_object(th, this)?)))
+        },
+    );
+    methods.insert(method.name_desc.clone(), Rc::new(method));
+
+    let waits: [(&str, NativeFn); 2] = [
+        ("()V", |jvm, th, args| {
+            jvm.wait(th, this_ref(args)?, 0)?;
+            Ok(None)
+        }),
+        ("(J)V", |jvm, th, args| {
+            let JValue::Long(millis) = args[1] else {
+                anyhow::bail!("invalid millis");
+            };
+            throw_unless!(
+                millis >= 0,
+                IllegalArgumentException,
+                "timeout value is negative"
+            );
+            jvm.wait(th, this_ref(args)?, millis as u64)?;
+            Ok(None)
+        }),
+    ];
+    for (desc, func) in waits {
+        let method = define_method(acc_method::PUBLIC | acc_method::FINAL, "wait", desc, func);
+        methods.insert(method.name_desc.clone(), Rc::new(method));
+    }
+
+    let notifies: [(&str, NativeFn); 2] = [
+        ("notify", |jvm, th, args| {
+            jvm.notify(th.id, this_ref(args)?, false)?;
+            Ok(None)
+        }),
+        ("notifyAll", |jvm, th, args| {
+            jvm.notify(th.id, this_ref(args)?, true)?;
+            Ok(None)
+        }),
+    ];
+    for (name, func) in notifies {
+        let method = define_method(acc_method::PUBLIC | acc_method::FINAL, name, "()V", func);
+        methods.insert(method.name_desc.clone(), Rc::new(method));
+    }
+
+    parse::define_native_class("java/lang/Object", None, HashMap::new(), methods)
+}
+
+/// An interface without methods, such as Cloneable (implemented by arrays).
+fn marker_interface(name: &str) -> JClass {
+    let mut cls = parse::define_native_class(
+        name,
+        Some("java/lang/Object"),
+        HashMap::new(),
+        HashMap::new(),
+    );
+    cls.access_flags = acc_class::PUBLIC | acc_class::INTERFACE | acc_class::ABSTRACT;
+    cls
+}
+
 fn java_lang_system() -> JClass {
     let mut fields = HashMap::new();
-    let methods = HashMap::new();
+    let mut methods = HashMap::new();
+
+    let method = define_method(
+        acc_method::PUBLIC | acc_method::STATIC,
+        "gc",
+        "()V",
+        |jvm, th, _| {
+            jvm.gc(th);
+            Ok(None)
+        },
+    );
+    methods.insert(method.name_desc.clone(), Rc::new(method));
+
+    let method = define_method(
+        acc_method::PUBLIC | acc_method::STATIC,
+        "currentTimeMillis",
+        "()J",
+        |jvm, _, _| Ok(Some(JValue::Long(jvm.clock.now().as_millis() as i64))),
+    );
+    methods.insert(method.name_desc.clone(), Rc::new(method));
+
+    let method = define_method(
+        acc_method::PUBLIC | acc_method::STATIC,
+        "nanoTime",
+        "()J",
+        |jvm, _, _| Ok(Some(JValue::Long(jvm.clock.monotonic().as_nanos() as i64))),
+    );
+    methods.insert(method.name_desc.clone(), Rc::new(method));
+
+    let method = define_method(
+        acc_method::PUBLIC | acc_method::STATIC,
+        "identityHashCode",
+        "(Ljava/lang/Object;)I",
+        |jvm, _, args| {
+            let hash = match args[0] {
+                JValue::Ref(r) => jvm.heap.identity_hash(r),
+                _ => 0,
+            };
+            Ok(Some(JValue::Int(hash)))
+        },
+    );
+    methods.insert(method.name_desc.clone(), Rc::new(method));
 
-    let name = "out";
     let field = define_field(
         acc_field::PUBLIC | acc_field::STATIC,
-        name,
+        "out",
         "Ljava/io/PrintStream;",
     );
-    fields.insert(name.to_string(), Rc::new(field));
+    fields.insert(field.name_desc.clone(), Rc::new(field));
 
     parse::define_native_class(
         "java/lang/System",
@@ -62,3 +266,612 @@ fn java_lang_system() -> JClass {
         methods,
     )
 }
+
+/// The instance is kept in a static field, so it is never collected.
+fn java_lang_runtime() -> JClass {
+    let mut fields = HashMap::new();
+    let mut methods = HashMap::new();
+
+    let field = define_field(
+        acc_field::PRIVATE | acc_field::STATIC,
+        "currentRuntime",
+        "Ljava/lang/Runtime;",
+    );
+    fields.insert(field.name_desc.clone(), Rc::new(field));
+
+    let method = define_method(
+        acc_method::PUBLIC | acc_method::STATIC,
+        "getRuntime",
+        "()Ljava/lang/Runtime;",
+        |jvm, th, _| {
+            let (name, desc) = ("currentRuntime", "Ljava/lang/Runtime;");
+            let mut runtime = jvm.get_static(th, "java/lang/Runtime", name, desc)?;
+            if runtime == JValue::Null {
+                let r = jvm.alloc_or_collect(th, |jvm| jvm.alloc_object("java/lang/Runtime"))?;
+                runtime = JValue::Ref(r);
+                jvm.put_static(th, "java/lang/Runtime", name, desc, runtime.clone())?;
+            }
+            Ok(Some(runtime))
+        },
+    );
+    methods.insert(method.name_desc.clone(), Rc::new(method));
+
+    let method = define_method(acc_method::PUBLIC, "gc", "()V", |jvm, th, _| {
+        jvm.gc(th);
+        Ok(None)
+    });
+    methods.insert(method.name_desc.clone(), Rc::new(method));
+
+    let method = define_method(acc_method::PUBLIC, "freeMemory", "()J", |jvm, _, _| {
+        Ok(Some(JValue::Long(
+            jvm.heap.total().saturating_sub(jvm.heap.used()) as i64,
+        )))
+    });
+    methods.insert(method.name_desc.clone(), Rc::new(method));
+
+    let method = define_method(acc_method::PUBLIC, "totalMemory", "()J", |jvm, _, _| {
+        Ok(Some(JValue::Long(jvm.heap.total() as i64)))
+    });
+    methods.insert(method.name_desc.clone(), Rc::new(method));
+
+    let method = define_method(acc_method::PUBLIC, "maxMemory", "()J", |jvm, _, _| {
+        Ok(Some(JValue::Long(jvm.heap.max() as i64)))
+    });
+    methods.insert(method.name_desc.clone(), Rc::new(method));
+
+    parse::define_native_class(
+        "java/lang/Runtime",
+        Some("java/lang/Object"),
+        fields,
+        methods,
+    )
+}
+
+fn java_lang_runnable() -> JClass {
+    let mut methods = HashMap::new();
+
+    let mut method = define_method(
+        acc_method::PUBLIC | acc_method::ABSTRACT,
+        "run",
+        "()V",
+        |_, _, _| Ok(None),
+    );
+    method.method_body = MethodBody::None;
+    methods.insert(method.name_desc.clone(), Rc::new(method));
+
+    let mut cls = parse::define_native_class(
+        "java/lang/Runnable",
+        Some("java/lang/Object"),
+        HashMap::new(),
+        methods,
+    );
+    cls.access_flags = acc_class::PUBLIC | acc_class::INTERFACE | acc_class::ABSTRACT;
+    cls
+}
+
+/// The threads are scheduled by the VM. (see thread.rs)
+/// `started` is set by start(), and a started thread is alive while the VM
+/// holds it.
+fn java_lang_thread() -> JClass {
+    let mut fields = HashMap::new();
+    let mut methods = HashMap::new();
+
+    for (access_flags, name, desc) in [
+        (acc_field::PRIVATE, "name", "Ljava/lang/String;"),
+        (acc_field::PRIVATE, "target", "Ljava/lang/Runnable;"),
+        (acc_field::PRIVATE, "priority", "I"),
+        (acc_field::PRIVATE, "started", "Z"),
+        // numbers the default names (Thread-0, Thread-1, ...)
+        (
+            acc_field::PRIVATE | acc_field::STATIC,
+            "threadInitNumber",
+            "I",
+        ),
+    ] {
+        let field = define_field(access_flags, name, desc);
+        fields.insert(field.name_desc.clone(), Rc::new(field));
+    }
+
+    let constructors: [(&str, NativeFn); 4] = [
+        ("()V", |jvm, th, args| {
+            init_thread(jvm, th, &args[0], JValue::Null, None)
+        }),
+        ("(Ljava/lang/Runnable;)V", |jvm, th, args| {
+            init_thread(jvm, th, &args[0], args[1].clone(), None)
+        }),
+        ("(Ljava/lang/String;)V", |jvm, th, args| {
+            init_thread(jvm, th, &args[0], JValue::Null, Some(&args[1]))
+        }),
+        (
+            "(Ljava/lang/Runnable;Ljava/lang/String;)V",
+            |jvm, th, args| init_thread(jvm, th, &args[0], args[1].clone(), Some(&args[2])),
+        ),
+    ];
+    for (desc, func) in constructors {
+        let method = define_method(acc_method::PUBLIC, "<init>", desc, func);
+        methods.insert(method.name_desc.clone(), Rc::new(method));
+    }
+
+    let method = define_method(
+        acc_method::PUBLIC | acc_method::STATIC,
+        "currentThread",
+        "()Ljava/lang/Thread;",
+        |jvm, th, _| Ok(Some(JValue::Ref(jvm.current_thread(th)?))),
+    );
+    methods.insert(method.name_desc.clone(), Rc::new(method));
+
+    let method = define_method(
+        acc_method::PUBLIC | acc_method::STATIC,
+        "sleep",
+        "(J)V",
+        |jvm, th, args| {
+            let JValue::Long(millis) = args[0] else {
+                anyhow::bail!("invalid millis");
+            };
+            throw_unless!(
+                millis >= 0,
+                IllegalArgumentException,
+                "timeout value is negative"
+            );
+            if millis == 0 {
+                th.yield_now();
+            } else {
+                th.block(BlockReason::Sleep(
+                    jvm.millis().saturating_add(millis as u64),
+                ));
+            }
+            Ok(None)
+        },
+    );
+    methods.insert(method.name_desc.clone(), Rc::new(method));
+
+    let method = define_method(
+        acc_method::PUBLIC | acc_method::STATIC,
+        "yield",
+        "()V",
+        |_, th, _| {
+            th.yield_now();
+            Ok(None)
+        },
+    );
+    methods.insert(method.name_desc.clone(), Rc::new(method));
+
+    let method = define_method(acc_method::PUBLIC, "start", "()V", |jvm, _, args| {
+        jvm.start_thread(this_ref(args)?)?;
+        Ok(None)
+    });
+    methods.insert(method.name_desc.clone(), Rc::new(method));
+
+    // calls target.run() in its place, so it runs in the interpreter
+    let method = define_method(acc_method::PUBLIC, "run", "()V", |jvm, th, args| {
+        let this = this_ref(args)?;
+        if let JValue::Ref(target) = jvm.get_field(this, "target", "Ljava/lang/Runnable;")? {
+            jvm.call_virtual(th, target, "run", "()V", &[])?;
+        }
+        Ok(None)
+    });
+    methods.insert(method.name_desc.clone(), Rc::new(method));
+
+    let joins: [(&str, NativeFn); 2] = [
+        ("()V", |jvm, th, args| join_thread(jvm, th, args, 0)),
+        ("(J)V", |jvm, th, args| {
+            let JValue::Long(millis) = args[1] else {
+                anyhow::bail!("invalid millis");
+            };
+            throw_unless!(
+                millis >= 0,
+                IllegalArgumentException,
+                "timeout value is negative"
+            );
+            join_thread(jvm, th, args, millis as u64)
+        }),
+    ];
+    for (desc, func) in joins {
+        let method = define_method(acc_method::PUBLIC | acc_method::FINAL, "join", desc, func);
+        methods.insert(method.name_desc.clone(), Rc::new(method));
+    }
+
+    let method = define_method(
+        acc_method::PUBLIC | acc_method::FINAL,
+        "isAlive",
+        "()Z",
+        |jvm, th, args| {
+            let alive = jvm.is_alive(th, this_ref(args)?);
+            Ok(Some(JValue::Int(alive as i32)))
+        },
+    );
+    methods.insert(method.name_desc.clone(), Rc::new(method));
+
+    let method = define_method(
+        acc_method::PUBLIC | acc_method::FINAL,
+        "getName",
+        "()Ljava/lang/String;",
+        |jvm, _, args| {
+            let name = jvm.get_field(this_ref(args)?, "name", "Ljava/lang/String;")?;
+            Ok(Some(name))
+        },
+    );
+    methods.insert(method.name_desc.clone(), Rc::new(method));
+
+    let method = define_method(
+        acc_method::PUBLIC | acc_method::FINAL,
+        "getPriority",
+        "()I",
+        |jvm, _, args| Ok(Some(jvm.get_field(this_ref(args)?, "priority", "I")?)),
+    );
+    methods.insert(method.name_desc.clone(), Rc::new(method));
+
+    let method = define_method(
+        acc_method::PUBLIC | acc_method::FINAL,
+        "setPriority",
+        "(I)V",
+        |jvm, _, args| {
+            let JValue::Int(priority) = args[1] else {
+                anyhow::bail!("invalid priority");
+            };
+            throw_unless!(
+                (thread::priority::MIN..=thread::priority::MAX).contains(&priority),
+                IllegalArgumentException,
+                "priority out of range: {priority}"
+            );
+            jvm.put_field(this_ref(args)?, "priority", "I", args[1].clone())?;
+            Ok(None)
+        },
+    );
+    methods.insert(method.name_desc.clone(), Rc::new(method));
+
+    let mut cls = parse::define_native_class(
+        "java/lang/Thread",
+        Some("java/lang/Object"),
+        fields,
+        methods,
+    );
+    cls.interfaces = vec![Rc::new("java/lang/Runnable".to_string())];
+    cls
+}
+
+/// Thread constructors. The priority is inherited from the current thread.
+fn init_thread(
+    jvm: &mut JVM,
+    th: &mut JThreadContext,
+    this: &JValue,
+    target: JValue,
+    name: Option<&JValue>,
+) -> anyhow::Result<Option<JValue>> {
+    let JValue::Ref(this) = *this else {
+        anyhow::bail!("invalid this");
+    };
+    // the arguments are popped, so they are kept alive while the current
+    // Thread object and the name are allocated
+    let roots: Vec<JRef> = [JValue::Ref(this), target.clone()]
+        .iter()
+        .chain(name)
+        .filter_map(JValue::as_ref)
+        .collect();
+    let (name, priority) = jvm.with_roots(&roots, |jvm| {
+        let current = jvm.current_thread(th)?;
+        let priority = jvm.get_field(current, "priority", "I")?;
+        let name = match name {
+            Some(JValue::Ref(name)) => JValue::Ref(*name),
+            Some(_) => throw!(NullPointerException, "name cannot be null"),
+            None => {
+                let (cls, field) = ("java/lang/Thread", "threadInitNumber");
+                let JValue::Int(n) = jvm.get_static(th, cls, field, "I")? else {
+                    anyhow::bail!("invalid {field}");
+                };
+                jvm.put_static(th, cls, field, "I", JValue::Int(n + 1))?;
+                JValue::Ref(jvm.new_string(th, &format!("Thread-{n}"))?)
+            }
+        };
+        Ok((name, priority))
+    })?;
+
+    jvm.put_field(this, "name", "Ljava/lang/String;", name)?;
+    jvm.put_field(this, "target", "Ljava/lang/Runnable;", target)?;
+    jvm.put_field(this, "priority", "I", priority)?;
+    Ok(None)
+}
+
+/// Thread.join: block until the thread terminates, or for `millis` if not 0.
+fn join_thread(
+    jvm: &mut JVM,
+    th: &mut JThreadContext,
+    args: &[JValue],
+    millis: u64,
+) -> anyhow::Result<Option<JValue>> {
+    let this = this_ref(args)?;
+    if jvm.is_alive(th, this) {
+        let until = (millis > 0).then(|| jvm.millis().saturating_add(millis));
+        th.block(BlockReason::Join(this, until));
+    }
+    Ok(None)
+}
+
+/// Milliseconds since the Unix epoch in `fastTime`, as in the JDK.
+/// `new Date()` reads the clock of the VM.
+fn java_util_date() -> JClass {
+    let mut fields = HashMap::new();
+    let mut methods = HashMap::new();
+
+    let field = define_field(acc_field::PRIVATE | acc_field::TRANSIENT, "fastTime", "J");
+    fields.insert(field.name_desc.clone(), Rc::new(field));
+
+    let constructors: [(&str, NativeFn); 2] = [
+        ("()V", |jvm, _, args| {
+            let now = JValue::Long(jvm.clock.now().as_millis() as i64);
+            jvm.put_field(this_ref(args)?, "fastTime", "J", now)?;
+            Ok(None)
+        }),
+        ("(J)V", |jvm, _, args| {
+            jvm.put_field(this_ref(args)?, "fastTime", "J", args[1].clone())?;
+            Ok(None)
+        }),
+    ];
+    for (desc, func) in constructors {
+        let method = define_method(acc_method::PUBLIC, "<init>", desc, func);
+        methods.insert(method.name_desc.clone(), Rc::new(method));
+    }
+
+    let method = define_method(acc_method::PUBLIC, "getTime", "()J", |jvm, _, args| {
+        Ok(Some(jvm.get_field(this_ref(args)?, "fastTime", "J")?))
+    });
+    methods.insert(method.name_desc.clone(), Rc::new(method));
+
+    let method = define_method(acc_method::PUBLIC, "setTime", "(J)V", |jvm, _, args| {
+        jvm.put_field(this_ref(args)?, "fastTime", "J", args[1].clone())?;
+        Ok(None)
+    });
+    methods.insert(method.name_desc.clone(), Rc::new(method));
+
+    let method = define_method(
+        acc_method::PUBLIC,
+        "equals",
+        "(Ljava/lang/Object;)Z",
+        |jvm, _, args| {
+            let eq = match args[1] {
+                JValue::Ref(other)
+                    if jvm.heap.get(other).class.this_class.as_str() == "java/util/Date" =>
+                {
+                    jvm.get_field(this_ref(args)?, "fastTime", "J")?
+                        == jvm.get_field(other, "fastTime", "J")?
+                }
+                _ => false,
+            };
+            Ok(Some(JValue::Int(eq as i32)))
+        },
+    );
+    methods.insert(method.name_desc.clone(), Rc::new(method));
+
+    let method = define_method(acc_method::PUBLIC, "hashCode", "()I", |jvm, _, args| {
+        let JValue::Long(time) = jvm.get_field(this_ref(args)?, "fastTime", "J")? else {
+            anyhow::bail!("invalid fastTime");
+        };
+        Ok(Some(JValue::Int((time ^ (time >> 32)) as i32)))
+    });
+    methods.insert(method.name_desc.clone(), Rc::new(method));
+
+    let mut cls =
+        parse::define_native_class("java/util/Date", Some("java/lang/Object"), fields, methods);
+    cls.interfaces = vec![
+        Rc::new("java/io/Serializable".to_string()),
+        Rc::new("java/lang/Cloneable".to_string()),
+    ];
+    cls
+}
+
+/// The contents are a char[] in `value`. (see string.rs)
+/// `hash` caches hashCode, and 0 means not computed yet, as in the JDK.
+fn java_lang_string() -> JClass {
+    let mut fields = HashMap::new();
+    let mut methods = HashMap::new();
+
+    let field = define_field(acc_field::PRIVATE | acc_field::FINAL, "value", "[C");
+    fields.insert(field.name_desc.clone(), Rc::new(field));
+    let field = define_field(acc_field::PRIVATE, "hash", "I");
+    fields.insert(field.name_desc.clone(), Rc::new(field));
+
+    let constructors: [(&str, NativeFn); 4] = [
+        ("()V", |jvm, th, args| {
+            let value = jvm.with_roots(&[this_ref(args)?], |jvm| jvm.new_char_array(th, &[]))?;
+            jvm.put_field(this_ref(args)?, "value", "[C", JValue::Ref(value))?;
+            Ok(None)
+        }),
+        ("([C)V", |jvm, th, args| {
+            let chars = char_array(jvm, &args[1])?.to_vec();
+            let value = jvm.with_roots(&[this_ref(args)?], |jvm| jvm.new_char_array(th, &chars))?;
+            jvm.put_field(this_ref(args)?, "value", "[C", JValue::Ref(value))?;
+            Ok(None)
+        }),
+        ("([CII)V", |jvm, th, args| {
+            let (JValue::Int(offset), JValue::Int(count)) = (&args[2], &args[3]) else {
+                anyhow::bail!("invalid args");
+            };
+            let chars = char_array(jvm, &args[1])?;
+            let len = chars.len();
+            let range = usize::try_from(*offset)
+                .ok()
+                .zip(usize::try_from(*count).ok())
+                .map(|(offset, count)| offset..offset + count)
+                .filter(|range| range.end <= len);
+            let Some(range) = range else {
+                throw!(
+                    StringIndexOutOfBoundsException,
+                    "offset {offset}, count {count}, length {len}"
+                );
+            };
+            let chars = chars[range].to_vec();
+            let value = jvm.with_roots(&[this_ref(args)?], |jvm| jvm.new_char_array(th, &chars))?;
+            jvm.put_field(this_ref(args)?, "value", "[C", JValue::Ref(value))?;
+            Ok(None)
+        }),
+        // the contents are shared, since a String never changes them
+        ("(Ljava/lang/String;)V", |jvm, _, args| {
+            let JValue::Ref(original) = args[1] else {
+                throw!(NullPointerException, "Cannot copy a null String");
+            };
+            let value = jvm.get_field(original, "value", "[C")?;
+            jvm.put_field(this_ref(args)?, "value", "[C", value)?;
+            Ok(None)
+        }),
+    ];
+    for (desc, func) in constructors {
+        let method = define_method(acc_method::PUBLIC, "<init>", desc, func);
+        methods.insert(method.name_desc.clone(), Rc::new(method));
+    }
+
+    let method = define_method(acc_method::PUBLIC, "length", "()I", |jvm, _, args| {
+        let len = this_chars(jvm, args)?.len();
+        Ok(Some(JValue::Int(len as i32)))
+    });
+    methods.insert(method.name_desc.clone(), Rc::new(method));
+
+    let method = define_method(acc_method::PUBLIC, "charAt", "(I)C", |jvm, _, args| {
+        let JValue::Int(index) = args[1] else {
+            anyhow::bail!("invalid index");
+        };
+        let chars = this_chars(jvm, args)?;
+        let len = chars.len();
+        let c = usize::try_from(index).ok().and_then(|i| chars.get(i));
+        let Some(&c) = c else {
+            throw!(
+                StringIndexOutOfBoundsException,
+                "Index {index} out of bounds for length {len}"
+            );
+        };
+        Ok(Some(JValue::Int(c as i32)))
+    });
+    methods.insert(method.name_desc.clone(), Rc::new(method));
+
+    let method = define_method(acc_method::PUBLIC, "hashCode", "()I", |jvm, _, args| {
+        let this = this_ref(args)?;
+        let JValue::Int(mut hash) = jvm.get_field(this, "hash", "I")? else {
+            anyhow::bail!("invalid hash");
+        };
+        if hash == 0 {
+            hash = JVM::string_hash(this_chars(jvm, args)?);
+            jvm.put_field(this, "hash", "I", JValue::Int(hash))?;
+        }
+        Ok(Some(JValue::Int(hash)))
+    });
+    methods.insert(method.name_desc.clone(), Rc::new(method));
+
+    let method = define_method(
+        acc_method::PUBLIC,
+        "equals",
+        "(Ljava/lang/Object;)Z",
+        |jvm, _, args| {
+            let eq = match args[1] {
+                JValue::Ref(other) => jvm
+                    .string_chars(other)
+                    .is_some_and(|other| this_chars(jvm, args).is_ok_and(|this| this == other)),
+                _ => false,
+            };
+            Ok(Some(JValue::Int(eq as i32)))
+        },
+    );
+    methods.insert(method.name_desc.clone(), Rc::new(method));
+
+    let method = define_method(
+        acc_method::PUBLIC,
+        "intern",
+        "()Ljava/lang/String;",
+        |jvm, _, args| Ok(Some(JValue::Ref(jvm.intern(this_ref(args)?)?))),
+    );
+    methods.insert(method.name_desc.clone(), Rc::new(method));
+
+    let method = define_method(
+        acc_method::PUBLIC,
+        "toString",
+        "()Ljava/lang/String;",
+        |_, _, args| Ok(Some(args[0].clone())),
+    );
+    methods.insert(method.name_desc.clone(), Rc::new(method));
+
+    let mut cls = parse::define_native_class(
+        "java/lang/String",
+        Some("java/lang/Object"),
+        fields,
+        methods,
+    );
+    cls.access_flags = acc_class::PUBLIC | acc_class::FINAL;
+    cls
+}
+
+/// The contents of `this` String.
+fn this_chars<'a>(jvm: &'a JVM, args: &[JValue]) -> anyhow::Result<&'a [u16]> {
+    jvm.string_chars(this_ref(args)?)
+        .context("String is not constructed")
+}
+
+/// The elements of a char[] argument. NullPointerException if null.
+fn char_array<'a>(jvm: &'a JVM, v: &JValue) -> anyhow::Result<&'a [u16]> {
+    let JValue::Ref(r) = *v else {
+        throw!(
+            NullPointerException,
+            "Cannot read the array length because the reference is null"
+        );
+    };
+    match jvm.heap.get(r).array.as_ref() {
+        Some(array::JArray::Char(a)) => Ok(a),
+        _ => anyhow::bail!("not a char[]"),
+    }
+}
+
+/// Instances are created by the VM. (see [JVM::class_object])
+fn java_lang_class() -> JClass {
+    let mut cls = parse::define_native_class(
+        "java/lang/Class",
+        Some("java/lang/Object"),
+        HashMap::new(),
+        HashMap::new(),
+    );
+    cls.access_flags = acc_class::PUBLIC | acc_class::FINAL;
+    cls
+}
+
+/// Throwable() and Throwable(String), which fill in the stack trace.
+fn throwable_constructors() -> HashMap<String, Rc<MethodInfo>> {
+    let mut methods = HashMap::new();
+
+    for desc in ["()V", "(Ljava/lang/String;)V"] {
+        let method = define_method(acc_method::PUBLIC, "<init>", desc, |jvm, th, args| {
+            let JValue::Ref(this) = args[0] else {
+                anyhow::bail!("invalid this");
+            };
+            jvm.fill_in_stack_trace(th, this)?;
+            if let Some(&JValue::Ref(message)) = args.get(1) {
+                let message = jvm.get_string(message);
+                jvm.exceptions.entry(this).or_default().message = message;
+            }
+            Ok(None)
+        });
+        methods.insert(method.name_desc.clone(), Rc::new(method));
+    }
+
+    methods
+}
+
+fn java_lang_throwable() -> JClass {
+    let mut methods = throwable_constructors();
+
+    let method = define_method(
+        acc_method::PUBLIC,
+        "printStackTrace",
+        "()V",
+        |jvm, _, args| {
+            let JValue::Ref(this) = args[0] else {
+                anyhow::bail!("invalid this");
+            };
+            jvm.print_stack_trace(this);
+            Ok(None)
+        },
+    );
+    methods.insert(method.name_desc.clone(), Rc::new(method));
+
+    parse::define_native_class(
+        "java/lang/Throwable",
+        Some("java/lang/Object"),
+        HashMap::new(),
+        methods,
+    )
+}
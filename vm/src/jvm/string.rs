@@ -0,0 +1,408 @@
+//! java/lang/String instances and loadable constants.
+//!
+//! A String holds its contents as UTF-16 code units in a char[] (the `value`
+//! field), as Java does, so charAt, length and hashCode are exactly Java's
+//! even for text which is not valid Unicode.
+//! String literals (CONSTANT_String) and String.intern share one VM-wide
+//! table, so the same literal in any class is the same instance.
+
+use super::*;
+
+use anyhow::Context;
+use array::JArray;
+
+impl JVM {
+    /// Create a new String instance of the text. (not interned)
+    /// Garbage is collected if the heap is full, as [JVM::alloc_or_collect].
+    pub fn new_string(&mut self, th: &JThreadContext, s: &str) -> anyhow::Result<JRef> {
+        let chars: Vec<u16> = s.encode_utf16().collect();
+        self.new_string_utf16(th, &chars)
+    }
+
+    /// Create a new String instance of the UTF-16 code units. (not interned)
+    pub fn new_string_utf16(&mut self, th: &JThreadContext, chars: &[u16]) -> anyhow::Result<JRef> {
+        let value = self.new_char_array(th, chars)?;
+        let r = self.with_roots(&[value], |jvm| {
+            jvm.alloc_or_collect(th, |jvm| jvm.alloc_object("java/lang/String"))
+        })?;
+        self.put_field(r, "value", "[C", JValue::Ref(value))?;
+
+        Ok(r)
+    }
+
+    /// A String[] of new Strings. (the arguments of main)
+    pub fn new_string_array(
+        &mut self,
+        th: &JThreadContext,
+        strs: &[String],
+    ) -> anyhow::Result<JRef> {
+        let len = i32::try_from(strs.len()).unwrap_or(i32::MAX);
+        let array = self.alloc_or_collect(th, |jvm| jvm.alloc_array("[Ljava/lang/String;", len))?;
+        self.with_roots(&[array], |jvm| {
+            for (i, s) in strs.iter().enumerate() {
+                let s = jvm.new_string(th, s)?;
+                jvm.heap
+                    .get_mut(array)
+                    .elements_mut()
+                    .set(i, JValue::Ref(s))?;
+            }
+            Ok(())
+        })?;
+
+        Ok(array)
+    }
+
+    /// A char[] with a copy of the code units.
+    pub(super) fn new_char_array(
+        &mut self,
+        th: &JThreadContext,
+        chars: &[u16],
+    ) -> anyhow::Result<JRef> {
+        // too long for an array: OutOfMemoryError by the size limit
+        let len = i32::try_from(chars.len()).unwrap_or(i32::MAX);
+        let value = self.alloc_or_collect(th, |jvm| jvm.alloc_array("[C", len))?;
+        if let JArray::Char(a) = self.heap.get_mut(value).elements_mut() {
+            a.copy_from_slice(chars);
+        }
+
+        Ok(value)
+    }
+
+    /// The interned String instance of the text. (a string literal)
+    pub fn intern_string(&mut self, th: &JThreadContext, s: &str) -> anyhow::Result<JRef> {
+        let chars: Vec<u16> = s.encode_utf16().collect();
+        self.intern_utf16(th, &chars)
+    }
+
+    /// The interned String instance of the UTF-16 code units.
+    pub fn intern_utf16(&mut self, th: &JThreadContext, chars: &[u16]) -> anyhow::Result<JRef> {
+        if let Some(&r) = self.interned.get(chars) {
+            return Ok(r);
+        }
+        let r = self.new_string_utf16(th, chars)?;
+        self.interned.insert(chars.into(), r);
+
+        Ok(r)
+    }
+
+    /// String.intern: the instance in the table with the same contents, or
+    /// the String itself after adding it.
+    pub(super) fn intern(&mut self, r: JRef) -> anyhow::Result<JRef> {
+        let chars = self.string_chars(r).context("not a String")?;
+        if let Some(&interned) = self.interned.get(chars) {
+            return Ok(interned);
+        }
+        self.interned.insert(chars.into(), r);
+
+        Ok(r)
+    }
+
+    /// The UTF-16 contents of a String instance, or None if it is not a
+    /// String (or not constructed yet).
+    pub fn string_chars(&self, r: JRef) -> Option<&[u16]> {
+        let obj = self.heap.get(r);
+        if obj.class.this_class.as_str() != "java/lang/String" {
+            return None;
+        }
+        let layout = self.class_rt["java/lang/String"].layout.as_ref()?;
+        let value = JRef::from_slot(obj.fields[layout.slots["value[C"] as usize])?;
+        match self.heap.get(value).array.as_ref()? {
+            JArray::Char(a) => Some(a),
+            _ => None,
+        }
+    }
+
+    /// String.hashCode: `s[0]*31^(n-1) + s[1]*31^(n-2) + ... + s[n-1]` in
+    /// int arithmetic, over the UTF-16 code units.
+    pub fn string_hash(chars: &[u16]) -> i32 {
+        chars
+            .iter()
+            .fold(0i32, |h, &c| h.wrapping_mul(31).wrapping_add(c as i32))
+    }
+
+    /// The contents of a String instance as Rust text, or None if it is not
+    /// a String. An unpaired surrogate becomes U+FFFD.
+    pub fn get_string(&self, r: JRef) -> Option<String> {
+        self.string_chars(r).map(String::from_utf16_lossy)
+    }
+
+    /// The java/lang/Class instance of the class, array class or primitive
+    /// type, created on first use.
+    pub fn class_object(&mut self, th: &JThreadContext, name: &str) -> anyhow::Result<JRef> {
+        if let Some(&r) = self.class_objects.get(name) {
+            return Ok(r);
+        }
+        let r = self.alloc_or_collect(th, |jvm| jvm.alloc_object("java/lang/Class"))?;
+        self.class_objects.insert(name.to_string(), r);
+
+        Ok(r)
+    }
+
+    /// 5.1. The Run-Time Constant Pool
+    /// The value of a loadable constant (ldc, ldc_w, ldc2_w, ConstantValue).
+    pub(super) fn loadable_constant(
+        &mut self,
+        th: &JThreadContext,
+        info: &ConstInfo,
+    ) -> anyhow::Result<JValue> {
+        let v = match info {
+            ConstInfo::Integer { bytes } => JValue::Int(*bytes),
+            ConstInfo::Float { bytes } => JValue::Float(*bytes),
+            ConstInfo::Long { bytes } => JValue::Long(*bytes),
+            ConstInfo::Double { bytes } => JValue::Double(*bytes),
+            ConstInfo::String { string } => JValue::Ref(self.intern_utf16(th, string)?),
+            ConstInfo::Class { name } => JValue::Ref(self.class_object(th, name)?),
+            // TODO: MethodType, MethodHandle and Dynamic are not parsed yet
+            _ => anyhow::bail!("not a loadable constant: {info:?}"),
+        };
+
+        Ok(v)
+    }
+
+    /// ldc, ldc_w and ldc2_w: [JVM::loadable_constant] of the constant pool
+    /// entry. The String instance of a CONSTANT_String is cached per constant
+    /// pool index of the class definition, as interned Strings are never
+    /// collected.
+    pub(super) fn ldc(
+        &mut self,
+        th: &JThreadContext,
+        cls: &Rc<JClass>,
+        index: u16,
+    ) -> anyhow::Result<JValue> {
+        let info = cls.constant_pool.get(index)?;
+        if !matches!(info, ConstInfo::String { .. }) {
+            return self.loadable_constant(th, info);
+        }
+        let cache = self
+            .cp_cache(cls)
+            .and_then(|cache| cache.strings.get(&index));
+        if let Some(&r) = cache {
+            return Ok(JValue::Ref(r));
+        }
+
+        let v = self.loadable_constant(th, info)?;
+        if let JValue::Ref(r) = v {
+            self.cp_cache_mut(cls).strings.insert(index, r);
+        }
+
+        Ok(v)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use test_util::setup;
+
+    #[test]
+    fn test_intern() {
+        let mut jvm = setup();
+        let th = JThreadContext::default();
+
+        let a = jvm.intern_string(&th, "hello").unwrap();
+        let b = jvm.intern_string(&th, "hello").unwrap();
+        let c = jvm.new_string(&th, "hello").unwrap();
+        assert_eq!(a, b);
+        assert_ne!(a, c);
+        assert_eq!(jvm.get_string(c).as_deref(), Some("hello"));
+        assert_eq!(
+            jvm.heap.get(a).class.this_class.as_str(),
+            "java/lang/String"
+        );
+
+        let obj = jvm.alloc_object("java/lang/Object").unwrap();
+        assert_eq!(jvm.get_string(obj), None);
+
+        // String.intern returns the literal
+        assert_eq!(jvm.intern(c).unwrap(), a);
+        let d = jvm.new_string(&th, "fresh").unwrap();
+        assert_eq!(jvm.intern(d).unwrap(), d);
+        assert_eq!(jvm.intern_string(&th, "fresh").unwrap(), d);
+
+        let args = ["a".to_string(), "\u{3042}".to_string()];
+        let array = jvm.new_string_array(&th, &args).unwrap();
+        let JArray::Ref(elements) = jvm.heap.get(array).elements().clone() else {
+            panic!();
+        };
+        assert_eq!(jvm.get_string(elements[0].unwrap()).as_deref(), Some("a"));
+        assert_eq!(
+            jvm.get_string(elements[1].unwrap()).as_deref(),
+            Some("\u{3042}")
+        );
+    }
+
+    #[test]
+    fn test_string_methods() {
+        let mut jvm = setup();
+        let call = |jvm: &mut JVM, name, args: &[JValue]| test_util::call(jvm, "Strs", name, args);
+        let th = JThreadContext::default();
+        let string = |jvm: &mut JVM, s| jvm.new_string(&th, s).unwrap();
+
+        // the values of Java's String.hashCode
+        let hello = string(&mut jvm, "hello");
+        assert_eq!(
+            call(&mut jvm, "hash", &[JValue::Ref(hello)]),
+            JValue::Int(99162322)
+        );
+        let empty = string(&mut jvm, "");
+        assert_eq!(
+            call(&mut jvm, "hash", &[JValue::Ref(empty)]),
+            JValue::Int(0)
+        );
+        let long = string(&mut jvm, "The quick brown fox jumps over the lazy dog");
+        assert_eq!(
+            call(&mut jvm, "hash", &[JValue::Ref(long)]),
+            JValue::Int(-609428141)
+        );
+        assert_eq!(
+            call(&mut jvm, "length", &[JValue::Ref(hello)]),
+            JValue::Int(5)
+        );
+        assert_eq!(
+            call(&mut jvm, "charAt", &[JValue::Ref(hello), JValue::Int(1)]),
+            JValue::Int('e' as i32)
+        );
+
+        // UTF-16 code units, not Unicode characters
+        let JValue::Ref(emoji) = call(&mut jvm, "surrogates", &[]) else {
+            panic!();
+        };
+        assert_eq!(jvm.get_string(emoji).as_deref(), Some("\u{1F600}!"));
+        assert_eq!(
+            call(&mut jvm, "length", &[JValue::Ref(emoji)]),
+            JValue::Int(3)
+        );
+        assert_eq!(
+            call(&mut jvm, "charAt", &[JValue::Ref(emoji), JValue::Int(0)]),
+            JValue::Int(0xd83d)
+        );
+        assert_eq!(
+            call(&mut jvm, "charAt", &[JValue::Ref(emoji), JValue::Int(1)]),
+            JValue::Int(0xde00)
+        );
+        assert_eq!(
+            call(&mut jvm, "hash", &[JValue::Ref(emoji)]),
+            JValue::Int(54959902)
+        );
+
+        let mut th = JThreadContext::default();
+        let res = jvm.call_method_by_name(
+            &mut th,
+            "Strs",
+            "charAt",
+            &[JValue::Ref(hello), JValue::Int(5)],
+        );
+        let Ok(invoke::InvokeResult::Exception(e)) = res else {
+            panic!("{res:?}");
+        };
+        assert_eq!(
+            jvm.heap.get(e).class.this_class.as_str(),
+            "java/lang/StringIndexOutOfBoundsException"
+        );
+
+        // switch on a String, including two with the same hash
+        for (s, expected) in [
+            ("apple", 1),
+            ("banana", 2),
+            ("Aa", 3),
+            ("BB", 4),
+            ("cherry", 0),
+        ] {
+            let s = string(&mut jvm, s);
+            assert_eq!(
+                call(&mut jvm, "dispatch", &[JValue::Ref(s)]),
+                JValue::Int(expected)
+            );
+        }
+
+        assert_eq!(call(&mut jvm, "sameLiteral", &[]), JValue::Int(1));
+        assert_eq!(call(&mut jvm, "interned", &[]), JValue::Int(1));
+        let JValue::Ref(s) = call(&mut jvm, "slice", &[JValue::Int(1), JValue::Int(2)]) else {
+            panic!();
+        };
+        assert_eq!(jvm.get_string(s).as_deref(), Some("bc"));
+        let res =
+            jvm.call_method_by_name(&mut th, "Strs", "slice", &[JValue::Int(3), JValue::Int(2)]);
+        assert!(
+            matches!(res, Ok(invoke::InvokeResult::Exception(_))),
+            "{res:?}"
+        );
+    }
+
+    #[test]
+    fn test_collect_when_full() {
+        let mut jvm = test_util::setup_with_config(JVMConfig {
+            max_heap: 256 << 10,
+            max_object_size: 64 << 10,
+        });
+        let th = JThreadContext::default();
+        // the heap is filled with garbage, up to the last few bytes
+        let fill = |jvm: &mut JVM| {
+            for len in [1 << 10, 1 << 4, 1] {
+                while jvm.alloc_array("[I", len).is_ok() {}
+            }
+        };
+
+        fill(&mut jvm);
+        let s = jvm.new_string(&th, "after").unwrap();
+        assert_eq!(jvm.get_string(s).as_deref(), Some("after"));
+        fill(&mut jvm);
+        jvm.new_string_array(&th, &["a".to_string()]).unwrap();
+        fill(&mut jvm);
+        jvm.class_object(&th, "Strs").unwrap();
+        fill(&mut jvm);
+        let JValue::Long(max) = test_util::call(&mut jvm, "Mem", "max", &[]) else {
+            panic!();
+        };
+        assert_eq!(max, 256 << 10);
+    }
+
+    #[test]
+    fn test_ldc() {
+        let mut jvm = setup();
+        let call = |jvm: &mut JVM, name| test_util::call(jvm, "Ldc", name, &[]);
+
+        assert_eq!(call(&mut jvm, "bigInt"), JValue::Int(1_000_000));
+        assert_eq!(call(&mut jvm, "half"), JValue::Float(2.5));
+        assert_eq!(call(&mut jvm, "bigLong"), JValue::Long(1 << 40));
+        assert_eq!(call(&mut jvm, "e"), JValue::Double(std::f64::consts::E));
+
+        // the same literal in another method and the ConstantValue is the
+        // same instance
+        let JValue::Ref(s) = call(&mut jvm, "greeting") else {
+            panic!();
+        };
+        assert_eq!(jvm.get_string(s).as_deref(), Some("hello, ldc"));
+        assert_eq!(call(&mut jvm, "greeting2"), JValue::Ref(s));
+        let v = jvm.get_static(
+            &mut JThreadContext::default(),
+            "Ldc",
+            "GREETING",
+            "Ljava/lang/String;",
+        );
+        assert_eq!(v.unwrap(), JValue::Ref(s));
+
+        // the code units are kept, and the same instance is loaded again
+        let JValue::Ref(s) = call(&mut jvm, "surrogate") else {
+            panic!();
+        };
+        assert_eq!(jvm.string_chars(s), Some(&[0x61, 0xd800, 0x62][..]));
+        assert_eq!(call(&mut jvm, "surrogate"), JValue::Ref(s));
+        assert_eq!(
+            jvm.cp_cache(&jvm.get_class("Ldc").unwrap())
+                .unwrap()
+                .strings
+                .len(),
+            2
+        );
+
+        let JValue::Ref(c) = call(&mut jvm, "self") else {
+            panic!();
+        };
+        assert_eq!(
+            jvm.class_object(&JThreadContext::default(), "Ldc").unwrap(),
+            c
+        );
+        assert_eq!(jvm.heap.get(c).class.this_class.as_str(), "java/lang/Class");
+    }
+}
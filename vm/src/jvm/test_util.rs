@@ -0,0 +1,73 @@
+//! Fixtures shared by the tests of the modules.
+
+use super::*;
+
+/// A VM with the core classes and the sample classes loaded.
+pub fn setup() -> JVM {
+    setup_with_config(JVMConfig::default())
+}
+
+pub fn setup_with_config(config: JVMConfig) -> JVM {
+    let mut jvm = JVM::with_config(config);
+    stdlib::load_core(&mut jvm);
+    for bin in crate::res::SAMPLE_CLASS_FILES {
+        jvm.load_class(bin).unwrap();
+    }
+    jvm
+}
+
+/// Invoke the method by name on a new main thread.
+pub fn invoke(jvm: &mut JVM, clsname: &str, name: &str, args: &[JValue]) -> InvokeResult {
+    let mut th = JThreadContext::default();
+    jvm.call_method_by_name(&mut th, clsname, name, args)
+        .unwrap()
+}
+
+/// The return value of a method which returns one.
+pub fn call(jvm: &mut JVM, clsname: &str, name: &str, args: &[JValue]) -> JValue {
+    match invoke(jvm, clsname, name, args) {
+        InvokeResult::Return(Some(v)) => v,
+        res => panic!("{clsname}.{name}: {res:?}"),
+    }
+}
+
+/// The class name of the exception thrown by the invocation.
+pub fn exception_class(jvm: &JVM, res: InvokeResult) -> String {
+    match res {
+        InvokeResult::Exception(ex) => jvm.heap.get(ex).class.this_class.to_string(),
+        res => panic!("{res:?}"),
+    }
+}
+
+/// A class defined on the host. Its methods return at once, except the
+/// abstract ones, which have no code.
+pub fn define_class(
+    access_flags: u16,
+    name: &str,
+    super_class: Option<&str>,
+    interfaces: &[&str],
+    fields: &[(u16, &str, &str)],
+    methods: &[(u16, &str, &str)],
+) -> JClass {
+    let fields = fields
+        .iter()
+        .map(|&(access_flags, name, desc)| {
+            let f = stdlib::define_field(access_flags, name, desc);
+            (f.name_desc.clone(), Rc::new(f))
+        })
+        .collect();
+    let methods = methods
+        .iter()
+        .map(|&(access_flags, name, desc)| {
+            let mut m = stdlib::define_method(access_flags, name, desc, |_, _, _| Ok(None));
+            if access_flags & acc_method::ABSTRACT != 0 {
+                m.method_body = MethodBody::None;
+            }
+            (m.name_desc.clone(), Rc::new(m))
+        })
+        .collect();
+    let mut cls = parse::define_native_class(name, super_class, fields, methods);
+    cls.access_flags = access_flags;
+    cls.interfaces = interfaces.iter().map(|s| Rc::new(s.to_string())).collect();
+    cls
+}
@@ -0,0 +1,396 @@
+//! Green threads.
+//!
+//! All Java threads run on the host thread, interleaved by the interpreter,
+//! so no host threads are needed. Slices are timed by the [clock::Clock] of
+//! the VM, which a host without std::time (wasm) supplies.
+//! [JVM::run_for] takes the threads round-robin and runs each for a turn of
+//! instructions, until the budget of the slice runs out. The turn is
+//! proportional to the priority of the thread, so a thread of higher
+//! priority runs longer but no thread starves. A thread also gives up its
+//! turn when it yields, sleeps or blocks.
+//!
+//! A java/lang/Thread instance is tied to its [JThreadContext] when it is
+//! started. The main thread gets its instance on the first currentThread.
+
+use super::*;
+
+use std::time::Duration;
+
+/// A thread owned by the [JVM].
+pub(super) struct JThread {
+    pub ctx: JThreadContext,
+    /// Why the thread cannot run now.
+    pub blocked: Option<BlockReason>,
+}
+
+/// State of the threads when [JVM::run_for] returns.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SchedulerState {
+    /// Every thread has terminated.
+    Terminated,
+    /// The budget ran out, and threads can continue.
+    Yielded,
+    /// Every thread is blocked, and the first one can resume after the
+    /// duration. (sleeping, or waiting with a timeout)
+    /// A clock which advances when idle ([clock::VirtualClock]) never
+    /// returns it.
+    Idle(Duration),
+    /// Every thread is blocked without a timeout.
+    Deadlocked,
+}
+
+/// Thread priorities. (constants of java.lang.Thread)
+pub mod priority {
+    pub const MIN: i32 = 1;
+    pub const NORM: i32 = 5;
+    pub const MAX: i32 = 10;
+}
+
+/// Instructions of a turn at the normal priority.
+const TURN: u64 = 1000;
+
+impl JVM {
+    /// Add a thread to be run by [JVM::run_for]. The host sets up its
+    /// frames with [JVM::call_method].
+    pub fn spawn(&mut self, th: JThreadContext) {
+        self.threads.push_back(JThread {
+            ctx: th,
+            blocked: None,
+        });
+    }
+
+    /// Number of threads owned by the VM which have not terminated.
+    pub fn thread_count(&self) -> usize {
+        self.threads.len()
+    }
+
+    /// Run the threads for a slice limited by the budget.
+    ///
+    /// An uncaught exception terminates the thread and is printed.
+    /// The state of each thread stays in the VM, and calling it again
+    /// continues. If `gc_time` is set, a slice of incremental GC runs first.
+    pub fn run_for(&mut self, budget: Budget) -> anyhow::Result<SchedulerState> {
+        if let Some(gc_time) = budget.gc_time {
+            self.gc_slice(None, gc_time);
+        }
+        self.start_slice(budget);
+        let state = self.schedule();
+        self.end_slice();
+
+        state
+    }
+
+    fn schedule(&mut self) -> anyhow::Result<SchedulerState> {
+        loop {
+            if self.threads.is_empty() {
+                return Ok(SchedulerState::Terminated);
+            }
+            if self.budget.instructions.is_some_and(|n| self.executed >= n)
+                || self.deadline_passed()
+            {
+                return Ok(SchedulerState::Yielded);
+            }
+            let Some(mut thread) = self.next_thread() else {
+                match self.idle_state() {
+                    // a virtual clock jumps to the timeout
+                    SchedulerState::Idle(duration) if self.clock.idle(duration) => continue,
+                    state => return Ok(state),
+                }
+            };
+
+            let priority = self.priority(&thread.ctx)?;
+            self.turn_end = self.executed + TURN * priority as u64 / priority::NORM as u64;
+            let state = self.run_until(&mut thread.ctx, 0);
+            self.turn_end = u64::MAX;
+            match state {
+                Ok(ThreadState::Finished(_)) => {}
+                Ok(ThreadState::Uncaught(ex)) => self.print_uncaught(&thread.ctx, ex),
+                Ok(ThreadState::Yielded) => self.threads.push_back(thread),
+                Ok(ThreadState::Blocked(reason)) => {
+                    thread.blocked = Some(reason);
+                    self.threads.push_back(thread);
+                }
+                Err(err) => {
+                    self.threads.push_front(thread);
+                    return Err(err);
+                }
+            }
+        }
+    }
+
+    /// Take the first thread that can run, unblocking it if resumable.
+    fn next_thread(&mut self) -> Option<JThread> {
+        for _ in 0..self.threads.len() {
+            let mut thread = self.threads.pop_front()?;
+            match thread.blocked.take() {
+                Some(reason) if !self.can_resume(&thread.ctx, &reason) => {
+                    thread.blocked = Some(reason);
+                    self.threads.push_back(thread);
+                }
+                Some(BlockReason::Wait(r, _)) => {
+                    self.reenter(&thread.ctx, r);
+                    return Some(thread);
+                }
+                _ => return Some(thread),
+            }
+        }
+        None
+    }
+
+    fn can_resume(&self, th: &JThreadContext, reason: &BlockReason) -> bool {
+        let now = self.millis();
+        match *reason {
+            BlockReason::Sleep(until) => now >= until,
+            BlockReason::Join(thread, until) => {
+                !self.is_alive(th, thread) || until.is_some_and(|until| now >= until)
+            }
+            BlockReason::Monitor(r) => self.can_enter(th.id, r),
+            BlockReason::Wait(r, until) => self.can_reenter(th, r, until),
+        }
+    }
+
+    /// A thread blocked in a host invocation, where no other thread runs.
+    /// Returns when it can resume, after advancing the clock to the timeout
+    /// if the clock can ([clock::VirtualClock]).
+    /// Otherwise it would never resume, and IllegalThreadStateException is
+    /// thrown where it blocked.
+    pub(super) fn block_in_host(
+        &mut self,
+        th: &mut JThreadContext,
+        reason: BlockReason,
+    ) -> anyhow::Result<()> {
+        while !self.can_resume(th, &reason) {
+            let now = self.millis();
+            let until = match reason {
+                BlockReason::Sleep(until) => Some(until),
+                BlockReason::Join(_, until) | BlockReason::Wait(_, until) => until,
+                BlockReason::Monitor(_) => None,
+            };
+            let idle = until
+                .filter(|&until| until > now)
+                .is_some_and(|until| self.clock.idle(Duration::from_millis(until - now)));
+            if !idle {
+                // thrown with the monitor taken back, as from wait
+                if let BlockReason::Wait(r, _) = reason {
+                    self.reenter(th, r);
+                }
+                // a synchronized method not entered yet throws in the caller
+                let frame = th.current_frame();
+                if frame.monitor.is_some() && !frame.locked {
+                    th.pop_frame();
+                }
+                throw!(
+                    IllegalThreadStateException,
+                    "cannot block in a host invocation: {reason:?}"
+                );
+            }
+        }
+        if let BlockReason::Wait(r, _) = reason {
+            self.reenter(th, r);
+        }
+
+        Ok(())
+    }
+
+    /// When no thread can run: Idle until the first timeout, or Deadlocked.
+    /// (a timed out wait may still need a monitor held by a sleeping thread)
+    fn idle_state(&self) -> SchedulerState {
+        let now = self.millis();
+        let wake = self
+            .threads
+            .iter()
+            .filter_map(|t| match t.blocked {
+                Some(BlockReason::Sleep(until)) => Some(until),
+                Some(BlockReason::Join(_, until) | BlockReason::Wait(_, until)) => until,
+                _ => None,
+            })
+            .filter(|&until| until > now)
+            .min();
+        match wake {
+            Some(until) => SchedulerState::Idle(Duration::from_millis(until - now)),
+            None => SchedulerState::Deadlocked,
+        }
+    }
+
+    /// The priority of the Thread object of the thread.
+    fn priority(&mut self, th: &JThreadContext) -> anyhow::Result<i32> {
+        let Some(object) = th.object else {
+            return Ok(priority::NORM);
+        };
+        match self.get_field(object, "priority", "I")? {
+            JValue::Int(priority) => Ok(priority),
+            v => anyhow::bail!("invalid priority: {v:?}"),
+        }
+    }
+
+    fn is_queued(&self, thread: JRef) -> bool {
+        self.threads.iter().any(|t| t.ctx.object == Some(thread))
+    }
+
+    /// Thread.isAlive: started and not terminated.
+    pub(super) fn is_alive(&self, th: &JThreadContext, thread: JRef) -> bool {
+        th.object == Some(thread) || self.is_queued(thread)
+    }
+
+    /// Thread.currentThread. The Thread object of a thread run by the host
+    /// is created on first use.
+    pub(super) fn current_thread(&mut self, th: &mut JThreadContext) -> anyhow::Result<JRef> {
+        if let Some(object) = th.object {
+            return Ok(object);
+        }
+        let object = self.alloc_or_collect(th, |jvm| jvm.alloc_object("java/lang/Thread"))?;
+        th.object = Some(object);
+        let name = self.new_string(th, &th.name)?;
+        self.put_field(object, "name", "Ljava/lang/String;", JValue::Ref(name))?;
+        self.put_field(object, "priority", "I", JValue::Int(priority::NORM))?;
+        self.put_field(object, "started", "Z", JValue::Int(1))?;
+
+        Ok(object)
+    }
+
+    /// Thread.start: a new thread calls `run()` of the Thread object.
+    pub(super) fn start_thread(&mut self, thread: JRef) -> anyhow::Result<()> {
+        throw_unless!(
+            self.get_field(thread, "started", "Z")? == JValue::Int(0),
+            IllegalThreadStateException,
+            "already started"
+        );
+        self.put_field(thread, "started", "Z", JValue::Int(1))?;
+        let name = match self.get_field(thread, "name", "Ljava/lang/String;")? {
+            JValue::Ref(name) => self.get_string(name).unwrap_or_default(),
+            _ => String::new(),
+        };
+
+        let mut th = JThreadContext::new(self.next_thread_id, &name);
+        self.next_thread_id += 1;
+        th.object = Some(thread);
+        // a native run() may return at once, and the thread terminates
+        // on its first turn
+        self.call_virtual(&mut th, thread, "run", "()V", &[])?;
+        self.spawn(th);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use test_util::setup;
+
+    /// Spawn the main thread to run the static method of Threads.
+    fn spawn_main(jvm: &mut JVM, name: &str) {
+        let mut th = JThreadContext::default();
+        jvm.initialize_class(&mut th, "Threads").unwrap();
+        let cls = jvm.get_class("Threads").unwrap();
+        let method = cls.get_method(name).unwrap();
+        jvm.call_method(&mut th, cls, method, &[]).unwrap();
+        jvm.spawn(th);
+    }
+
+    /// Run the threads until they terminate or deadlock, sleeping when idle.
+    fn run_all(jvm: &mut JVM) -> SchedulerState {
+        loop {
+            match jvm.run_for(Budget::default()).unwrap() {
+                SchedulerState::Idle(duration) => std::thread::sleep(duration),
+                SchedulerState::Yielded => {}
+                state => return state,
+            }
+        }
+    }
+
+    fn get_static(jvm: &mut JVM, clsname: &str, name: &str, desc: &str) -> JValue {
+        let mut th = JThreadContext::default();
+        jvm.get_static(&mut th, clsname, name, desc).unwrap()
+    }
+
+    fn ints(jvm: &mut JVM, name: &str) -> Vec<i32> {
+        let JValue::Ref(log) = get_static(jvm, "Threads", name, "[I") else {
+            panic!();
+        };
+        let array::JArray::Int(a) = jvm.heap.get(log).elements() else {
+            panic!();
+        };
+        a.to_vec()
+    }
+
+    #[test]
+    fn test_start_join() {
+        let mut jvm = setup();
+        spawn_main(&mut jvm, "interleave()V");
+        assert_eq!(run_all(&mut jvm), SchedulerState::Terminated);
+        assert_eq!(jvm.thread_count(), 0);
+
+        // yield passes the turn to the other thread, and join waits for both
+        let log = ints(&mut jvm, "log");
+        assert_eq!(log[..6], [1, 2, 1, 2, 1, 2]);
+        assert_eq!(
+            get_static(&mut jvm, "Threads", "total", "I"),
+            JValue::Int(9)
+        );
+        assert_eq!(get_static(&mut jvm, "Threads", "ok", "Z"), JValue::Int(1));
+
+        spawn_main(&mut jvm, "subclass()V");
+        assert_eq!(run_all(&mut jvm), SchedulerState::Terminated);
+        assert_eq!(get_static(&mut jvm, "Threads", "ok", "Z"), JValue::Int(1));
+    }
+
+    #[test]
+    fn test_sleep() {
+        let mut jvm = setup();
+        spawn_main(&mut jvm, "sleepy()V");
+
+        let start = jvm.clock.monotonic();
+        let res = jvm.run_for(Budget::default()).unwrap();
+        let SchedulerState::Idle(duration) = res else {
+            panic!("{res:?}");
+        };
+        assert!(duration <= Duration::from_millis(30));
+        assert_eq!(jvm.thread_count(), 1);
+        assert_eq!(get_static(&mut jvm, "Sleeper", "woke", "Z"), JValue::Int(0));
+
+        assert_eq!(run_all(&mut jvm), SchedulerState::Terminated);
+        assert!(jvm.clock.monotonic() - start >= Duration::from_millis(30));
+        assert_eq!(get_static(&mut jvm, "Sleeper", "woke", "Z"), JValue::Int(1));
+    }
+
+    #[test]
+    fn test_budget_and_priority() {
+        let mut jvm = setup();
+        spawn_main(&mut jvm, "spin()V");
+
+        // the threads never end, and share each slice
+        for _ in 0..10 {
+            let res = jvm.run_for(Budget::instructions(20_000)).unwrap();
+            assert_eq!(res, SchedulerState::Yielded);
+        }
+        assert_eq!(jvm.thread_count(), 2);
+        let spins = ints(&mut jvm, "spins");
+        // MAX_PRIORITY gets turns 10 times as long as MIN_PRIORITY
+        assert!(spins[1] > 0);
+        assert!(spins[0] > spins[1] * 5, "{spins:?}");
+
+        // the Runnables are reachable only from the stacks of the threads
+        jvm.gc(&JThreadContext::default());
+        let histogram = jvm.heap_histogram();
+        let spinners = histogram.iter().find(|e| e.class.as_str() == "Spinner");
+        assert_eq!(spinners.map(|e| e.instances), Some(2));
+        jvm.run_for(Budget::instructions(20_000)).unwrap();
+        assert!(ints(&mut jvm, "spins")[1] > spins[1]);
+    }
+
+    #[test]
+    fn test_thread_state() {
+        let mut jvm = setup();
+        spawn_main(&mut jvm, "states()V");
+        assert_eq!(run_all(&mut jvm), SchedulerState::Terminated);
+        // main, Thread-0, IllegalThreadStateException, IllegalArgumentException
+        let checks = ints(&mut jvm, "checks");
+        assert_eq!(checks, [1, 1, 1, 1, 1, 1]);
+
+        // join on itself never returns
+        spawn_main(&mut jvm, "selfJoin()V");
+        assert_eq!(run_all(&mut jvm), SchedulerState::Deadlocked);
+        assert_eq!(jvm.thread_count(), 1);
+    }
+}
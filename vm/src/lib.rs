@@ -0,0 +1,7 @@
+//! A JVM which runs Java programs (the mc2 game) on the host thread.
+//!
+//! [jvm::JVM] is the host API. The `vm` binary runs the main method of a
+//! built-in class.
+
+pub mod jvm;
+pub mod res;
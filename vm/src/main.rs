@@ -1,10 +1,8 @@
-use crate::jvm::JThreadContext;
+use anyhow::Context;
+use vm::jvm::{self, JThreadContext};
+use vm::res;
 
-mod jvm;
-mod res;
-
-#[allow(dead_code)]
-fn test_dump_method(vm: &jvm::JVM, cls: &str, method: &str) -> anyhow::Result<()> {
+fn dump_method(vm: &jvm::JVM, cls: &str, method: &str) -> anyhow::Result<()> {
     let main_class = vm.get_class(cls)?;
     let method = main_class.get_method(method)?;
     println!("{method:?}");
@@ -18,34 +16,64 @@ fn test_dump_method(vm: &jvm::JVM, cls: &str, method: &str) -> anyhow::Result<()
             println!("native call");
             return Ok(());
         }
-        jvm::MethodBody::Java(code) => &code.code,
+        jvm::MethodBody::Java(code) => &code.ops,
     };
 
-    let mut pc: &[u8] = code;
-    let mut ind = 0;
-    while !pc.is_empty() {
-        let (op, len) = jvm::next_op(pc)?;
-        println!("[{ind:02}] {op:?}");
-        ind += 1;
-        pc = &pc[len..];
+    for (ind, (pc, op)) in code.ops().iter().enumerate() {
+        println!("[{ind:02}] {pc}: {op:?}");
     }
 
     Ok(())
 }
 
-fn run_main(vm: &mut jvm::JVM, cls: &str) -> anyhow::Result<()> {
+fn run_main(vm: &mut jvm::JVM, cls: &str, args: &[String]) -> anyhow::Result<()> {
     let main_class = vm.get_class(cls)?;
     let method = main_class.get_method("main([Ljava/lang/String;)V")?;
     println!("Invoke {cls}.main(String[] args)");
 
     let mut th = JThreadContext::default();
-    vm.invoke_static(&mut th, main_class, method)?;
-    vm.run(&mut th)?;
+    if let Err(err) = vm.initialize_class(&mut th, cls) {
+        let ex = vm.exception_object(&th, err)?;
+        vm.print_uncaught(&th, ex);
+        std::process::exit(1);
+    }
+    let args = vm.new_string_array(&th, args)?;
+    vm.call_method(&mut th, main_class, method, &[jvm::JValue::Ref(args)])?;
+    vm.spawn(th);
+    // run until all threads terminate, and an uncaught exception is printed
+    loop {
+        match vm.run_for(jvm::Budget::default())? {
+            jvm::SchedulerState::Terminated => break,
+            jvm::SchedulerState::Yielded => {}
+            jvm::SchedulerState::Idle(duration) => std::thread::sleep(duration),
+            jvm::SchedulerState::Deadlocked => anyhow::bail!("all threads are blocked"),
+        }
+    }
 
     Ok(())
 }
 
+/// `vm [--redefine file.class]... [--dump method] [class [args...]]`: run
+/// the main method of the class. (Hello by default)
+///
+/// A class file given by --redefine replaces the method bodies of a
+/// built-in class, so edited game logic runs without rebuilding the VM.
+/// --dump prints the decoded ops of the method (name and descriptor, such
+/// as `<init>()V`) of the class instead.
 fn main() -> anyhow::Result<()> {
+    let mut args = std::env::args().skip(1).peekable();
+    let mut redefined = Vec::new();
+    while args.next_if(|arg| arg == "--redefine").is_some() {
+        let path = args.next().context("--redefine needs a class file")?;
+        redefined.push(std::fs::read(&path).with_context(|| format!("cannot read {path}"))?);
+    }
+    let dump = match args.next_if(|arg| arg == "--dump") {
+        Some(_) => Some(args.next().context("--dump needs a method")?),
+        None => None,
+    };
+    let cls = args.next().unwrap_or_else(|| "Hello".to_string());
+    let args: Vec<String> = args.collect();
+
     let mut jvm = jvm::JVM::new();
     jvm::stdlib_load_core(&mut jvm);
 
@@ -55,11 +83,22 @@ fn main() -> anyhow::Result<()> {
     for bin in res::SAMPLE_CLASS_FILES {
         jvm.load_class(bin)?;
     }
+    for bin in &redefined {
+        jvm.redefine_class(bin)?;
+    }
 
-    //test_dump_method(&jvm, "MasaoConstruction", "<init>()V")?;
-    //test_dump_method(&jvm, "Hello", "main([Ljava/lang/String;)V")?;
+    jvm.register_native("Hello", "println(Ljava/lang/String;)V", |jvm, _, args| {
+        match args[0] {
+            jvm::JValue::Ref(s) => println!("{}", jvm.get_string(s).unwrap_or_default()),
+            _ => println!("null"),
+        }
+        Ok(None)
+    });
 
-    run_main(&mut jvm, "Hello")?;
+    if let Some(method) = dump {
+        return dump_method(&jvm, &cls, &method);
+    }
+    run_main(&mut jvm, &cls, &args)?;
 
     Ok(())
 }
@@ -17,4 +17,38 @@ pub const MC_CLASS_FILES: &[&[u8]] = &[
     mc_name_bin!("mc2", "MasaoConstruction"),
 ];
 
-pub const SAMPLE_CLASS_FILES: &[&[u8]] = &[mc_name_bin!("jsample", "Hello")];
+pub const SAMPLE_CLASS_FILES: &[&[u8]] = &[
+    mc_name_bin!("jsample", "Hello"),
+    mc_name_bin!("jsample", "Invoke"),
+    mc_name_bin!("jsample", "Shape"),
+    mc_name_bin!("jsample", "Base"),
+    mc_name_bin!("jsample", "Square"),
+    mc_name_bin!("jsample", "Cube"),
+    mc_name_bin!("jsample", "Tri"),
+    mc_name_bin!("jsample", "Dispatch"),
+    mc_name_bin!("jsample", "Branch"),
+    mc_name_bin!("jsample", "Exc"),
+    mc_name_bin!("jsample", "Ldc"),
+    mc_name_bin!("jsample", "FieldsIface"),
+    mc_name_bin!("jsample", "FieldsBase"),
+    mc_name_bin!("jsample", "FieldsBad"),
+    mc_name_bin!("jsample", "Fields"),
+    mc_name_bin!("jsample", "Node"),
+    mc_name_bin!("jsample", "Linked"),
+    mc_name_bin!("jsample", "Mem"),
+    mc_name_bin!("jsample", "Arrays"),
+    mc_name_bin!("jsample", "Plain"),
+    mc_name_bin!("jsample", "Header"),
+    mc_name_bin!("jsample", "Strs"),
+    mc_name_bin!("jsample", "Threads"),
+    mc_name_bin!("jsample", "Sleeper"),
+    mc_name_bin!("jsample", "Spinner"),
+    mc_name_bin!("jsample", "Sync"),
+    mc_name_bin!("jsample", "Pacing"),
+    mc_name_bin!("jsample", "Hot"),
+    mc_name_bin!("jsample", "HotSub"),
+];
+
+/// Hot with other method bodies. (for redefine_class)
+#[cfg(test)]
+pub const REDEFINED_CLASS_FILES: &[&[u8]] = &[mc_name_bin!("jsample/redefine", "Hot")];